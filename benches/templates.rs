@@ -0,0 +1,71 @@
+// Compares the rendering of task params parsed on each call, with a new cache, with the
+// rendering of the templates compiled once in the cache of a flow. Run with `cargo bench --bench templates`.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Map, Value};
+
+use flowrunner::template::TemplateCache;
+
+const ITERATIONS: u32 = 2000;
+
+fn main() {
+    // Params of a task inserting the messages of a Kafka source in Postgres
+    let params = json!({
+        "conn_str": "postgres://flowrunner@{{ context.variables.pg_host }}:5432/events",
+        "max_conn": 5,
+        "stmts": [
+            {
+                "stmt": "INSERT INTO events (id, source, payload) VALUES ($1, $2, $3)",
+                "params": [
+                    "{{ context.msg_id.uuid }}",
+                    "{{ context.msg_id.sender }}",
+                    "{{ context.msg_id.data | json_encode() | safe }}",
+                ],
+                "cond": "{% if context.msg_id.data.type == 'order' %}true{% else %}false{% endif %}",
+                "fetch": "none",
+            },
+        ],
+    });
+
+    let mut data = Map::new();
+    data.insert("context".to_string(), json!({
+        "variables": { "pg_host": "localhost" },
+        "msg_id": {
+            "uuid": "0f0c8e2a-6f5e-4d7b-9c4e-6c1d5b7e9a10",
+            "sender": "kafka1",
+            "data": { "type": "order", "id": 42, "items": ["a", "b", "c"] },
+        },
+    }));
+
+    let per_call = bench("parsed on each call", || {
+        TemplateCache::new(&BTreeMap::new(), None).render_value("task1", "params", &params, &data).unwrap()
+    });
+
+    let cache = TemplateCache::new(&BTreeMap::new(), None);
+    cache.compile(&params).unwrap();
+
+    let cached = bench("compiled once", || {
+        cache.render_value("task1", "params", &params, &data).unwrap()
+    });
+
+    println!("speedup: {:.1}x", per_call.as_secs_f64() / cached.as_secs_f64());
+}
+
+fn bench<F: FnMut() -> Value>(name: &str, mut f: F) -> Duration {
+    // Warm up
+    for _ in 0..ITERATIONS / 10 {
+        f();
+    }
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        std::hint::black_box(f());
+    }
+    let elapsed = start.elapsed() / ITERATIONS;
+
+    println!("{:<20} {:>10.2?}/render", name, elapsed);
+
+    elapsed
+}
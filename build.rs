@@ -0,0 +1,18 @@
+use std::env;
+use std::process::Command;
+
+// Exposes the compiler version to check that plugins are built with the same
+// compiler as the runner (see `plugin::PluginDeclaration`).
+fn main() {
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+
+    let version = Command::new(rustc)
+        .arg("--version")
+        .output()
+        .ok()
+        .and_then(|o| String::from_utf8(o.stdout).ok())
+        .unwrap_or_default();
+
+    println!("cargo:rustc-env=RUSTC_VERSION={}", version.trim());
+    println!("cargo:rerun-if-env-changed=RUSTC");
+}
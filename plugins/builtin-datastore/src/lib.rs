@@ -1,18 +1,21 @@
 extern crate flowrunner;
 use flowrunner::plugin::{Plugin, PluginExecResult, Status};
+use flowrunner::export_plugin;
 use flowrunner::message::Message as FlowMessage;
 use flowrunner::return_plugin_exec_result_err;
 use flowrunner::datastore::store::BoxStore;
+use flowrunner::condition;
 
 extern crate json_ops;
 use json_ops::{json_map, JsonOps};
 
 use std::fmt;
 
-//use std::collections::HashMap;
+use std::collections::HashMap;
 use serde::{Serialize, Deserialize};
 use serde_json::Value;
 use serde_json::Map;
+use serde_json::json;
 
 use anyhow::{anyhow, Result};
 
@@ -20,15 +23,14 @@ use anyhow::{anyhow, Result};
 use async_channel::{Sender, Receiver};
 use async_trait::async_trait;
 
-use evalexpr::*;
-
-use log::debug;
 
 // Our plugin implementation
 #[derive(Default, Clone)]
 struct DataStore {
     ds: Option<BoxStore>,
     ops: Vec<Op>,
+    // Params given to the plugin, against which the conditions of the operations are evaluated
+    params: Map<String, Value>,
 }
 
 impl fmt::Debug for DataStore {
@@ -77,8 +79,34 @@ impl Plugin for DataStore {
         params
     }
 
+    fn param_schema(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "ops": {
+                    "type": "array",
+                    "minItems": 1,
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "namespace": { "type": "string", "minLength": 1 },
+                            "cond": { "type": "string" },
+                            "key": { "type": "string", "minLength": 1 },
+                            "value": {},
+                            "action": { "type": "string", "enum": ["get", "set", "delete", "find"] }
+                        },
+                        "required": ["namespace", "key", "action"],
+                        "additionalProperties": false
+                    }
+                }
+            },
+            "required": ["ops"],
+            "additionalProperties": false
+        })
+    }
+
     fn validate_params(&mut self, params: Map<String, Value>) -> Result<()> {
-        let ds_params = JsonOps::new(Value::Object(params));
+        let ds_params = JsonOps::new(Value::Object(params.clone()));
 
         // Check Patch
         match ds_params.get_value_e::<Vec<Op>>("ops") {
@@ -114,6 +142,8 @@ impl Plugin for DataStore {
             },
         };
 
+        self.params = params;
+
         Ok(())
     }
 
@@ -138,9 +168,10 @@ impl Plugin for DataStore {
 
         for (idx, op) in self.ops.iter().enumerate() {
             // Check cond, if false, zap the operation
-            let cond = op.cond.clone();
-            if !eval_boolean(cond.unwrap_or_else(|| "true".to_string()).as_str()).unwrap_or(false) {
-                continue;
+            match op.cond.as_ref().map(|c| condition::eval(c.as_str(), &self.params)).transpose() {
+                Ok(Some(false)) => continue,
+                Ok(_) => {},
+                Err(e) => return_plugin_exec_result_err!(result, format!("op[{}], ns {}, key {}: {}", idx, op.namespace, op.key, e)),
             }
 
             match op.action.as_str() {
@@ -207,16 +238,80 @@ impl Plugin for DataStore {
         result.status = Status::Ok;
         result
     }
-}
 
-#[no_mangle]
-pub fn get_plugin() -> *mut (dyn Plugin + Send + Sync) {
-    debug!("Plugin DataStore loaded!");
+    /// Shows the values which would be set or deleted. Get and find operations are ignored
+    /// as they do not change anything.
+    async fn func_check(&self, _sender: Option<String>, _rx: &Vec<Sender<FlowMessage>>, _tx: &Vec<Receiver<FlowMessage>>) -> Option<PluginExecResult> {
+        let _ =  env_logger::try_init();
+
+        let mut result = PluginExecResult::default();
+
+        let store = match &self.ds {
+            Some(s) => s,
+            None => {
+                result.error = "The field ds (datastore) is None".to_string();
+                return Some(result);
+            },
+        };
+
+        // Values changed by the previous operations, empty when deleted
+        let mut values: HashMap<(String, String), String> = HashMap::new();
+        let mut diff: Vec<String> = Vec::new();
+
+        for (idx, op) in self.ops.iter().enumerate() {
+            match op.cond.as_ref().map(|c| condition::eval(c.as_str(), &self.params)).transpose() {
+                Ok(Some(false)) => continue,
+                Ok(_) => {},
+                Err(e) => {
+                    result.error = format!("op[{}], ns {}, key {}: {}", idx, op.namespace, op.key, e);
+                    return Some(result);
+                },
+            }
+
+            if op.action != "set" && op.action != "delete" {
+                continue;
+            }
+
+            let id = (op.namespace.clone(), op.key.clone());
+            let current = match values.get(&id) {
+                Some(v) => v.clone(),
+                None => match store.get(op.namespace.as_str(), op.key.as_str()) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        result.error = format!("op[{}], ns {}, key {}: {}", idx, op.namespace, op.key, e);
+                        return Some(result);
+                    },
+                },
+            };
+
+            let new = match &op.value {
+                Some(v) if op.action == "set" => serde_json::to_string(v).unwrap_or_else(|_| "".to_string()),
+                _ => "".to_string(),
+            };
+
+            if current != new {
+                if !current.is_empty() {
+                    diff.push(format!("-{}/{}: {}", op.namespace, op.key, current));
+                }
+
+                if !new.is_empty() {
+                    diff.push(format!("+{}/{}: {}", op.namespace, op.key, new));
+                }
+            }
+
+            values.insert(id, new);
+        }
+
+        result.status = Status::Ok;
+        result.output.insert("changed".to_string(), Value::Bool(!diff.is_empty()));
+        result.output.insert("diff".to_string(), Value::String(diff.join("\n")));
 
-    // Return a raw pointer to an instance of our plugin
-    Box::into_raw(Box::new(DataStore::default()))
+        Some(result)
+    }
 }
 
+export_plugin!(DataStore);
+
 #[cfg(test)]
 mod tests {
     use flowrunner::{
@@ -355,5 +450,76 @@ mod tests {
         let result = datastore.func(None, &txs, &rxs).await;
 
         assert_eq!(expected, result);
+
+        let params: Map<String, Value> = serde_json::from_str(r#"{
+            "ops": [
+                {
+                    "namespace": "ns1",
+                    "action": "set",
+                    "key": "key1",
+                    "value": "value1"
+                },
+                {
+                    "namespace": "ns1",
+                    "action": "set",
+                    "key": "key2",
+                    "value": "value2"
+                },
+                {
+                    "namespace": "ns1",
+                    "action": "delete",
+                    "key": "key2"
+                },
+                {
+                    "namespace": "ns1",
+                    "action": "delete",
+                    "key": "key3"
+                }
+            ]
+        }"#).unwrap();
+
+        datastore.validate_params(params).unwrap();
+
+        let expected = plugin_exec_result!(
+            Status::Ok,
+            "",
+            "changed" => Value::Bool(true),
+            "diff" => Value::String("-ns1/key1: \"value111\"\n+ns1/key1: \"value1\"\n+ns1/key2: \"value2\"\n-ns1/key2: \"value2\"".to_string())
+        );
+
+        assert_eq!(Some(expected), datastore.func_check(None, &txs, &rxs).await);
+        assert_eq!("\"value111\"", datastore.ds.as_ref().unwrap().get("ns1", "key1").unwrap());
+
+        // Conditions are evaluated against the params, an invalid one fails the plugin
+        let mut params: Map<String, Value> = serde_json::from_str(r#"{
+            "ops": [
+                {
+                    "namespace": "ns1",
+                    "action": "delete",
+                    "key": "key1",
+                    "cond": "len(ops) > 1"
+                }
+            ]
+        }"#).unwrap();
+
+        datastore.validate_params(params.clone()).unwrap();
+
+        let expected = plugin_exec_result!(
+            Status::Ok,
+            "",
+            "changed" => Value::Bool(false),
+            "diff" => Value::String("".to_string())
+        );
+
+        assert_eq!(Some(expected), datastore.func_check(None, &txs, &rxs).await);
+
+        params["ops"][0]["cond"] = json!("len(ops) >");
+        datastore.validate_params(params).unwrap();
+
+        let result = datastore.func(None, &txs, &rxs).await;
+
+        assert_eq!(Status::Ko, result.status);
+        assert!(result.error.starts_with("op[0], ns ns1, key key1: condition"), "{}", result.error);
+        assert_eq!("\"value111\"", datastore.ds.as_ref().unwrap().get("ns1", "key1").unwrap());
     }
 }
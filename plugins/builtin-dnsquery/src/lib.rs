@@ -1,5 +1,6 @@
 extern crate flowrunner;
 use flowrunner::plugin::{Plugin, PluginExecResult, Status};
+use flowrunner::export_plugin;
 use flowrunner::message::Message as FlowMessage;
 use flowrunner::return_plugin_exec_result_err;
 use flowrunner::datastore::store::BoxStore;
@@ -10,6 +11,7 @@ use json_ops::JsonOps;
 //use std::collections::HashMap;
 use serde::{Serialize, Deserialize};
 use serde_json::{Value, Map};
+use serde_json::json;
 
 use anyhow::{anyhow, Result};
 
@@ -78,6 +80,32 @@ impl Plugin for DnsQuery {
         params
     }
 
+    fn param_schema(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "nameserver": { "type": "string", "description": "Name server with the format ip:port" },
+                "queries": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "name": { "type": "string" },
+                            "rtype": { "type": "string" },
+                            "rdata": { "type": "object" },
+                            "result": { "type": "string" },
+                            "status": { "type": "boolean" }
+                        },
+                        "required": ["name", "rtype"],
+                        "additionalProperties": false
+                    }
+                }
+            },
+            "required": ["nameserver", "queries"],
+            "additionalProperties": false
+        })
+    }
+
     fn validate_params(&mut self, params: Map<String, Value>) -> Result<()> {
         let jops_params = JsonOps::new(Value::Object(params));
         let mut default = DnsQuery::default();
@@ -281,10 +309,4 @@ fn new_rdata(rtype: RecordType, rdata: Map<String, Value>) -> Result<RData> {
     }
 }
 
-#[no_mangle]
-pub fn get_plugin() -> *mut (dyn Plugin + Send + Sync) {
-    debug!("Plugin DnsQuery loaded!");
-
-    // Return a raw pointer to an instance of our plugin
-    Box::into_raw(Box::new(DnsQuery::default()))
-}
+export_plugin!(DnsQuery);
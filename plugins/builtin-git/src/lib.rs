@@ -1,8 +1,10 @@
 extern crate flowrunner;
 use flowrunner::plugin::{Plugin, PluginExecResult, Status};
+use flowrunner::export_plugin;
 use flowrunner::message::Message as FlowMessage;
 use flowrunner::return_plugin_exec_result_err;
 use flowrunner::datastore::store::BoxStore;
+use flowrunner::condition;
 //use flowrunner::utils::*;
 
 extern crate json_ops;
@@ -11,6 +13,7 @@ use json_ops::JsonOps;
 //use std::collections::HashMap;
 use serde::{Serialize, Deserialize};
 use serde_json::{Value, Map};
+use serde_json::json;
 
 use anyhow::{anyhow, Result};
 
@@ -22,8 +25,6 @@ use async_trait::async_trait;
 use log::*;
 //use tracing::*;
 
-use evalexpr::*;
-
 use std::path::Path;
 use git2::{
     Repository,
@@ -56,7 +57,10 @@ struct GitRepo {
     #[serde(default)]
     update: bool,
     auth: Option<Auth>,
-    actions: Vec<Action>
+    actions: Vec<Action>,
+    // Params given to the plugin, against which the conditions of the actions are evaluated
+    #[serde(skip)]
+    params: Map<String, Value>,
 }
 
 fn default_remote() -> String {
@@ -112,8 +116,49 @@ impl Plugin for GitRepo {
         params
     }
 
+    fn param_schema(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "remote_url": { "type": "string" },
+                "local_dir": { "type": "string" },
+                "remote": { "type": "string", "default": "origin" },
+                "branch": { "type": "string", "default": "master" },
+                "username": { "type": "string", "default": "flowrunner" },
+                "usermail": { "type": "string", "default": "flowrunner@bazarlab.io" },
+                "recursive": { "type": "boolean", "default": false },
+                "update": { "type": "boolean", "default": false },
+                "auth": {
+                    "type": "object",
+                    "properties": {
+                        "mode": { "type": "string", "enum": ["ssh", "userpass"] },
+                        "config": { "type": "object", "minProperties": 1 }
+                    },
+                    "required": ["mode", "config"],
+                    "additionalProperties": false
+                },
+                "actions": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "name": { "type": "string", "enum": ["add", "remove", "commit", "pull", "push", "fetch"] },
+                            "cond": { "type": "string" },
+                            "files": { "type": "array", "items": { "type": "string" } },
+                            "commit_msg": { "type": "string" }
+                        },
+                        "required": ["name"],
+                        "additionalProperties": false
+                    }
+                }
+            },
+            "required": ["local_dir"],
+            "additionalProperties": false
+        })
+    }
+
     fn validate_params(&mut self, params: Map<String, Value>) -> Result<()> {
-        let jops_params = JsonOps::new(Value::Object(params));
+        let jops_params = JsonOps::new(Value::Object(params.clone()));
         let mut default = GitRepo::default();
 
         match jops_params.get_value_e::<String>("remote_url") {
@@ -207,6 +252,7 @@ impl Plugin for GitRepo {
             Err(_) => {},
         };
 
+        default.params = params;
         *self = default;
 
         Ok(())
@@ -275,9 +321,10 @@ impl Plugin for GitRepo {
 
 
         for action in &self.actions {
-            let cond = action.cond.as_ref()
-                .and_then(|v| eval_boolean(v.as_str()).ok())
-                .unwrap_or(true);
+            let cond = match action.cond.as_ref().map(|v| condition::eval(v.as_str(), &self.params)).transpose() {
+                Ok(v) => v.unwrap_or(true),
+                Err(e) => return_plugin_exec_result_err!(result, format!("action {}: {}", action.name, e)),
+            };
 
             if cond {
                 match action.name.as_str() {
@@ -326,6 +373,144 @@ impl Plugin for GitRepo {
         result.status = Status::Ok;
         result
     }
+
+    /// Shows the pending changes of the actions without applying them: files to add or
+    /// remove, commits and commits to push. Remote operations are listed without contacting
+    /// the remote.
+    async fn func_check(&self, _sender: Option<String>, _tx: &Vec<Sender<FlowMessage>>, _rx: &Vec<Receiver<FlowMessage>>) -> Option<PluginExecResult> {
+        let _ =  env_logger::try_init();
+
+        let mut result = PluginExecResult::default();
+
+        let pending = match repo_pending_changes(self) {
+            Ok(p) => p,
+            Err(e) => {
+                result.error = e.to_string();
+                return Some(result);
+            },
+        };
+
+        result.status = Status::Ok;
+        result.output.insert("changed".to_string(), Value::Bool(!pending.is_empty()));
+        result.output.insert("diff".to_string(), Value::String(pending.join("\n")));
+
+        Some(result)
+    }
+}
+
+// Returns one line per change that the actions would do in the repository
+fn repo_pending_changes(config: &GitRepo) -> Result<Vec<String>> {
+    let mut pending: Vec<String> = Vec::new();
+    let local_dir_path = Path::new(config.local_dir.as_str());
+
+    if !config.remote_url.is_empty() && !local_dir_path.exists() {
+        pending.push(format!("clone {} ({}) into {}", config.remote_url, config.branch, config.local_dir));
+        pending.extend(config.actions.iter().map(|a| a.name.clone()));
+
+        return Ok(pending);
+    }
+
+    let repo = Repository::open(local_dir_path)?;
+
+    if config.update {
+        pending.push(format!("pull {}/{}", config.remote, config.branch));
+    }
+
+    let mut staged = repo_changed_files(&repo, &[], git2::Status::INDEX_NEW
+        | git2::Status::INDEX_MODIFIED
+        | git2::Status::INDEX_DELETED
+        | git2::Status::INDEX_RENAMED
+        | git2::Status::INDEX_TYPECHANGE)?;
+
+    // Commits to push, None when the remote branch is not known locally
+    let mut ahead = if config.actions.iter().any(|a| a.name == "push") {
+        repo_ahead(&repo, config)?
+    } else {
+        Some(0)
+    };
+
+    for action in &config.actions {
+        let cond = action.cond.as_ref()
+            .map(|v| condition::eval(v.as_str(), &config.params))
+            .transpose()
+            .map_err(|e| anyhow!("action {}: {}", action.name, e))?
+            .unwrap_or(true);
+
+        if !cond {
+            continue;
+        }
+
+        match action.name.as_str() {
+            "fetch" | "pull" => pending.push(format!("{} {}/{}", action.name, config.remote, config.branch)),
+            "add" => {
+                for f in repo_changed_files(&repo, &action.files, git2::Status::WT_MODIFIED
+                    | git2::Status::WT_NEW
+                    | git2::Status::WT_DELETED
+                    | git2::Status::WT_TYPECHANGE
+                    | git2::Status::WT_RENAMED)? {
+                    if !staged.contains(&f) {
+                        pending.push(format!("add {}", f));
+                        staged.push(f);
+                    }
+                }
+            },
+            "remove" => {
+                for f in action.files.iter() {
+                    if local_dir_path.join(f).exists() {
+                        pending.push(format!("remove {}", f));
+                        staged.push(f.clone());
+                    }
+                }
+            },
+            "commit" => {
+                if !staged.is_empty() {
+                    pending.push(format!("commit {:?}: {} file(s)", action.commit_msg, staged.len()));
+                    staged.clear();
+                    ahead = ahead.map(|a| a + 1);
+                }
+            },
+            "push" => {
+                match ahead {
+                    Some(0) => (),
+                    Some(n) => pending.push(format!("push {} commit(s) to {}/{}", n, config.remote, config.branch)),
+                    None => pending.push(format!("push to {}/{}", config.remote, config.branch)),
+                }
+
+                ahead = Some(0);
+            },
+            _ => return Err(anyhow!("action name {} is not supported", action.name)),
+        }
+    }
+
+    Ok(pending)
+}
+
+// Returns the files matching the pathspecs, all files if empty, with one of the statuses
+fn repo_changed_files(repo: &Repository, pathspecs: &[String], statuses: git2::Status) -> Result<Vec<String>> {
+    let mut opts = git2::StatusOptions::new();
+    opts.include_untracked(true).recurse_untracked_dirs(true);
+
+    for p in pathspecs.iter() {
+        opts.pathspec(p);
+    }
+
+    let files = repo.statuses(Some(&mut opts))?
+        .iter()
+        .filter(|s| s.status().intersects(statuses))
+        .filter_map(|s| s.path().map(|p| p.to_string()))
+        .collect();
+
+    Ok(files)
+}
+
+// Returns the number of local commits which are not in the remote branch
+fn repo_ahead(repo: &Repository, config: &GitRepo) -> Result<Option<usize>> {
+    let local = repo_get_last_commit(repo)?.id();
+
+    match repo.refname_to_id(&format!("refs/remotes/{}/{}", config.remote, config.branch)) {
+        Ok(upstream) => Ok(Some(repo.graph_ahead_behind(local, upstream)?.0)),
+        Err(_) => Ok(None),
+    }
 }
 
 fn configure_remote_callbacks<'a>(username: &'a str, auth_config: &'a Option<Auth>) -> RemoteCallbacks<'a> {
@@ -612,10 +797,4 @@ fn repo_pull(repo: &Repository, config: &GitRepo) -> Result<()> {
     Ok(())
 }
 
-#[no_mangle]
-pub fn get_plugin() -> *mut (dyn Plugin + Send + Sync) {
-    debug!("Plugin GitRepo loaded!");
-
-    // Return a raw pointer to an instance of our plugin
-    Box::into_raw(Box::new(GitRepo::default()))
-}
+export_plugin!(GitRepo);
@@ -1,5 +1,6 @@
 extern crate flowrunner;
 use flowrunner::plugin::{Plugin, PluginExecResult, Status};
+use flowrunner::export_plugin;
 use flowrunner::message::Message as FlowMessage;
 use flowrunner::return_plugin_exec_result_err;
 use flowrunner::datastore::store::BoxStore;
@@ -77,6 +78,39 @@ impl Plugin for HttpServer {
         params
     }
 
+    fn param_schema(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "host_addr": { "type": "string", "description": "Listening address with the format ip:port" },
+                "routes": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "path": { "type": "string" },
+                            "method": { "type": "string" },
+                            "result": {
+                                "type": "object",
+                                "properties": {
+                                    "job": { "type": "string" },
+                                    "task": { "type": "string" },
+                                    "payload": { "type": "string" }
+                                },
+                                "required": ["job", "task"],
+                                "additionalProperties": false
+                            }
+                        },
+                        "required": ["path", "method", "result"],
+                        "additionalProperties": false
+                    }
+                }
+            },
+            "required": ["host_addr", "routes"],
+            "additionalProperties": false
+        })
+    }
+
     fn validate_params(&mut self, params: Map<String, Value>) -> Result<()> {
         let jops_params = JsonOps::new(Value::Object(params));
         let mut default = HttpServer::default();
@@ -388,13 +422,7 @@ fn build_response(result: &HttpResult, v: &Value) -> (StatusCode, Value) {
     }
 }
 
-#[no_mangle]
-pub fn get_plugin() -> *mut (dyn Plugin + Send + Sync) {
-    debug!("Plugin HttpServer loaded!");
-
-    // Return a raw pointer to an instance of our plugin
-    Box::into_raw(Box::new(HttpServer::default()))
-}
+export_plugin!(HttpServer);
 
 #[cfg(test)]
 mod tests {
@@ -2,7 +2,9 @@ extern crate flowrunner;
 use flowrunner::datastore::store::BoxStore;
 use flowrunner::message::Message as FlowMessage;
 use flowrunner::plugin::{Plugin, PluginExecResult, Status};
+use flowrunner::export_plugin;
 use flowrunner::return_plugin_exec_result_err;
+use flowrunner::condition;
 
 extern crate json_ops;
 use json_ops::JsonOps;
@@ -11,6 +13,7 @@ use json_ops::JsonOps;
 use serde::{Deserialize, Serialize};
 use serde_json::value::Value;
 use serde_json::Map;
+use serde_json::json;
 
 use anyhow::{anyhow, Result};
 
@@ -18,15 +21,15 @@ use anyhow::{anyhow, Result};
 use async_channel::{Receiver, Sender};
 use async_trait::async_trait;
 
-use log::debug;
-
-use evalexpr::*;
 
 // Our plugin implementation
 #[derive(Default, Debug, Serialize, Deserialize, Clone)]
 struct JsonPatch {
     target: String,
     patch: Vec<Op>,
+    // Params given to the plugin, against which the conditions of the operations are evaluated
+    #[serde(skip)]
+    params: Map<String, Value>,
 }
 
 #[derive(Default, Debug, Serialize, Deserialize, Clone)]
@@ -58,8 +61,34 @@ impl Plugin for JsonPatch {
         params
     }
 
+    fn param_schema(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "target": { "type": "string" },
+                "patch": {
+                    "type": "array",
+                    "minItems": 1,
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "path": { "type": "string" },
+                            "cond": { "type": "string" },
+                            "action": { "type": "string", "enum": ["add", "replace", "remove"] },
+                            "value": {}
+                        },
+                        "required": ["path", "action"],
+                        "additionalProperties": false
+                    }
+                }
+            },
+            "required": ["target", "patch"],
+            "additionalProperties": false
+        })
+    }
+
     fn validate_params(&mut self, params: Map<String, Value>) -> Result<()> {
-        let jops_params = JsonOps::new(Value::Object(params));
+        let jops_params = JsonOps::new(Value::Object(params.clone()));
 
         // Check Target
         match jops_params.get_value_e::<String>("target") {
@@ -116,6 +145,8 @@ impl Plugin for JsonPatch {
             }
         };
 
+        self.params = params;
+
         Ok(())
     }
 
@@ -141,11 +172,15 @@ impl Plugin for JsonPatch {
         let mut json_ops = JsonOps::new(v_target);
 
         for (idx, op) in self.patch.iter().enumerate() {
-            let cond = op
+            let cond = match op
                 .cond
                 .as_ref()
-                .and_then(|v| eval_boolean(v.as_str()).ok())
-                .unwrap_or(true);
+                .map(|v| condition::eval(v.as_str(), &self.params))
+                .transpose()
+            {
+                Ok(v) => v.unwrap_or(true),
+                Err(e) => return_plugin_exec_result_err!(result, format!("op[{}], path {}: {}", idx, op.path, e)),
+            };
 
             if cond {
                 // Convert string correctly to value
@@ -214,13 +249,7 @@ impl Plugin for JsonPatch {
     }
 }
 
-#[no_mangle]
-pub fn get_plugin() -> *mut (dyn Plugin + Send + Sync) {
-    debug!("Plugin JsonPatch loaded!");
-
-    // Return a raw pointer to an instance of our plugin
-    Box::into_raw(Box::new(JsonPatch::default()))
-}
+export_plugin!(JsonPatch);
 
 #[cfg(test)]
 mod tests {
@@ -353,4 +382,51 @@ mod tests {
 
         assert_eq!(expected, result);
     }
+
+    #[tokio::test]
+    async fn test_cond() {
+        let txs = Vec::<Sender<FlowMessage>>::new();
+        let rxs = Vec::<Receiver<FlowMessage>>::new();
+
+        let mut jsonpatch = JsonPatch::default();
+
+        let mut params: Map<String, Value> = serde_json::from_str(
+            r#"{
+            "target": "{\"env\": \"dev\", \"replicas\": 1}",
+            "patch": [
+                {
+                    "path": "replicas",
+                    "action": "replace",
+                    "value": "3",
+                    "cond": "len(patch) == 2"
+                },
+                {
+                    "path": "env",
+                    "action": "remove",
+                    "cond": "patch[0].value == '1'"
+                }
+            ]
+        }"#,
+        )
+        .unwrap();
+
+        jsonpatch.validate_params(params.clone()).unwrap();
+
+        let expected = plugin_exec_result!(
+            Status::Ok,
+            "",
+            "result" => json!({ "env": "dev", "replicas": 3 })
+        );
+
+        assert_eq!(expected, jsonpatch.func(None, &txs, &rxs).await);
+
+        // An invalid condition fails the plugin instead of applying the operation
+        params["patch"][1]["cond"] = json!("patch[0].value ==");
+        jsonpatch.validate_params(params).unwrap();
+
+        let result = jsonpatch.func(None, &txs, &rxs).await;
+
+        assert_eq!(Status::Ko, result.status);
+        assert!(result.error.starts_with("op[1], path env: condition"), "{}", result.error);
+    }
 }
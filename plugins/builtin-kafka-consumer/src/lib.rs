@@ -1,5 +1,6 @@
 extern crate flowrunner;
 use flowrunner::plugin::{Plugin, PluginExecResult, Status};
+use flowrunner::export_plugin;
 use flowrunner::message::Message as FlowMessage;
 use flowrunner::datastore::store::BoxStore;
 use flowrunner::utils::*;
@@ -11,6 +12,7 @@ use serde::{Deserialize, Serialize};
 //use std::collections::HashMap;
 use serde_json::value::Value;
 use serde_json::Map;
+use serde_json::json;
 
 use anyhow::{anyhow, Result};
 
@@ -95,6 +97,29 @@ impl Plugin for KafkaConsumer {
         params
     }
 
+    fn param_schema(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "brokers": { "type": "array", "items": { "type": "string" } },
+                "consumer": {
+                    "type": "object",
+                    "properties": {
+                        "group_id": { "type": "string", "default": "consumer-group-1" },
+                        "offset": { "type": "string", "default": "earliest" },
+                        "topics": { "type": "array", "items": { "type": "string" } },
+                        "options": { "type": "object" },
+                        "log_level": { "type": "string" }
+                    },
+                    "required": ["topics", "options"],
+                    "additionalProperties": false
+                }
+            },
+            "required": ["brokers", "consumer"],
+            "additionalProperties": false
+        })
+    }
+
     fn validate_params(&mut self, params: Map<String, Value>) -> Result<()> {
         let jops_params = JsonOps::new(Value::Object(params));
         let mut default = KafkaConsumer::default();
@@ -228,13 +253,7 @@ async fn run(sender: Option<String>, brokers: Vec<String>, config: Config, rx: &
     }
 }
 
-#[no_mangle]
-pub fn get_plugin() -> *mut (dyn Plugin + Send + Sync) {
-    info!("Plugin KafkaConsumer loaded!");
-
-    // Return a raw pointer to an instance of our plugin
-    Box::into_raw(Box::new(KafkaConsumer::default()))
-}
+export_plugin!(KafkaConsumer);
 
 #[cfg(test)]
 mod tests {
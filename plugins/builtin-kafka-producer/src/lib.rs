@@ -1,8 +1,10 @@
 extern crate flowrunner;
 use flowrunner::plugin::{Plugin, PluginExecResult, Status};
+use flowrunner::export_plugin;
 use flowrunner::message::Message as FlowMessage;
 use flowrunner::return_plugin_exec_result_err;
 use flowrunner::datastore::store::BoxStore;
+use flowrunner::condition;
 
 extern crate json_ops;
 use json_ops::JsonOps;
@@ -11,6 +13,7 @@ use serde::{Deserialize, Serialize};
 //use std::collections::HashMap;
 use serde_json::value::Value;
 use serde_json::Map;
+use serde_json::json;
 
 use std::time::Duration;
 use anyhow::{anyhow, Result};
@@ -20,7 +23,6 @@ use async_channel::{Sender, Receiver};
 use tokio::runtime::Runtime;
 use async_trait::async_trait;
 
-use evalexpr::*;
 use log::*;
 
 use rdkafka::{
@@ -36,6 +38,9 @@ struct KafkaProducer {
     messages: Vec<KafkaMessage>,
     #[serde(default = "default_loglevel")]
     log_level: String,
+    // Params given to the plugin, against which the conditions of the messages are evaluated
+    #[serde(skip)]
+    params: Map<String, Value>,
 }
 
 fn default_loglevel() -> String {
@@ -70,8 +75,35 @@ impl Plugin for KafkaProducer {
         params
     }
 
+    fn param_schema(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "brokers": { "type": "array", "items": { "type": "string" } },
+                "options": { "type": "object" },
+                "messages": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "topic": { "type": "string", "minLength": 1 },
+                            "if": { "type": "string" },
+                            "key": { "type": "string" },
+                            "message": { "type": "string", "minLength": 1 }
+                        },
+                        "required": ["topic", "key", "message"],
+                        "additionalProperties": false
+                    }
+                },
+                "log_level": { "type": "string", "default": "info" }
+            },
+            "required": ["brokers", "messages"],
+            "additionalProperties": false
+        })
+    }
+
     fn validate_params(&mut self, params: Map<String, Value>) -> Result<()> {
-        let jops_params = JsonOps::new(Value::Object(params));
+        let jops_params = JsonOps::new(Value::Object(params.clone()));
 
         match jops_params.get_value_e::<Vec<String>>("brokers") {
             Ok(b) => self.brokers = b,
@@ -101,6 +133,8 @@ impl Plugin for KafkaProducer {
             Err(_) => self.log_level = "info".to_string(),
         };
 
+        self.params = params;
+
         Ok(())
     }
 
@@ -143,10 +177,13 @@ impl Plugin for KafkaProducer {
             };
 
 
-        for msg in self.messages.clone().iter() {
-            let r#if = msg.r#if.clone().unwrap_or("true".to_string());
+        for (idx, msg) in self.messages.clone().iter().enumerate() {
+            let r#if = match msg.r#if.as_ref().map(|c| condition::eval(c.as_str(), &self.params)).transpose() {
+                Ok(v) => v.unwrap_or(true),
+                Err(e) => { return_plugin_exec_result_err!(result, format!("messages[{}], topic {}: {}", idx, msg.topic, e)); },
+            };
 
-            if eval_boolean(r#if.as_str()).unwrap_or(false) {
+            if r#if {
                 info!("Sending message {:?}", msg);
 
                 let mut fr = FutureRecord::to(msg.topic.as_str())
@@ -179,10 +216,4 @@ impl Plugin for KafkaProducer {
     }
 }
 
-#[no_mangle]
-pub fn get_plugin() -> *mut (dyn Plugin + Send + Sync) {
-    info!("Plugin KafkaProducer loaded!");
-
-    // Return a raw pointer to an instance of our plugin
-    Box::into_raw(Box::new(KafkaProducer::default()))
-}
+export_plugin!(KafkaProducer);
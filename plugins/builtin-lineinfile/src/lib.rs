@@ -1,8 +1,10 @@
 extern crate flowrunner;
 use flowrunner::plugin::{Plugin, PluginExecResult, Status};
+use flowrunner::export_plugin;
 use flowrunner::message::Message as FlowMessage;
 use flowrunner::return_plugin_exec_result_err;
 use flowrunner::datastore::store::BoxStore;
+use flowrunner::utils::diff_lines;
 
 extern crate json_ops;
 use json_ops::JsonOps;
@@ -10,6 +12,7 @@ use json_ops::JsonOps;
 //use std::collections::HashMap;
 use serde::{Serialize, Deserialize};
 use serde_json::{Value, Map};
+use serde_json::json;
 
 use anyhow::{anyhow, Result};
 
@@ -68,6 +71,31 @@ impl Plugin for LineInFile {
         params
     }
 
+    fn param_schema(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "path": { "type": "string", "minLength": 1 },
+                "actions": {
+                    "type": "array",
+                    "minItems": 1,
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "regexp": { "type": "string", "minLength": 1 },
+                            "line": { "type": "string" },
+                            "state": { "type": "string", "enum": ["present", "absent"], "default": "present" }
+                        },
+                        "required": ["regexp"],
+                        "additionalProperties": false
+                    }
+                }
+            },
+            "required": ["path", "actions"],
+            "additionalProperties": false
+        })
+    }
+
     fn validate_params(&mut self, params: Map<String, Value>) -> Result<()> {
         let jops_params = JsonOps::new(Value::Object(params));
         let mut default = LineInFile::default();
@@ -127,9 +155,58 @@ impl Plugin for LineInFile {
 
         let mut result = PluginExecResult::default();
 
+        let (_, content) = match self.apply_actions() {
+            Ok(v) => v,
+            Err(e) => return_plugin_exec_result_err!(result, e.to_string()),
+        };
+
+        debug!("Writing new content after actions executed: content={:#?}", content);
+
+        let mut f_opts = fs::OpenOptions::new();
+        f_opts.write(true).truncate(true);
+        if let Err(e) = f_opts.open(self.path.as_str())
+            .and_then(|mut v| {
+                v.write_all(content.as_bytes())
+                //v.write_all(b"\n")
+            }) {
+                return_plugin_exec_result_err!(result, e.to_string());
+            }
+
+        result.status = Status::Ok;
+        result.output.insert("content".to_string(), Value::String(content));
+        result
+    }
+
+    /// Shows the lines which would be removed or added in the file
+    async fn func_check(&self, _sender: Option<String>, _tx: &Vec<Sender<FlowMessage>>, _rx: &Vec<Receiver<FlowMessage>>) -> Option<PluginExecResult> {
+        let _ =  env_logger::try_init();
+
+        let mut result = PluginExecResult::default();
+
+        let (current, content) = match self.apply_actions() {
+            Ok(v) => v,
+            Err(e) => {
+                result.error = e.to_string();
+                return Some(result);
+            },
+        };
+
+        result.status = Status::Ok;
+        result.output.insert("changed".to_string(), Value::Bool(current != content));
+        result.output.insert("diff".to_string(), Value::String(diff_lines(&current, &content)));
+        result.output.insert("content".to_string(), Value::String(content));
+
+        Some(result)
+    }
+}
+
+impl LineInFile {
+    // Reads the file and applies the actions to its lines. Returns the current content
+    // and the new content of the file.
+    fn apply_actions(&self) -> Result<(String, String)> {
         let path = Path::new(self.path.as_str());
         if !path.exists() {
-            return_plugin_exec_result_err!(result, format!("{} does not exist", self.path));
+            return Err(anyhow!("{} does not exist", self.path));
         }
 
         // Set options according to override
@@ -158,10 +235,7 @@ impl Plugin for LineInFile {
         for a in self.actions.iter() {
             info!("Doing action: file={}, action={:?}", self.path, a);
 
-            let re = match Regex::new(a.regexp.as_str()) {
-                Ok(v) => v,
-                Err(e) => return_plugin_exec_result_err!(result, e.to_string()),
-            };
+            let re = Regex::new(a.regexp.as_str())?;
 
             let indexes: Vec<usize> = lines.iter()
                 .enumerate()
@@ -199,30 +273,13 @@ impl Plugin for LineInFile {
             }
         }
 
-        debug!("Writing new content after actions executed: content={:#?}", lines);
+        let new_content = lines.join("\n");
 
-        f_opts.write(true).truncate(true);
-        if let Err(e) = f_opts.open(self.path.as_str())
-            .and_then(|mut v| {
-                v.write_all(lines.join("\n").as_bytes())
-                //v.write_all(b"\n")
-            }) {
-                return_plugin_exec_result_err!(result, e.to_string());
-            }
-
-        result.status = Status::Ok;
-        result.output.insert("content".to_string(), Value::String(lines.join("\n")));
-        result
+        Ok((content, new_content))
     }
 }
 
-#[no_mangle]
-pub fn get_plugin() -> *mut (dyn Plugin + Send + Sync) {
-    debug!("Plugin LineInFile loaded!");
-
-    // Return a raw pointer to an instance of our plugin
-    Box::into_raw(Box::new(LineInFile::default()))
-}
+export_plugin!(LineInFile);
 
 #[cfg(test)]
 mod tests {
@@ -308,4 +365,32 @@ line_has_been_added"#.to_string()));
 
         assert_eq!(expected, result);
     }
+
+    #[tokio::test]
+    async fn lineinfile_func_check() {
+        let txs = Vec::<Sender<FlowMessage>>::new();
+        let rxs = Vec::<Receiver<FlowMessage>>::new();
+
+        let path = std::env::temp_dir().join("lineinfile_func_check.txt");
+        fs::write(&path, "key1=value1\nkey2=value2").unwrap();
+
+        let mut lif = LineInFile::default();
+        lif.validate_params(serde_json::from_value(json!({
+            "path": path.display().to_string(),
+            "actions": [
+                { "regexp": "^key2=", "line": "key2=value3" },
+                { "regexp": "^key1=", "state": "absent" }
+            ]
+        })).unwrap()).unwrap();
+
+        let expected = plugin_exec_result!(
+            Status::Ok,
+            "",
+            "changed" => Value::Bool(true),
+            "diff" => Value::String("-key1=value1\n-key2=value2\n+key2=value3".to_string()),
+            "content" => Value::String("key2=value3".to_string()));
+
+        assert_eq!(Some(expected), lif.func_check(None, &txs, &rxs).await);
+        assert_eq!("key1=value1\nkey2=value2", fs::read_to_string(&path).unwrap());
+    }
 }
@@ -1,8 +1,10 @@
-#[macro_use]
 extern crate flowrunner;
 use flowrunner::plugin::{Plugin, PluginExecResult, Status};
+use flowrunner::export_plugin;
+use flowrunner::return_plugin_exec_result_err;
 use flowrunner::message::Message as FlowMessage;
 use flowrunner::datastore::store::BoxStore;
+use flowrunner::condition;
 
 extern crate json_ops;
 use json_ops::JsonOps;
@@ -11,6 +13,7 @@ use serde::{Deserialize, Serialize};
 //use std::collections::HashMap;
 use serde_json::Value;
 use serde_json::{Map, Number};
+use serde_json::json;
 
 use anyhow::{anyhow, Result};
 
@@ -27,7 +30,6 @@ use chrono::{DateTime, Utc, NaiveDate, NaiveDateTime, NaiveTime};
 #[allow(unused_imports)]
 use std::str::FromStr;
 
-use evalexpr::*;
 use regex::Regex;
 
 use log::*;
@@ -39,6 +41,9 @@ struct Pgql {
     max_conn: u32,
     stmts: Vec<Stmt>,
     deallocate_pp_stmt: bool,
+    // Params given to the plugin, against which the conditions of the statements are evaluated
+    #[serde(skip)]
+    params: Map<String, Value>,
 }
 
 #[derive(Debug, Default, Deserialize, Serialize, Clone)]
@@ -400,10 +405,48 @@ impl Plugin for Pgql {
         params
     }
 
+    fn param_schema(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "conn_str": { "type": "string" },
+                "max_conn": { "type": "integer", "minimum": 0, "default": 3 },
+                "stmts": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "stmt": { "type": "string" },
+                            "cond": { "type": "string" },
+                            "params": {
+                                "type": "array",
+                                "items": {
+                                    "type": "object",
+                                    "properties": {
+                                        "value": {},
+                                        "pg_type": { "type": "string" }
+                                    },
+                                    "required": ["value", "pg_type"],
+                                    "additionalProperties": false
+                                }
+                            },
+                            "fetch": { "type": "string" }
+                        },
+                        "required": ["stmt", "params"],
+                        "additionalProperties": false
+                    }
+                },
+                "deallocate_pp_stmt": { "type": "boolean", "default": false }
+            },
+            "required": ["conn_str", "stmts"],
+            "additionalProperties": false
+        })
+    }
+
     fn set_datastore(&mut self, _datastore: Option<BoxStore>) {}
 
     fn validate_params(&mut self, params: Map<String, Value>) -> Result<()> {
-        let jops_params = JsonOps::new(Value::Object(params));
+        let jops_params = JsonOps::new(Value::Object(params.clone()));
 
         // Check URL
         match jops_params.get_value_e::<String>("conn_str") {
@@ -432,6 +475,8 @@ impl Plugin for Pgql {
             Err(_) => self.deallocate_pp_stmt = false,
         };
 
+        self.params = params;
+
         Ok(())
     }
 
@@ -474,10 +519,19 @@ impl Plugin for Pgql {
             };
 
             for (idx, st) in self.stmts.iter().enumerate() {
-                let mut cond = st.cond.clone().unwrap_or("true".to_string());
-                cond = if cond.is_empty() { "true".to_string() } else { cond };
-
-                if eval_boolean(cond.as_str()).unwrap_or(false) {
+                // No or empty condition: the statement is executed
+                let cond = match st.cond.as_ref()
+                    .filter(|c| !c.is_empty())
+                    .map(|c| condition::eval(c.as_str(), &self.params))
+                    .transpose() {
+                    Ok(v) => v.unwrap_or(true),
+                    Err(e) => {
+                        let _ = transaction.rollback().await;
+                        return_plugin_exec_result_err!(result, format!("stmts[{}]: {}", idx, e));
+                    },
+                };
+
+                if cond {
                     let stmt = st.stmt.as_str();
                     // Parse query
                     let qry_type = sql_parser(stmt);
@@ -615,13 +669,7 @@ fn sql_parser(stmt: &str) -> String {
     }
 }
 
-#[no_mangle]
-pub fn get_plugin() -> *mut (dyn Plugin + Send + Sync) {
-    debug!("Plugin Pgql loaded!");
-
-    // Return a raw pointer to an instance of our plugin
-    Box::into_raw(Box::new(Pgql::default()))
-}
+export_plugin!(Pgql);
 
 #[cfg(test)]
 mod tests {
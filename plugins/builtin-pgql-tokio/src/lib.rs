@@ -1,8 +1,10 @@
-#[macro_use]
 extern crate flowrunner;
 use flowrunner::datastore::store::BoxStore;
 use flowrunner::message::Message as FlowMessage;
 use flowrunner::plugin::{Plugin, PluginExecResult, Status};
+use flowrunner::export_plugin;
+use flowrunner::return_plugin_exec_result_err;
+use flowrunner::condition;
 
 extern crate json_ops;
 use json_ops::JsonOps;
@@ -11,6 +13,7 @@ use serde::{Deserialize, Serialize};
 //use std::collections::HashMap;
 use serde_json::Value;
 use serde_json::{Map, Number};
+use serde_json::json;
 
 use anyhow::{anyhow, Result};
 
@@ -32,7 +35,6 @@ use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
 #[allow(unused_imports)]
 use std::str::FromStr;
 
-use evalexpr::*;
 use regex::Regex;
 
 use log::*;
@@ -44,6 +46,9 @@ struct TokioPgql {
     stmts: Vec<Stmt>,
     pp_stmt_enabled: bool,
     tls: Option<TlsConfig>,
+    // Params given to the plugin, against which the conditions of the statements are evaluated
+    #[serde(skip)]
+    params: Map<String, Value>,
 }
 
 #[derive(Debug, Default, Deserialize, Serialize, Clone)]
@@ -416,10 +421,58 @@ impl Plugin for TokioPgql {
         params
     }
 
+    fn param_schema(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "conn_str": { "type": "string" },
+                "stmts": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "stmt": { "type": "string" },
+                            "cond": { "type": "string" },
+                            "params": {
+                                "type": "array",
+                                "items": {
+                                    "type": "object",
+                                    "properties": {
+                                        "value": {},
+                                        "pg_type": { "type": "string" }
+                                    },
+                                    "required": ["value", "pg_type"],
+                                    "additionalProperties": false
+                                }
+                            },
+                            "fetch": { "type": "string" }
+                        },
+                        "required": ["stmt", "params"],
+                        "additionalProperties": false
+                    }
+                },
+                "pp_stmt_enabled": { "type": "boolean", "default": true },
+                "tls": {
+                    "type": "object",
+                    "properties": {
+                        "verify": { "type": "boolean" },
+                        "client_key": { "type": "string" },
+                        "client_cert": { "type": "string" },
+                        "ca_cert": { "type": "string" }
+                    },
+                    "required": ["verify"],
+                    "additionalProperties": false
+                }
+            },
+            "required": ["conn_str", "stmts"],
+            "additionalProperties": false
+        })
+    }
+
     fn set_datastore(&mut self, _datastore: Option<BoxStore>) {}
 
     fn validate_params(&mut self, params: Map<String, Value>) -> Result<()> {
-        let jops_params = JsonOps::new(Value::Object(params));
+        let jops_params = JsonOps::new(Value::Object(params.clone()));
         let mut default = TokioPgql::default();
 
         // Check URL
@@ -448,6 +501,7 @@ impl Plugin for TokioPgql {
             Err(_) => (),
         };
 
+        default.params = params;
         *self = default;
 
         Ok(())
@@ -566,14 +620,22 @@ impl Plugin for TokioPgql {
             };
 
             for (idx, st) in self.stmts.iter().enumerate() {
-                let mut cond = st.cond.clone().unwrap_or("true".to_string());
-                cond = if cond.is_empty() {
-                    "true".to_string()
-                } else {
-                    cond
+                // No or empty condition: the statement is executed
+                let cond = match st
+                    .cond
+                    .as_ref()
+                    .filter(|c| !c.is_empty())
+                    .map(|c| condition::eval(c.as_str(), &self.params))
+                    .transpose()
+                {
+                    Ok(v) => v.unwrap_or(true),
+                    Err(e) => {
+                        let _ = transaction.rollback().await;
+                        return_plugin_exec_result_err!(result, format!("stmts[{}]: {}", idx, e));
+                    }
                 };
 
-                if eval_boolean(cond.as_str()).unwrap_or(false) {
+                if cond {
                     let stmt = st.stmt.as_str();
 
                     let mut pp_stmt: Option<Statement> = None;
@@ -744,13 +806,7 @@ fn sql_parser(stmt: &str) -> String {
     }
 }
 
-#[no_mangle]
-pub fn get_plugin() -> *mut (dyn Plugin + Send + Sync) {
-    debug!("Plugin TokioPgql loaded!");
-
-    // Return a raw pointer to an instance of our plugin
-    Box::into_raw(Box::new(TokioPgql::default()))
-}
+export_plugin!(TokioPgql);
 
 #[cfg(test)]
 mod tests {
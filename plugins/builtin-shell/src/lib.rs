@@ -1,5 +1,6 @@
 extern crate flowrunner;
 use flowrunner::plugin::{Plugin, PluginExecResult, Status};
+use flowrunner::export_plugin;
 use flowrunner::message::Message as FlowMessage;
 use flowrunner::datastore::store::BoxStore;
 
@@ -9,13 +10,14 @@ use json_ops::JsonOps;
 //use std::collections::HashMap;
 use serde_json::value::Value;
 use serde_json::{Map, Number};
+use serde_json::json;
 
 use anyhow::{anyhow, Result};
 
 use async_trait::async_trait;
 use async_channel::{Sender, Receiver};
 
-use std::process::Command;
+use tokio::process::Command;
 
 use log::*;
 
@@ -45,6 +47,17 @@ impl Plugin for Shell {
         params
     }
 
+    fn param_schema(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "cmd": { "type": "string", "description": "Command to execute with its arguments separated by spaces" }
+            },
+            "required": ["cmd"],
+            "additionalProperties": false
+        })
+    }
+
     fn validate_params(&mut self, params: Map<String, Value>) -> Result<()> {
         let jops_params = JsonOps::new(Value::Object(params));
 
@@ -74,7 +87,10 @@ impl Plugin for Shell {
             cmd.arg(self.args[i].clone());
         }
 
-        let output = cmd.output();
+        // The command is killed if the task is cancelled, e.g. when its timeout is reached
+        cmd.kill_on_drop(true);
+
+        let output = cmd.output().await;
 
         match output {
             Ok(o) => {
@@ -106,13 +122,7 @@ impl Plugin for Shell {
     }
 }
 
-#[no_mangle]
-pub fn get_plugin() -> *mut dyn Plugin {
-    debug!("Plugin Shell loaded!");
-
-    // Return a raw pointer to an instance of our plugin
-    Box::into_raw(Box::new(Shell::default()))
-}
+export_plugin!(Shell);
 
 #[cfg(test)]
 mod tests {
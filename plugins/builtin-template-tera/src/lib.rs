@@ -1,8 +1,10 @@
 extern crate flowrunner;
 use flowrunner::plugin::{Plugin, PluginExecResult, Status};
+use flowrunner::export_plugin;
 use flowrunner::message::Message as FlowMessage;
 use flowrunner::return_plugin_exec_result_err;
 use flowrunner::datastore::store::BoxStore;
+use flowrunner::utils::diff_lines;
 
 extern crate json_ops;
 use json_ops::JsonOps;
@@ -10,6 +12,7 @@ use json_ops::JsonOps;
 //use std::collections::HashMap;
 use serde::{Serialize, Deserialize};
 use serde_json::{Value, Map};
+use serde_json::json;
 
 use anyhow::{anyhow, Result};
 
@@ -54,6 +57,20 @@ impl Plugin for TeraTemplate {
         params
     }
 
+    fn param_schema(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "src": { "type": "string", "minLength": 1 },
+                "dest": { "type": "string", "minLength": 1 },
+                "ctx": { "type": "string", "minLength": 1 },
+                "override": { "type": "boolean", "default": true }
+            },
+            "required": ["src", "dest", "ctx"],
+            "additionalProperties": false
+        })
+    }
+
     fn validate_params(&mut self, params: Map<String, Value>) -> Result<()> {
         let jops_params = JsonOps::new(Value::Object(params));
         let mut default = TeraTemplate::default();
@@ -116,51 +133,22 @@ impl Plugin for TeraTemplate {
 
         let mut result = PluginExecResult::default();
 
-        let src = Path::new(self.src.as_str());
-
-        // Create an instance Tera
-        let tera = if src.is_dir() {
-            let tera = match Tera::new(format!("{}/**/*", self.src).as_str()) {
-                Ok(t) => t,
-                Err(e) => return_plugin_exec_result_err!(result, e.to_string()),
-            };
-
-            let dest = Path::new(self.dest.as_str());
-            if dest.exists() {
-                if !dest.is_dir() {
-                    return_plugin_exec_result_err!(result, "src and dest must be the same kind: directory".to_string());
-                }
-            } else {
-                if let Err(e) = fs::create_dir_all(self.dest.as_str()) {
-                    return_plugin_exec_result_err!(result, e.to_string());
-                }
-            }
+        let tera = match self.load_templates() {
+            Ok(t) => t,
+            Err(e) => return_plugin_exec_result_err!(result, e.to_string()),
+        };
 
-            tera
-        } else if src.is_file() {
-            let mut tera = Tera::default();
-            if let Err(e) = tera.add_template_file(self.src.as_str(), Some("default")) {
+        if Path::new(self.src.as_str()).is_dir() && !Path::new(self.dest.as_str()).exists() {
+            if let Err(e) = fs::create_dir_all(self.dest.as_str()) {
                 return_plugin_exec_result_err!(result, e.to_string());
             }
-
-            let dest = Path::new(self.dest.as_str());
-            if dest.exists() && !dest.is_file() {
-                return_plugin_exec_result_err!(result, "src and dest must be the same kind: file".to_string());
-            }
-
-            tera
-        } else {
-            return_plugin_exec_result_err!(result, "src must be a directory or a file".to_string());
-        };
+        }
 
         // Context
-        let context = match serde_json::from_str(self.ctx.as_str())
-            .map_err(|e| anyhow!(e))
-            .and_then(|v| Context::from_value(v)
-                      .map_err(|e| anyhow!(e))) {
-                Ok(v) => v,
-                Err(e) => return_plugin_exec_result_err!(result, e.to_string()),
-            };
+        let context = match self.context() {
+            Ok(v) => v,
+            Err(e) => return_plugin_exec_result_err!(result, e.to_string()),
+        };
 
         // Set options according to override
         let mut f_opts = fs::OpenOptions::new();
@@ -173,41 +161,116 @@ impl Plugin for TeraTemplate {
         }
 
         // If src = file, just one template (default) to render
-        if src.is_file() {
-            let f_output = match f_opts.open(self.dest.as_str()) {
+        for (tpl, dest) in self.outputs(&tera) {
+            let f_output = match f_opts.open(dest) {
                 Ok(f) => f,
                 Err(e) => return_plugin_exec_result_err!(result, e.to_string()),
             };
 
-            if let Err(e) = tera.render_to("default", &context, f_output) {
+            if let Err(e) = tera.render_to(tpl, &context, f_output) {
                 return_plugin_exec_result_err!(result, e.to_string());
             }
-        } else { // src == directory
-            for tpl in tera.get_template_names() {
-                let f_output = match f_opts.open(format!("{}/{}", self.dest, tpl)) {
-                    Ok(f) => f,
-                    Err(e) => return_plugin_exec_result_err!(result, e.to_string()),
-                };
-
-                if let Err(e) = tera.render_to(tpl, &context, f_output) {
-                    return_plugin_exec_result_err!(result, e.to_string());
-                }
-            }
         }
 
         result.status = Status::Ok;
         result
     }
+
+    /// Shows the differences between the rendered templates and the files of dest
+    async fn func_check(&self, _sender: Option<String>, _tx: &Vec<Sender<FlowMessage>>, _rx: &Vec<Receiver<FlowMessage>>) -> Option<PluginExecResult> {
+        let _ =  env_logger::try_init();
+
+        let mut result = PluginExecResult::default();
+
+        let (tera, context) = match self.load_templates().and_then(|t| Ok((t, self.context()?))) {
+            Ok(v) => v,
+            Err(e) => {
+                result.error = e.to_string();
+                return Some(result);
+            },
+        };
+
+        let mut changed = false;
+        let mut diffs: Vec<String> = Vec::new();
+
+        for (tpl, dest) in self.outputs(&tera) {
+            let rendered = match tera.render(tpl, &context) {
+                Ok(r) => r,
+                Err(e) => {
+                    result.error = e.to_string();
+                    return Some(result);
+                },
+            };
+
+            let current = fs::read_to_string(&dest).ok();
+            if current.is_some() && !self.r#override {
+                result.error = format!("{} already exists", dest);
+                return Some(result);
+            }
+
+            let current = current.unwrap_or_default();
+            if rendered != current || !Path::new(&dest).exists() {
+                changed = true;
+                diffs.push(format!("--- {}\n{}", dest, diff_lines(&current, &rendered)));
+            }
+        }
+
+        result.status = Status::Ok;
+        result.output.insert("changed".to_string(), Value::Bool(changed));
+        result.output.insert("diff".to_string(), Value::String(diffs.join("\n")));
+
+        Some(result)
+    }
 }
 
-#[no_mangle]
-pub fn get_plugin() -> *mut (dyn Plugin + Send + Sync) {
-    debug!("Plugin TeraTemplate loaded!");
+impl TeraTemplate {
+    // Loads the templates of src, which must be of the same kind as dest if it exists
+    fn load_templates(&self) -> Result<Tera> {
+        let src = Path::new(self.src.as_str());
+        let dest = Path::new(self.dest.as_str());
+
+        if src.is_dir() {
+            let tera = Tera::new(format!("{}/**/*", self.src).as_str())?;
+
+            if dest.exists() && !dest.is_dir() {
+                return Err(anyhow!("src and dest must be the same kind: directory"));
+            }
+
+            Ok(tera)
+        } else if src.is_file() {
+            let mut tera = Tera::default();
+            tera.add_template_file(self.src.as_str(), Some("default"))?;
+
+            if dest.exists() && !dest.is_file() {
+                return Err(anyhow!("src and dest must be the same kind: file"));
+            }
+
+            Ok(tera)
+        } else {
+            Err(anyhow!("src must be a directory or a file"))
+        }
+    }
+
+    fn context(&self) -> Result<Context> {
+        let value: Value = serde_json::from_str(self.ctx.as_str())?;
 
-    // Return a raw pointer to an instance of our plugin
-    Box::into_raw(Box::new(TeraTemplate::default()))
+        Ok(Context::from_value(value)?)
+    }
+
+    // Returns the templates to render with their dest file
+    fn outputs<'a>(&self, tera: &'a Tera) -> Vec<(&'a str, String)> {
+        if Path::new(self.src.as_str()).is_file() {
+            return vec![("default", self.dest.clone())];
+        }
+
+        tera.get_template_names()
+            .map(|t| (t, format!("{}/{}", self.dest, t)))
+            .collect()
+    }
 }
 
+export_plugin!(TeraTemplate);
+
 #[cfg(test)]
 mod tests {
     use flowrunner::plugin_exec_result;
@@ -236,4 +299,32 @@ mod tests {
 
         assert_eq!(expected, result);
     }
+
+    #[tokio::test]
+    async fn test_func_check() {
+        let txs = Vec::<Sender<FlowMessage>>::new();
+        let rxs = Vec::<Receiver<FlowMessage>>::new();
+
+        let dir = std::env::temp_dir().join("template_tera_func_check");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("src.tpl"), "name={{ name }}\nport=80").unwrap();
+        fs::write(dir.join("dest"), "name=old\nport=80").unwrap();
+
+        let mut tpl = TeraTemplate::default();
+        tpl.validate_params(serde_json::from_value(json!({
+            "src": dir.join("src.tpl").display().to_string(),
+            "dest": dir.join("dest").display().to_string(),
+            "ctx": r#"{"name": "new"}"#,
+        })).unwrap()).unwrap();
+
+        let expected = plugin_exec_result!(
+            Status::Ok,
+            "",
+            "changed" => Value::Bool(true),
+            "diff" => Value::String(format!("--- {}\n-name=old\n+name=new", dir.join("dest").display())));
+
+        assert_eq!(Some(expected), tpl.func_check(None, &txs, &rxs).await);
+        assert_eq!("name=old\nport=80", fs::read_to_string(dir.join("dest")).unwrap());
+    }
 }
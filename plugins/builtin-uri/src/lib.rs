@@ -1,5 +1,6 @@
 extern crate flowrunner;
 use flowrunner::plugin::{Plugin, PluginExecResult, Status};
+use flowrunner::export_plugin;
 use flowrunner::message::Message as FlowMessage;
 use flowrunner::datastore::store::BoxStore;
 
@@ -9,6 +10,7 @@ use json_ops::JsonOps;
 //use std::collections::HashMap;
 use serde_json::value::Value;
 use serde_json::{Map, Number};
+use serde_json::json;
 
 use std::collections::HashMap;
 
@@ -60,6 +62,26 @@ impl Plugin for Uri {
         params
     }
 
+    fn param_schema(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "url": { "type": "string" },
+                "method": { "type": "string" },
+                "headers": { "type": "object", "additionalProperties": { "type": "string" } },
+                "status_codes": { "type": "array", "items": { "type": "integer", "minimum": 100, "maximum": 999 }, "default": [200] },
+                "body": {},
+                "include_resp_headers": { "type": "boolean", "default": false },
+                "include_resp_url": { "type": "boolean", "default": false },
+                "include_resp_remote_addr": { "type": "boolean", "default": false },
+                "include_resp_content_length": { "type": "boolean", "default": false },
+                "include_resp_cookies": { "type": "boolean", "default": false }
+            },
+            "required": ["url", "method"],
+            "additionalProperties": false
+        })
+    }
+
     fn validate_params(&mut self, params: Map<String, Value>) -> Result<()> {
         let jops_params = JsonOps::new(Value::Object(params));
         let mut default = Uri::default();
@@ -259,13 +281,7 @@ impl Plugin for Uri {
     }
 }
 
-#[no_mangle]
-pub fn get_plugin() -> *mut (dyn Plugin + Send + Sync) {
-    debug!("Plugin Uri loaded!");
-
-    // Return a raw pointer to an instance of our plugin
-    Box::into_raw(Box::new(Uri::default()))
-}
+export_plugin!(Uri);
 
 #[cfg(test)]
 mod tests {
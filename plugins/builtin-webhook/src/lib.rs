@@ -1,5 +1,6 @@
 extern crate flowrunner;
 use flowrunner::plugin::{Plugin, PluginExecResult, Status};
+use flowrunner::export_plugin;
 use flowrunner::message::Message as FlowMessage;
 use flowrunner::return_plugin_exec_result_err;
 use flowrunner::datastore::store::BoxStore;
@@ -15,6 +16,7 @@ use std::sync::Arc;
 //use std::collections::HashMap;
 use serde::{Serialize, Deserialize};
 use serde_json::{Value, Map};
+use serde_json::json;
 
 use anyhow::{anyhow, Result};
 
@@ -75,6 +77,29 @@ impl Plugin for Webhook {
         params
     }
 
+    fn param_schema(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "host_addr": { "type": "string", "description": "Listening address with the format ip:port" },
+                "hooks": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "name": { "type": "string" },
+                            "format": { "type": "object", "additionalProperties": { "type": "string" } }
+                        },
+                        "required": ["name"],
+                        "additionalProperties": false
+                    }
+                }
+            },
+            "required": ["host_addr", "hooks"],
+            "additionalProperties": false
+        })
+    }
+
     fn validate_params(&mut self, params: Map<String, Value>) -> Result<()> {
         let jops_params = JsonOps::new(Value::Object(params));
         let mut default = Webhook::default();
@@ -172,13 +197,7 @@ async fn handler(
     StatusCode::OK
 }
 
-#[no_mangle]
-pub fn get_plugin() -> *mut (dyn Plugin + Send + Sync) {
-    debug!("Plugin Webhook loaded!");
-
-    // Return a raw pointer to an instance of our plugin
-    Box::into_raw(Box::new(Webhook::default()))
-}
+export_plugin!(Webhook);
 
 #[cfg(test)]
 mod tests {
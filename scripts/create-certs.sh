@@ -53,4 +53,8 @@ do
   echo "flowrunner" > ${i}_truststore_creds
 done
 
+# SSH key used to test remote job execution with the sshd container
+mkdir -p ssh
+ssh-keygen -t rsa -b 4096 -N "" -f ssh/id_rsa
+
 popd
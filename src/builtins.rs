@@ -0,0 +1,80 @@
+// Builtin plugins compiled in the binary.
+// Each plugin is enabled by its cargo feature `builtin-<name>` and its sources are
+// included from `plugins/`, so the same code can still be built as a dynamic library.
+
+use crate::plugin::PluginRegistry;
+
+#[cfg(feature = "builtin-datastore")]
+#[path = "../plugins/builtin-datastore/src/lib.rs"]
+mod builtin_datastore;
+#[cfg(feature = "builtin-dnsquery")]
+#[path = "../plugins/builtin-dnsquery/src/lib.rs"]
+mod builtin_dnsquery;
+#[cfg(feature = "builtin-git")]
+#[path = "../plugins/builtin-git/src/lib.rs"]
+mod builtin_git;
+#[cfg(feature = "builtin-httpserver")]
+#[path = "../plugins/builtin-httpserver/src/lib.rs"]
+mod builtin_httpserver;
+#[cfg(feature = "builtin-json-patch")]
+#[path = "../plugins/builtin-json-patch/src/lib.rs"]
+mod builtin_json_patch;
+#[cfg(feature = "builtin-kafka-consumer")]
+#[path = "../plugins/builtin-kafka-consumer/src/lib.rs"]
+mod builtin_kafka_consumer;
+#[cfg(feature = "builtin-kafka-producer")]
+#[path = "../plugins/builtin-kafka-producer/src/lib.rs"]
+mod builtin_kafka_producer;
+#[cfg(feature = "builtin-lineinfile")]
+#[path = "../plugins/builtin-lineinfile/src/lib.rs"]
+mod builtin_lineinfile;
+#[cfg(feature = "builtin-pgql-sqlx")]
+#[path = "../plugins/builtin-pgql-sqlx/src/lib.rs"]
+mod builtin_pgql_sqlx;
+#[cfg(feature = "builtin-pgql-tokio")]
+#[path = "../plugins/builtin-pgql-tokio/src/lib.rs"]
+mod builtin_pgql_tokio;
+#[cfg(feature = "builtin-shell")]
+#[path = "../plugins/builtin-shell/src/lib.rs"]
+mod builtin_shell;
+#[cfg(feature = "builtin-template-tera")]
+#[path = "../plugins/builtin-template-tera/src/lib.rs"]
+mod builtin_template_tera;
+#[cfg(feature = "builtin-uri")]
+#[path = "../plugins/builtin-uri/src/lib.rs"]
+mod builtin_uri;
+#[cfg(feature = "builtin-webhook")]
+#[path = "../plugins/builtin-webhook/src/lib.rs"]
+mod builtin_webhook;
+
+/// Registers all the builtin plugins enabled at compile time
+pub fn register_builtin_plugins() {
+    #[cfg(feature = "builtin-datastore")]
+    PluginRegistry::register_builtin(builtin_datastore::__flowrunner_get_plugin);
+    #[cfg(feature = "builtin-dnsquery")]
+    PluginRegistry::register_builtin(builtin_dnsquery::__flowrunner_get_plugin);
+    #[cfg(feature = "builtin-git")]
+    PluginRegistry::register_builtin(builtin_git::__flowrunner_get_plugin);
+    #[cfg(feature = "builtin-httpserver")]
+    PluginRegistry::register_builtin(builtin_httpserver::__flowrunner_get_plugin);
+    #[cfg(feature = "builtin-json-patch")]
+    PluginRegistry::register_builtin(builtin_json_patch::__flowrunner_get_plugin);
+    #[cfg(feature = "builtin-kafka-consumer")]
+    PluginRegistry::register_builtin(builtin_kafka_consumer::__flowrunner_get_plugin);
+    #[cfg(feature = "builtin-kafka-producer")]
+    PluginRegistry::register_builtin(builtin_kafka_producer::__flowrunner_get_plugin);
+    #[cfg(feature = "builtin-lineinfile")]
+    PluginRegistry::register_builtin(builtin_lineinfile::__flowrunner_get_plugin);
+    #[cfg(feature = "builtin-pgql-sqlx")]
+    PluginRegistry::register_builtin(builtin_pgql_sqlx::__flowrunner_get_plugin);
+    #[cfg(feature = "builtin-pgql-tokio")]
+    PluginRegistry::register_builtin(builtin_pgql_tokio::__flowrunner_get_plugin);
+    #[cfg(feature = "builtin-shell")]
+    PluginRegistry::register_builtin(builtin_shell::__flowrunner_get_plugin);
+    #[cfg(feature = "builtin-template-tera")]
+    PluginRegistry::register_builtin(builtin_template_tera::__flowrunner_get_plugin);
+    #[cfg(feature = "builtin-uri")]
+    PluginRegistry::register_builtin(builtin_uri::__flowrunner_get_plugin);
+    #[cfg(feature = "builtin-webhook")]
+    PluginRegistry::register_builtin(builtin_webhook::__flowrunner_get_plugin);
+}
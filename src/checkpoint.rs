@@ -0,0 +1,217 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use anyhow::{anyhow, Result};
+use log::{error, info};
+
+use crate::datastore::store::StoreConfig;
+use crate::job::{Job, Status as JobStatus};
+
+/// Checkpoint configuration of a flow
+///
+/// The state of each run is saved in the namespace `namespace` of the flow datastore,
+/// so that a failed run can be resumed from its failed tasks.
+#[derive(Default, Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CheckpointConfig {
+    pub namespace: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, Copy, Debug, PartialEq)]
+pub enum RunStatus {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+impl Default for RunStatus {
+    fn default() -> Self {
+        RunStatus::Running
+    }
+}
+
+impl FromStr for RunStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "running" => Ok(RunStatus::Running),
+            "succeeded" => Ok(RunStatus::Succeeded),
+            "failed" => Ok(RunStatus::Failed),
+            _ => Err(anyhow!("Run status {} is not valid, expected: running, succeeded or failed", s)),
+        }
+    }
+}
+
+/// State of a job in a flow run
+#[derive(Default, Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct JobState {
+    #[serde(default)]
+    pub status: JobStatus,
+    #[serde(default)]
+    pub result: Map<String, Value>,
+    // Variables registered by the tasks of the job (`context.register`)
+    #[serde(default)]
+    pub register: Value,
+    // Task from which the job is restarted when the run is resumed
+    #[serde(default)]
+    pub failed_task: Option<String>,
+}
+
+/// Variables given when a run is launched, applied again when the run is resumed.
+/// Encrypted variable files are saved by path, so that their decrypted values are
+/// not written in the datastore.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum VarsOverlay {
+    Variables(Map<String, Value>),
+    EncryptedFile(String),
+}
+
+/// State of a flow run saved in the datastore. Only finished jobs are recorded.
+#[derive(Default, Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct RunState {
+    pub run_id: String,
+    pub flow: String,
+    #[serde(default)]
+    pub status: RunStatus,
+    // Variables of the flow file are read again from the file
+    #[serde(default)]
+    pub overlays: Vec<VarsOverlay>,
+    #[serde(default)]
+    pub user_payload: Value,
+    #[serde(default)]
+    pub jobs: BTreeMap<String, JobState>,
+    #[serde(default)]
+    pub started_at: String,
+    #[serde(default)]
+    pub updated_at: String,
+}
+
+impl RunState {
+    pub fn new(run_id: &str, flow: &str, overlays: &[VarsOverlay], user_payload: &Value) -> Self {
+        let now = chrono::Utc::now().to_rfc3339();
+
+        RunState {
+            run_id: run_id.to_string(),
+            flow: flow.to_string(),
+            overlays: overlays.to_vec(),
+            user_payload: user_payload.clone(),
+            started_at: now.clone(),
+            updated_at: now,
+            ..Default::default()
+        }
+    }
+
+    /// Reads the state of the run `run_id` in the namespace `ns` of the datastore
+    pub fn load(datastore: &StoreConfig, ns: &str, run_id: &str) -> Result<RunState> {
+        let store = datastore.new_store()?;
+        let content = store.get(ns, run_id)
+            .map_err(|e| anyhow!("Cannot read the state of the run {}: {}", run_id, e))?;
+
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    /// Writes the state in the namespace `ns` of the datastore
+    pub fn save(&mut self, datastore: &StoreConfig, ns: &str) -> Result<()> {
+        self.updated_at = chrono::Utc::now().to_rfc3339();
+
+        let store = datastore.new_store()?;
+        store.set(ns, &self.run_id, &serde_json::to_string(self)?)
+    }
+
+    /// Records a finished job
+    pub fn set_job(&mut self, job: &Job) {
+        self.jobs.insert(job.name.clone(), JobState {
+            status: job.status,
+            result: job.result.clone(),
+            register: job.context.get("register").cloned().unwrap_or_default(),
+            failed_task: job.failed_task.clone(),
+        });
+    }
+}
+
+/// Saves the run state in the datastore while the flow runs
+#[derive(Clone, Debug)]
+pub struct Checkpoint {
+    datastore: StoreConfig,
+    namespace: String,
+    pub state: RunState,
+}
+
+impl Checkpoint {
+    pub fn new(datastore: &StoreConfig, namespace: &str, state: RunState) -> Self {
+        Checkpoint {
+            datastore: datastore.clone(),
+            namespace: namespace.to_string(),
+            state,
+        }
+    }
+
+    /// Records the finished job and saves the state. Errors are only logged so that
+    /// a datastore failure does not stop the flow.
+    pub fn job_done(&mut self, job: &Job) {
+        self.state.set_job(job);
+        self.save();
+    }
+
+    /// Sets the final status of the run and saves the state
+    pub fn finish(&mut self, status: RunStatus) {
+        self.state.status = status;
+        self.save();
+
+        info!("Flow run finished: flow={}, run_id={}, status={:?}", self.state.flow, self.state.run_id, status);
+    }
+
+    pub fn save(&mut self) {
+        if let Err(e) = self.state.save(&self.datastore, &self.namespace) {
+            error!("Failed to save the run state: run_id={}, err={}", self.state.run_id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_run_state() {
+        let mut job = Job::default();
+        job.name = "job1".to_string();
+        job.status = JobStatus::Ko;
+        job.failed_task = Some("task2".to_string());
+        job.result.insert("task1".to_string(), json!({ "status": "Ok" }));
+        job.context.insert("register".to_string(), json!({ "var1": 1 }));
+
+        let overlays = vec![
+            VarsOverlay::Variables(json!({ "env": "prod" }).as_object().unwrap().to_owned()),
+            VarsOverlay::EncryptedFile("/etc/flowrunner/prod.yaml".to_string()),
+        ];
+        let mut state = RunState::new("run1", "flow1", &overlays, &Value::Null);
+        state.set_job(&job);
+
+        job.name = "job2".to_string();
+        job.status = JobStatus::Ok;
+        job.failed_task = None;
+        state.set_job(&job);
+
+        assert_eq!(JobState {
+            status: JobStatus::Ko,
+            result: job.result.clone(),
+            register: json!({ "var1": 1 }),
+            failed_task: Some("task2".to_string()),
+        }, state.jobs["job1"]);
+
+        assert_eq!(JobStatus::Ok, state.jobs["job2"].status);
+
+        let value: RunState = serde_json::from_str(&serde_json::to_string(&state).unwrap()).unwrap();
+        assert_eq!(state, value);
+
+        assert_eq!(RunStatus::Failed, "Failed".parse::<RunStatus>().unwrap());
+        assert!("done".parse::<RunStatus>().is_err());
+    }
+}
@@ -0,0 +1,715 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde_json::{Map, Value};
+
+use anyhow::{anyhow, Result};
+use regex::Regex;
+
+/// Condition evaluated against JSON data, used by the `if` and `until` of jobs, tasks and
+/// sinks and by the `cond` of plugins.
+///
+/// Variables are looked up in the data (`context`, `result`...) with `.field`, `[index]` or
+/// `["key"]`. Missing fields and fields of `null` evaluate to `null`. Supported operators,
+/// from the lowest precedence:
+///
+/// - `||` or `or`, `&&` or `and`, both short-circuiting, and `!` or `not`
+/// - `==`, `!=`, `<`, `<=`, `>`, `>=`, `in`, `not in`, `=~` and `!~` (regex)
+/// - `+`, `-`, `*`, `/` and `%`
+///
+/// Literals are numbers, strings in single or double quotes, `true`, `false`, `null` and
+/// arrays. `len(value)` returns the length of a string, an array or an object.
+///
+/// Conditions are not templates: `{{ }}` is rejected, since the interpolated data would
+/// be parsed as a part of the condition.
+///
+/// ```yaml
+/// if: result.task1.status == "Ok" && "x" in context.msg_id.data.tags
+/// ```
+#[derive(Debug)]
+pub struct Condition {
+    source: String,
+    root: Node,
+}
+
+impl Condition {
+    pub fn parse(source: &str) -> Result<Self> {
+        let tokens = tokenize(source).map_err(|e| anyhow!("condition {:?}: {}", source, e))?;
+        let mut parser = Parser { source, tokens, pos: 0 };
+
+        let root = parser.parse().map_err(|e| anyhow!("condition {:?}: {}", source, e))?;
+
+        Ok(Condition { source: source.to_string(), root })
+    }
+
+    /// Evaluates the condition. `null` is false, any other value than a boolean is an error.
+    pub fn eval(&self, data: &Map<String, Value>) -> Result<bool> {
+        self.root.eval_bool(data).map_err(|e| anyhow!("condition {:?}: {}", self.source, e))
+    }
+}
+
+impl FromStr for Condition {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Condition::parse(s)
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+/// Parses and evaluates the condition
+pub fn eval(source: &str, data: &Map<String, Value>) -> Result<bool> {
+    Condition::parse(source)?.eval(data)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Str(String),
+    Ident(String),
+    Op(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Str(s) => write!(f, "{:?}", s),
+            Token::Ident(s) => write!(f, "{}", s),
+            Token::Op(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+// Operators, the longest first
+const OPERATORS: [&str; 21] = [
+    "==", "!=", "<=", ">=", "&&", "||", "=~", "!~",
+    "<", ">", "!", "+", "-", "*", "/", "%", "(", ")", "[", "]", ",",
+];
+
+// Tokens with their byte offsets in the source
+fn tokenize(source: &str) -> std::result::Result<Vec<(Token, usize, usize)>, String> {
+    let mut tokens: Vec<(Token, usize, usize)> = Vec::new();
+    let chars: Vec<(usize, char)> = source.char_indices().collect();
+    let offset = |i: usize| chars.get(i).map(|c| c.0).unwrap_or(source.len());
+    let mut i = 0;
+
+    while i < chars.len() {
+        let (start, c) = chars[i];
+
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '.' {
+            tokens.push((Token::Op("."), start, start + 1));
+            i += 1;
+        } else if c.is_ascii_digit() {
+            // Indexes after a dot are integers: `a.0.1`
+            let after_dot = matches!(tokens.last(), Some((Token::Op("."), _, _)));
+            let mut j = i;
+            while j < chars.len() && (chars[j].1.is_ascii_digit() || (!after_dot && chars[j].1 == '.')) {
+                j += 1;
+            }
+
+            let text = &source[start..offset(j)];
+            let n = text.parse::<f64>().map_err(|_| format!("invalid number {} at position {}", text, start))?;
+            tokens.push((Token::Number(n), start, offset(j)));
+            i = j;
+        } else if c.is_alphabetic() || c == '_' {
+            let mut j = i;
+            while j < chars.len() && (chars[j].1.is_alphanumeric() || chars[j].1 == '_') {
+                j += 1;
+            }
+
+            tokens.push((Token::Ident(source[start..offset(j)].to_string()), start, offset(j)));
+            i = j;
+        } else if c == '"' || c == '\'' {
+            let mut s = String::new();
+            let mut j = i + 1;
+
+            loop {
+                match chars.get(j).map(|c| c.1) {
+                    None => return Err(format!("unterminated string at position {}", start)),
+                    Some(q) if q == c => break,
+                    Some('\\') => {
+                        match chars.get(j + 1).map(|c| c.1) {
+                            Some('n') => s.push('\n'),
+                            Some('t') => s.push('\t'),
+                            Some('r') => s.push('\r'),
+                            Some(e) if e == c || e == '\\' => s.push(e),
+                            // Other escapes are kept for regexes: '\d+'
+                            Some(e) => {
+                                s.push('\\');
+                                s.push(e);
+                            },
+                            None => return Err(format!("unterminated string at position {}", start)),
+                        }
+                        j += 2;
+                    },
+                    Some(ch) => {
+                        s.push(ch);
+                        j += 1;
+                    },
+                }
+            }
+
+            tokens.push((Token::Str(s), start, offset(j + 1)));
+            i = j + 1;
+        } else if source[start..].starts_with("{{") || source[start..].starts_with("{%") {
+            return Err(format!("template tags are not supported at position {}, variables are read directly: write `x == 1` instead of `{{{{ x == 1 }}}}`", start));
+        } else {
+            let op = OPERATORS.iter().find(|op| source[start..].starts_with(*op))
+                .ok_or_else(|| format!("unexpected character {:?} at position {}", c, start))?;
+
+            tokens.push((Token::Op(op), start, start + op.len()));
+            i += op.chars().count();
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BinOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    In,
+    NotIn,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+#[derive(Debug)]
+enum Expr {
+    Literal(Value),
+    Var(String),
+    Field(Box<Node>, String),
+    Index(Box<Node>, Box<Node>),
+    List(Vec<Node>),
+    Len(Box<Node>),
+    Not(Box<Node>),
+    Neg(Box<Node>),
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Binary(BinOp, Box<Node>, Box<Node>),
+    // The regex is compiled when the condition is parsed if the pattern is a literal
+    Match { value: Box<Node>, pattern: Box<Node>, regex: Option<Regex>, negate: bool },
+}
+
+// Expression with its text in the condition, shown in the evaluation errors
+#[derive(Debug)]
+struct Node {
+    expr: Expr,
+    text: String,
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    tokens: Vec<(Token, usize, usize)>,
+    pos: usize,
+}
+
+type ParseResult<T> = std::result::Result<T, String>;
+
+impl Parser<'_> {
+    fn parse(&mut self) -> ParseResult<Node> {
+        if self.tokens.is_empty() {
+            return Err("condition is empty".to_string());
+        }
+
+        let node = self.parse_or()?;
+
+        match self.tokens.get(self.pos) {
+            Some((t, start, _)) => Err(format!("unexpected {} at position {}", t, start)),
+            None => Ok(node),
+        }
+    }
+
+    fn parse_or(&mut self) -> ParseResult<Node> {
+        let start = self.start();
+        let mut node = self.parse_and()?;
+
+        while self.eat_op("||") || self.eat_ident("or") {
+            let rhs = self.parse_and()?;
+            node = self.node(start, Expr::Or(Box::new(node), Box::new(rhs)));
+        }
+
+        Ok(node)
+    }
+
+    fn parse_and(&mut self) -> ParseResult<Node> {
+        let start = self.start();
+        let mut node = self.parse_not()?;
+
+        while self.eat_op("&&") || self.eat_ident("and") {
+            let rhs = self.parse_not()?;
+            node = self.node(start, Expr::And(Box::new(node), Box::new(rhs)));
+        }
+
+        Ok(node)
+    }
+
+    fn parse_not(&mut self) -> ParseResult<Node> {
+        let start = self.start();
+
+        if self.eat_op("!") || self.eat_ident("not") {
+            let operand = self.parse_not()?;
+            return Ok(self.node(start, Expr::Not(Box::new(operand))));
+        }
+
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> ParseResult<Node> {
+        let start = self.start();
+        let lhs = self.parse_additive()?;
+
+        let op = match (self.peek(), self.tokens.get(self.pos + 1).map(|t| &t.0)) {
+            (Some(Token::Op(op)), _) if ["==", "!=", "<", "<=", ">", ">=", "=~", "!~"].contains(op) => *op,
+            (Some(Token::Ident(i)), _) if i == "in" => "in",
+            (Some(Token::Ident(i)), Some(Token::Ident(j))) if i == "not" && j == "in" => "not in",
+            _ => return Ok(lhs),
+        };
+        self.pos += if op == "not in" { 2 } else { 1 };
+
+        let rhs = self.parse_additive()?;
+
+        let expr = match op {
+            "=~" | "!~" => {
+                let regex = match &rhs.expr {
+                    Expr::Literal(Value::String(p)) => Some(Regex::new(p).map_err(|e| format!("`{}`: {}", rhs.text, e))?),
+                    _ => None,
+                };
+
+                Expr::Match { value: Box::new(lhs), pattern: Box::new(rhs), regex, negate: op == "!~" }
+            },
+            _ => {
+                let op = match op {
+                    "==" => BinOp::Eq,
+                    "!=" => BinOp::Ne,
+                    "<" => BinOp::Lt,
+                    "<=" => BinOp::Le,
+                    ">" => BinOp::Gt,
+                    ">=" => BinOp::Ge,
+                    "in" => BinOp::In,
+                    _ => BinOp::NotIn,
+                };
+
+                Expr::Binary(op, Box::new(lhs), Box::new(rhs))
+            },
+        };
+
+        Ok(self.node(start, expr))
+    }
+
+    fn parse_additive(&mut self) -> ParseResult<Node> {
+        let start = self.start();
+        let mut node = self.parse_multiplicative()?;
+
+        loop {
+            let op = if self.eat_op("+") {
+                BinOp::Add
+            } else if self.eat_op("-") {
+                BinOp::Sub
+            } else {
+                return Ok(node);
+            };
+
+            let rhs = self.parse_multiplicative()?;
+            node = self.node(start, Expr::Binary(op, Box::new(node), Box::new(rhs)));
+        }
+    }
+
+    fn parse_multiplicative(&mut self) -> ParseResult<Node> {
+        let start = self.start();
+        let mut node = self.parse_unary()?;
+
+        loop {
+            let op = if self.eat_op("*") {
+                BinOp::Mul
+            } else if self.eat_op("/") {
+                BinOp::Div
+            } else if self.eat_op("%") {
+                BinOp::Rem
+            } else {
+                return Ok(node);
+            };
+
+            let rhs = self.parse_unary()?;
+            node = self.node(start, Expr::Binary(op, Box::new(node), Box::new(rhs)));
+        }
+    }
+
+    fn parse_unary(&mut self) -> ParseResult<Node> {
+        let start = self.start();
+
+        if self.eat_op("-") {
+            let operand = self.parse_unary()?;
+            return Ok(self.node(start, Expr::Neg(Box::new(operand))));
+        }
+
+        self.parse_postfix()
+    }
+
+    fn parse_postfix(&mut self) -> ParseResult<Node> {
+        let start = self.start();
+        let mut node = self.parse_primary()?;
+
+        loop {
+            if self.eat_op(".") {
+                let field = match self.next()? {
+                    Token::Ident(i) => i,
+                    Token::Number(n) if n.fract() == 0.0 => format!("{}", n),
+                    t => return Err(format!("expected a field after `{}`, found {}", node.text, t)),
+                };
+
+                node = self.node(start, Expr::Field(Box::new(node), field));
+            } else if self.eat_op("[") {
+                let index = self.parse_or()?;
+                self.expect_op("]")?;
+
+                node = self.node(start, Expr::Index(Box::new(node), Box::new(index)));
+            } else {
+                return Ok(node);
+            }
+        }
+    }
+
+    fn parse_primary(&mut self) -> ParseResult<Node> {
+        let start = self.start();
+
+        let expr = match self.next()? {
+            Token::Number(n) => Expr::Literal(number(n)),
+            Token::Str(s) => Expr::Literal(Value::String(s)),
+            Token::Ident(i) => match i.as_str() {
+                "true" => Expr::Literal(Value::Bool(true)),
+                "false" => Expr::Literal(Value::Bool(false)),
+                "null" => Expr::Literal(Value::Null),
+                "len" if self.eat_op("(") => {
+                    let arg = self.parse_or()?;
+                    self.expect_op(")")?;
+                    Expr::Len(Box::new(arg))
+                },
+                "and" | "or" | "not" | "in" => return Err(format!("unexpected {} at position {}", i, start)),
+                _ => Expr::Var(i),
+            },
+            Token::Op("(") => {
+                let node = self.parse_or()?;
+                self.expect_op(")")?;
+                return Ok(node);
+            },
+            Token::Op("[") => {
+                let mut items = Vec::new();
+                if !self.eat_op("]") {
+                    loop {
+                        items.push(self.parse_or()?);
+                        if self.eat_op("]") {
+                            break;
+                        }
+                        self.expect_op(",")?;
+                    }
+                }
+                Expr::List(items)
+            },
+            t => return Err(format!("unexpected {} at position {}", t, start)),
+        };
+
+        Ok(self.node(start, expr))
+    }
+
+    fn node(&self, start: usize, expr: Expr) -> Node {
+        let end = match self.pos {
+            0 => start,
+            p => self.tokens[p - 1].2,
+        };
+
+        Node { expr, text: self.source[start..end].to_string() }
+    }
+
+    fn start(&self) -> usize {
+        self.tokens.get(self.pos).map(|t| t.1).unwrap_or(self.source.len())
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|t| &t.0)
+    }
+
+    fn next(&mut self) -> ParseResult<Token> {
+        let t = self.peek().cloned().ok_or_else(|| "unexpected end of condition".to_string())?;
+        self.pos += 1;
+
+        Ok(t)
+    }
+
+    fn eat_op(&mut self, op: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Op(o)) if *o == op) {
+            self.pos += 1;
+            return true;
+        }
+
+        false
+    }
+
+    fn eat_ident(&mut self, ident: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Ident(i)) if i == ident) {
+            self.pos += 1;
+            return true;
+        }
+
+        false
+    }
+
+    fn expect_op(&mut self, op: &str) -> ParseResult<()> {
+        if self.eat_op(op) {
+            return Ok(());
+        }
+
+        match self.tokens.get(self.pos) {
+            Some((t, start, _)) => Err(format!("expected {} at position {}, found {}", op, start, t)),
+            None => Err(format!("expected {} at the end of condition", op)),
+        }
+    }
+}
+
+type EvalResult<T> = std::result::Result<T, String>;
+
+impl Node {
+    fn eval_bool(&self, data: &Map<String, Value>) -> EvalResult<bool> {
+        match self.eval(data)? {
+            Value::Bool(b) => Ok(b),
+            Value::Null => Ok(false),
+            v => Err(format!("`{}` is {}, expected a boolean", self.text, describe(&v))),
+        }
+    }
+
+    fn eval(&self, data: &Map<String, Value>) -> EvalResult<Value> {
+        match &self.expr {
+            Expr::Literal(v) => Ok(v.clone()),
+            Expr::Var(name) => Ok(data.get(name).cloned().unwrap_or(Value::Null)),
+            Expr::Field(node, field) => Ok(get_field(&node.eval(data)?, field)),
+            Expr::Index(node, index) => {
+                let value = node.eval(data)?;
+
+                match index.eval(data)? {
+                    Value::String(s) => Ok(get_field(&value, &s)),
+                    Value::Number(n) => Ok(get_field(&value, &n.to_string())),
+                    v => Err(format!("`{}` is {}, expected a string or a number", index.text, describe(&v))),
+                }
+            },
+            Expr::List(items) => Ok(Value::Array(items.iter().map(|n| n.eval(data)).collect::<EvalResult<Vec<Value>>>()?)),
+            Expr::Len(node) => match node.eval(data)? {
+                Value::String(s) => Ok(Value::from(s.chars().count())),
+                Value::Array(a) => Ok(Value::from(a.len())),
+                Value::Object(o) => Ok(Value::from(o.len())),
+                Value::Null => Ok(Value::from(0)),
+                v => Err(format!("`{}` is {}, which has no length", node.text, describe(&v))),
+            },
+            Expr::Not(node) => Ok(Value::Bool(!node.eval_bool(data)?)),
+            Expr::Neg(node) => match node.eval(data)? {
+                Value::Number(n) => Ok(number(-n.as_f64().unwrap_or_default())),
+                v => Err(format!("`{}` is {}, expected a number", node.text, describe(&v))),
+            },
+            Expr::And(lhs, rhs) => Ok(Value::Bool(lhs.eval_bool(data)? && rhs.eval_bool(data)?)),
+            Expr::Or(lhs, rhs) => Ok(Value::Bool(lhs.eval_bool(data)? || rhs.eval_bool(data)?)),
+            Expr::Binary(op, lhs, rhs) => self.eval_binary(*op, lhs.eval(data)?, rhs.eval(data)?),
+            Expr::Match { value, pattern, regex, negate } => {
+                let s = match value.eval(data)? {
+                    Value::String(s) => s,
+                    Value::Null => return Ok(Value::Bool(*negate)),
+                    v => return Err(format!("`{}` is {}, expected a string", value.text, describe(&v))),
+                };
+
+                let is_match = match regex {
+                    Some(re) => re.is_match(&s),
+                    None => match pattern.eval(data)? {
+                        Value::String(p) => Regex::new(&p).map_err(|e| format!("`{}`: {}", pattern.text, e))?.is_match(&s),
+                        v => return Err(format!("`{}` is {}, expected a regex", pattern.text, describe(&v))),
+                    },
+                };
+
+                Ok(Value::Bool(is_match != *negate))
+            },
+        }
+    }
+
+    fn eval_binary(&self, op: BinOp, lhs: Value, rhs: Value) -> EvalResult<Value> {
+        match op {
+            BinOp::Eq => Ok(Value::Bool(equals(&lhs, &rhs))),
+            BinOp::Ne => Ok(Value::Bool(!equals(&lhs, &rhs))),
+            BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => {
+                let ordering = match (&lhs, &rhs) {
+                    // Comparisons with null are false
+                    (Value::Null, _) | (_, Value::Null) => return Ok(Value::Bool(false)),
+                    (Value::Number(a), Value::Number(b)) => a.as_f64().partial_cmp(&b.as_f64()),
+                    (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+                    _ => return Err(format!("`{}`: cannot compare {} with {}", self.text, describe(&lhs), describe(&rhs))),
+                };
+
+                let res = match ordering {
+                    Some(o) => match op {
+                        BinOp::Lt => o.is_lt(),
+                        BinOp::Le => o.is_le(),
+                        BinOp::Gt => o.is_gt(),
+                        _ => o.is_ge(),
+                    },
+                    None => false,
+                };
+
+                Ok(Value::Bool(res))
+            },
+            BinOp::In | BinOp::NotIn => {
+                let found = match (&lhs, &rhs) {
+                    (_, Value::Null) => false,
+                    (_, Value::Array(a)) => a.iter().any(|v| equals(&lhs, v)),
+                    (Value::String(s), Value::String(t)) => t.contains(s.as_str()),
+                    (Value::String(s), Value::Object(o)) => o.contains_key(s),
+                    _ => return Err(format!("`{}`: cannot look for {} in {}", self.text, describe(&lhs), describe(&rhs))),
+                };
+
+                Ok(Value::Bool(found == (op == BinOp::In)))
+            },
+            _ => match (&lhs, &rhs) {
+                (Value::Number(a), Value::Number(b)) => {
+                    let (a, b) = (a.as_f64().unwrap_or_default(), b.as_f64().unwrap_or_default());
+
+                    match op {
+                        BinOp::Add => Ok(number(a + b)),
+                        BinOp::Sub => Ok(number(a - b)),
+                        BinOp::Mul => Ok(number(a * b)),
+                        _ if b == 0.0 => Err(format!("`{}`: division by zero", self.text)),
+                        BinOp::Div => Ok(number(a / b)),
+                        _ => Ok(number(a % b)),
+                    }
+                },
+                (Value::String(a), Value::String(b)) if op == BinOp::Add => Ok(Value::String(format!("{}{}", a, b))),
+                _ => Err(format!("`{}`: unsupported operation on {} and {}", self.text, describe(&lhs), describe(&rhs))),
+            },
+        }
+    }
+}
+
+fn get_field(value: &Value, field: &str) -> Value {
+    match value {
+        Value::Object(o) => o.get(field).cloned().unwrap_or(Value::Null),
+        Value::Array(a) => field.parse::<usize>().ok().and_then(|i| a.get(i)).cloned().unwrap_or(Value::Null),
+        _ => Value::Null,
+    }
+}
+
+// Numbers are equal whatever their representation: 1 == 1.0
+fn equals(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => x.as_f64() == y.as_f64(),
+        _ => a == b,
+    }
+}
+
+// Integral numbers are kept as integers to be compared with the data and displayed as such
+fn number(n: f64) -> Value {
+    if n.fract() == 0.0 && n.abs() < i64::MAX as f64 {
+        return Value::from(n as i64);
+    }
+
+    Value::from(n)
+}
+
+fn describe(value: &Value) -> String {
+    let kind = match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    };
+
+    let mut s = value.to_string();
+    if s.len() > 50 {
+        s = format!("{}...", s.chars().take(47).collect::<String>());
+    }
+
+    match value {
+        Value::Null => kind.to_string(),
+        _ => format!("{} {}", kind, s),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_condition() {
+        let data = json!({
+            "context": {
+                "msg_id": { "data": { "tags": ["x", "y"], "count": 3, "name": "flow-1", "value": null } },
+                "variables": { "env": "prod", "limit": 2.5 },
+            },
+            "result": { "task1": { "status": "Ok", "output": { "items": [{ "id": 1 }, { "id": 2 }] } } },
+        });
+        let data = data.as_object().unwrap();
+
+        let ok = |s: &str| eval(s, data).unwrap();
+
+        assert!(ok(r#"result.task1.status == "Ok" && "x" in context.msg_id.data.tags"#));
+        assert!(ok("true"));
+        assert!(!ok("1 == 2"));
+        assert!(ok("context.msg_id.data.count > context.variables.limit"));
+        assert!(ok("context.msg_id.data.count == 3.0 and not (context.variables.env != 'prod')"));
+        assert!(ok("context.msg_id.data.count * 2 - 1 == 5 && 7 % 4 == 3 && -1 < 0"));
+        assert!(ok("result.task1.output.items.1.id == 2 && result['task1'][\"output\"].items[0].id == 1"));
+        assert!(ok("'z' not in context.msg_id.data.tags && 'low' in 'flowrunner' && 'env' in context.variables"));
+        assert!(ok("context.variables.env in ['dev', 'prod']"));
+        assert!(ok("context.msg_id.data.name =~ '^flow-\\d+$' && context.msg_id.data.name !~ 'job'"));
+        assert!(ok("len(context.msg_id.data.tags) == 2 && len(result.task1) == 2 && len(context.unknown) == 0"));
+        assert!(ok("'a' + 'b' == 'ab'"));
+
+        // Null safety
+        assert!(ok("context.msg_id.data.value == null && context.unknown.field.deep == null"));
+        assert!(!ok("context.msg_id.data.value"));
+        assert!(!ok("context.unknown > 1"));
+        assert!(!ok("'x' in context.unknown"));
+        assert!(ok("context.unknown !~ 'a'"));
+
+        // Short-circuiting
+        assert!(!ok("false && 1 + 'a'"));
+        assert!(ok("true || 1 + 'a'"));
+
+        // Errors show the sub-expression which failed
+        let err = eval("result.task1.status == 'Ok' && context.msg_id.data.tags > 1", data).unwrap_err();
+        assert_eq!(
+            r#"condition "result.task1.status == 'Ok' && context.msg_id.data.tags > 1": `context.msg_id.data.tags > 1`: cannot compare an array ["x","y"] with a number 1"#,
+            err.to_string()
+        );
+
+        let err = eval("context.msg_id.data.count", data).unwrap_err();
+        assert_eq!(r#"condition "context.msg_id.data.count": `context.msg_id.data.count` is a number 3, expected a boolean"#, err.to_string());
+
+        assert!(eval("1 / (context.msg_id.data.count - 3) == 1", data).unwrap_err().to_string().ends_with("`1 / (context.msg_id.data.count - 3)`: division by zero"));
+
+        // Parse errors
+        for s in ["", "1 ==", "(true", "'unterminated", "a.b ^ 1", "true false", "a =~ '('"] {
+            assert!(Condition::parse(s).is_err(), "{:?} must not be parsed", s);
+        }
+
+        // Data is never parsed as a part of the condition
+        assert!(!ok(r#"context.msg_id.data.name == 'x" || true || "'"#));
+        assert!(ok("context.msg_id.data.name != '{{ x }}'"));
+        assert_eq!(
+            r#"condition "{{ context.x == 1 }}": template tags are not supported at position 0, variables are read directly: write `x == 1` instead of `{{ x == 1 }}`"#,
+            Condition::parse("{{ context.x == 1 }}").unwrap_err().to_string()
+        );
+    }
+}
@@ -4,7 +4,9 @@ use std::fs::File;
 //
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug ,Serialize, Deserialize, PartialEq, Clone)]
+use crate::history::HistoryConfig;
+
+#[derive(Default, Debug ,Serialize, Deserialize, PartialEq, Clone)]
 pub struct Config {
     pub runner: RunnerConfig,
 }
@@ -16,7 +18,13 @@ pub struct RunnerConfig {
     #[serde(default)]
     pub flow_dir: String,
     #[serde(default)]
-    pub job_parallel: bool
+    pub job_parallel: bool,
+    // Lets plugins found in plugin_dir replace builtin plugins with the same name
+    #[serde(default)]
+    pub override_builtin_plugins: bool,
+    // Records the runs of action and cron flows
+    #[serde(default)]
+    pub history: Option<HistoryConfig>,
 }
 
 #[allow(dead_code)]
@@ -37,7 +45,9 @@ mod tests {
             runner: RunnerConfig{
                 plugin_dir: "plugins".to_string(),
                 flow_dir: "flows".to_string(),
-                job_parallel: true
+                job_parallel: true,
+                override_builtin_plugins: false,
+                history: None,
             }
         };
 
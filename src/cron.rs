@@ -6,19 +6,31 @@ use std::collections::HashMap;
 
 use log::*;
 
+use chrono::Utc;
+
 use tokio_cron_scheduler::{JobScheduler, Job};
 
 use crate::config::Config;
+use crate::exec::open_history;
 use crate::flow::{Flow, Kind};
+use crate::history::Trigger;
 
 pub async fn cron_run(config: &Config) -> Result<()> {
     let mut scheduler = JobScheduler::new()?;
     let mut flows: HashMap<String, Flow> = HashMap::new();
+    let history = open_history(config)?;
 
     let paths = fs::read_dir(config.runner.flow_dir.clone()).unwrap_or_else(|_| panic!("Cannot read files in the directory {}", config.runner.flow_dir));
     for path in paths {
-        if let Some(p) = path?.path().to_str() {
-            let flow = Flow::new_from_file(p)?;
+        let path = path?.path();
+
+        // Subdirectories hold files included in flows
+        if !path.is_file() {
+            continue;
+        }
+
+        if let Some(p) = path.file_name().and_then(|f| f.to_str()) {
+            let flow = Flow::new_from_flow_dir(&config.runner.flow_dir, p)?;
 
             if flow.kind == Kind::Cron {
                 if flows.contains_key(&flow.name.clone()) {
@@ -30,6 +42,7 @@ pub async fn cron_run(config: &Config) -> Result<()> {
 
                 let schedule = flow.schedule.clone();
                 let flow_cloned = flow.clone();
+                let history_cloned = history.clone();
 
                 info!("Adding new scheduled job: flow={}, schedule={}", flow.name, schedule);
                 let mut job = Job::new_async(
@@ -37,8 +50,15 @@ pub async fn cron_run(config: &Config) -> Result<()> {
                     //"1/4 * * * * *",
                     move |_uuid, _lock| {
                         let mut flow_cloned = flow_cloned.clone();
+                        let history_cloned = history_cloned.clone();
                         Box::pin(async move {
-                            if let Err(e) = flow_cloned.run().await {
+                            let started_at = Utc::now();
+                            let result = flow_cloned.run().await;
+                            if let Some(h) = &history_cloned {
+                                h.record_flow(&flow_cloned, Trigger::Cron, &started_at, &result);
+                            }
+
+                            if let Err(e) = result {
                                 error!("Failed to run the flow: err={e}")
                             }
 
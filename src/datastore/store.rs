@@ -2,6 +2,10 @@ use anyhow::{anyhow, Result};
 use serde::{Serialize, Deserialize};
 use serde_json::{Value, Map};
 
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
 use crate::datastore::store_rocksdb::RocksDB;
 
 pub type BoxStore = Box<dyn Store + Send + Sync>;
@@ -39,29 +43,56 @@ impl Clone for BoxStore {
 /// absolute path to a local file or a tcp address to a remote server. If the server supports TTL,
 /// we can define it with the attribute `ttl`. `Namespace` can be used to isolate data.
 #[derive(Default, Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
 pub struct StoreConfig {
+    #[serde(default)]
     pub kind: String,
+    #[serde(default)]
     pub conn_str: String,
+    #[serde(default)]
     pub options: Map<String, Value>,
+    #[serde(default)]
     pub ttl: u64,
+    #[serde(default)]
     pub namespaces: Vec<StoreNamespace>
 }
 
 /// Store namespace
 #[derive(Default, Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
 pub struct StoreNamespace {
+    #[serde(default)]
     pub name: String,
+    #[serde(default)]
     pub prefix_len: Option<usize>,
+    #[serde(default)]
     pub options: Map<String, Value>
 }
 
+lazy_static! {
+    // Stores opened by the process. A store such as RocksDB can not be opened several times
+    // at the same time, so tasks, jobs and flow checkpoints share the same store.
+    static ref STORES: Mutex<HashMap<String, BoxStore>> = Mutex::new(HashMap::new());
+}
+
 impl StoreConfig {
+    /// Returns the store of the configuration. A store is opened only once per process and
+    /// is identified by its kind and `conn_str`.
     pub fn new_store(&self) -> Result<BoxStore> {
+        let key = format!("{}:{}", self.kind, self.conn_str);
+        let mut stores = STORES.lock().unwrap();
+
+        if let Some(db) = stores.get(&key) {
+            return Ok(db.clone());
+        }
+
         let db: BoxStore = match self.kind.as_str() {
             "rocksdb" => Box::new(RocksDB::init(self)),
             _ => return Err(anyhow!("{}", format!("Datastore's kind {} not supported!", self.kind))),
         };
 
+        stores.insert(key, db.clone());
+
         Ok(db)
     }
 }
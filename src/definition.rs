@@ -0,0 +1,1143 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::de::{self, DeserializeSeed, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::de::value::MapAccessDeserializer;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+
+use anyhow::{anyhow, Result};
+use log::warn;
+
+use crate::checkpoint::CheckpointConfig;
+use crate::datastore::store::StoreConfig;
+use crate::flow::{Flow, Kind, read_vars_file};
+use crate::job::{Job, Task};
+use crate::secret::SecretDef;
+use crate::sink::Sink;
+use crate::source::Source;
+
+// Flow files are deserialized into the following definitions, which reject unknown
+// fields, then converted into the runtime structures. serde_yaml errors carry the path
+// and the position of the faulty node, for example:
+// `jobs[0]: unknown field `wait_timout`, expected one of ... at line 5 column 5`.
+//
+// Flows can be split across files:
+//
+// ```yaml
+// name: flow1
+// include:
+//   - common/kafka.yaml      # variables, job_templates, sources, jobs and sinks
+// vars_files:
+//   - vars/prod.yaml         # variables, possibly encrypted with `flowrunner vars encrypt`
+// jobs:
+//   - template: pg_insert    # job template defined in common/kafka.yaml
+//     vars:
+//       table: events        # used in the template tasks as {{ context.vars.table }}
+//   - tasks:
+//     - import_tasks: common/notify.yaml   # list of tasks
+// ```
+//
+// Included, imported and variable files are resolved relative to the flow directory.
+// Variables of variable files override the ones of the file referencing them.
+
+/// Definition of a flow as written in a flow file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct FlowDef {
+    name: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    include: Vec<String>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    vars_files: Vec<String>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    variables: Map<String, Value>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    job_templates: BTreeMap<String, JobDef>,
+    #[serde(default)]
+    kind: Kind,
+    #[serde(default)]
+    schedule: String,
+    #[serde(default)]
+    datastore: Option<DatastoreDef>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    sources: Vec<SourceDef>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    jobs: Vec<JobDef>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    sinks: Vec<SinkDef>,
+    #[serde(default)]
+    remote_plugin_dir: String,
+    #[serde(default)]
+    remote_exec_dir: String,
+    #[serde(default)]
+    inventory_file: String,
+    #[serde(default)]
+    is_on_remote: bool,
+    #[serde(default)]
+    timeout: Option<u64>,
+    #[serde(default)]
+    channel_capacity: Option<usize>,
+    #[serde(default)]
+    checkpoint: Option<CheckpointConfig>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    secrets: BTreeMap<String, SecretDef>,
+}
+
+/// Content of an included file: building blocks shared between flows
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IncludeDef {
+    #[serde(default, deserialize_with = "null_as_default")]
+    include: Vec<String>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    vars_files: Vec<String>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    variables: Map<String, Value>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    job_templates: BTreeMap<String, JobDef>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    sources: Vec<SourceDef>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    jobs: Vec<JobDef>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    sinks: Vec<SinkDef>,
+}
+
+// Datastore configuration checked while it is deserialized, so that errors have
+// the position of the datastore in the flow file
+#[derive(Debug)]
+struct DatastoreDef(StoreConfig);
+
+impl<'de> Deserialize<'de> for DatastoreDef {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct DatastoreVisitor;
+
+        impl<'de> Visitor<'de> for DatastoreVisitor {
+            type Value = DatastoreDef;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a datastore with kind, conn_str and namespaces")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<DatastoreDef, A::Error> {
+                let sc = StoreConfig::deserialize(MapAccessDeserializer::new(map))?;
+
+                if sc.kind.is_empty() {
+                    return Err(de::Error::custom("datastore.kind must not be empty!"));
+                }
+
+                if sc.conn_str.is_empty() {
+                    return Err(de::Error::custom("datastore.conn_str must not be empty!"));
+                }
+
+                if sc.namespaces.is_empty() {
+                    return Err(de::Error::custom("datastore.namespaces must not be empty"));
+                }
+
+                if sc.namespaces.iter().any(|ns| ns.name.is_empty()) {
+                    return Err(de::Error::custom("datastore namespace's name must not be empty!"));
+                }
+
+                Ok(DatastoreDef(sc))
+            }
+        }
+
+        deserializer.deserialize_map(DatastoreVisitor)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SourceDef {
+    #[serde(default)]
+    name: Option<String>,
+    plugin: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    params: Map<String, Value>,
+    #[serde(default)]
+    channel_capacity: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SinkDef {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    r#if: Option<String>,
+    plugin: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    params: Map<String, Value>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    from: Vec<String>,
+    #[serde(default)]
+    channel_capacity: Option<usize>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JobDef {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    template: Option<String>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    vars: Map<String, Value>,
+    #[serde(default)]
+    hosts: Option<String>,
+    #[serde(default)]
+    r#if: Option<String>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    from: Vec<String>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    depends_on: Vec<String>,
+    #[serde(default)]
+    wait_interval: Option<u64>,
+    #[serde(default)]
+    wait_timeout: Option<u64>,
+    #[serde(default)]
+    timeout: Option<u64>,
+    #[serde(default)]
+    concurrency: Option<usize>,
+    #[serde(default)]
+    partition_key: Option<String>,
+    #[serde(default)]
+    channel_capacity: Option<usize>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    tasks: Vec<TaskDef>,
+}
+
+fn default_wait_interval() -> u64 {
+    3000
+}
+
+fn default_wait_timeout() -> u64 {
+    300000
+}
+
+/// Fields of a task besides its plugin
+const TASK_FIELDS: &[&str] = &["name", "if", "loop", "loop_tempo", "loop_concurrency", "fail_fast", "register", "on_success", "on_failure",
+                                "retries", "delay", "backoff", "jitter", "until", "timeout"];
+
+/// Fields of a task group besides its block, rescue and always tasks
+const GROUP_FIELDS: &[&str] = &["name", "if", "on_success", "on_failure"];
+
+/// Task definition: the plugin is given by the only key which is not a task field.
+///
+/// ```yaml
+/// - name: task1
+///   builtin-shell:
+///     params:
+///       cmd: ls
+///   on_failure: task3
+/// ```
+///
+/// A task can also be replaced by the tasks of another file with `- import_tasks: file.yaml`,
+/// or be a group of tasks:
+///
+/// ```yaml
+/// - name: deploy
+///   block:
+///     - builtin-git: ...
+///   rescue:
+///     - builtin-shell: ...    # context.failed_task gives the name and error of the failed task
+///   always:
+///     - builtin-shell: ...
+/// ```
+#[derive(Debug, Default, Clone)]
+struct TaskDef {
+    name: Option<String>,
+    r#if: Option<String>,
+    r#loop: Option<Value>,
+    loop_tempo: Option<u64>,
+    loop_concurrency: Option<usize>,
+    fail_fast: bool,
+    register: Map<String, Value>,
+    on_success: String,
+    on_failure: String,
+    retries: u32,
+    delay: Option<u64>,
+    backoff: Option<f64>,
+    jitter: Option<f64>,
+    until: Option<String>,
+    timeout: Option<u64>,
+    plugin: String,
+    params: Map<String, Value>,
+    import_tasks: Option<String>,
+    block: Option<Vec<TaskDef>>,
+    rescue: Vec<TaskDef>,
+    always: Vec<TaskDef>,
+    // File the task is imported from, None when it is defined in the job
+    file: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, expecting = "a plugin with its params or a task field: name, if, loop, loop_tempo, loop_concurrency, fail_fast, register, on_success, on_failure, retries, delay, backoff, jitter, until, timeout")]
+struct PluginDef {
+    #[serde(deserialize_with = "null_as_default")]
+    params: Map<String, Value>,
+}
+
+// Params of the plugin given by a task key. A scalar or a sequence can not be a plugin, so the key
+// is reported as an unknown task field, as a misspelled `on_sucess: task2` would be.
+struct PluginSeed<'a>(&'a str);
+
+impl<'de> DeserializeSeed<'de> for PluginSeed<'_> {
+    type Value = PluginDef;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<PluginDef, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for PluginSeed<'_> {
+    type Value = PluginDef;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a plugin with its params or a task field: {}", TASK_FIELDS.join(", "))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<PluginDef, A::Error> {
+        PluginDef::deserialize(MapAccessDeserializer::new(map))
+    }
+
+    fn visit_bool<E: de::Error>(self, _v: bool) -> Result<PluginDef, E> {
+        Err(de::Error::unknown_field(self.0, TASK_FIELDS))
+    }
+
+    fn visit_i64<E: de::Error>(self, _v: i64) -> Result<PluginDef, E> {
+        Err(de::Error::unknown_field(self.0, TASK_FIELDS))
+    }
+
+    fn visit_u64<E: de::Error>(self, _v: u64) -> Result<PluginDef, E> {
+        Err(de::Error::unknown_field(self.0, TASK_FIELDS))
+    }
+
+    fn visit_f64<E: de::Error>(self, _v: f64) -> Result<PluginDef, E> {
+        Err(de::Error::unknown_field(self.0, TASK_FIELDS))
+    }
+
+    fn visit_str<E: de::Error>(self, _v: &str) -> Result<PluginDef, E> {
+        Err(de::Error::unknown_field(self.0, TASK_FIELDS))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, _seq: A) -> Result<PluginDef, A::Error> {
+        Err(de::Error::unknown_field(self.0, TASK_FIELDS))
+    }
+}
+
+impl<'de> Deserialize<'de> for TaskDef {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct TaskVisitor;
+
+        impl<'de> Visitor<'de> for TaskVisitor {
+            type Value = TaskDef;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a task with a plugin and the optional fields: {}", TASK_FIELDS.join(", "))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<TaskDef, A::Error> {
+                let mut task = TaskDef::default();
+                let mut plugins: Vec<String> = Vec::new();
+                let mut fields: Vec<String> = Vec::new();
+                let mut groups: Vec<String> = Vec::new();
+
+                while let Some(key) = map.next_key::<String>()? {
+                    if TASK_FIELDS.contains(&key.as_str()) {
+                        fields.push(key.clone());
+                    }
+
+                    match key.as_str() {
+                        "name" => task.name = map.next_value()?,
+                        "if" => task.r#if = map.next_value()?,
+                        "loop" => task.r#loop = map.next_value()?,
+                        "loop_tempo" => task.loop_tempo = map.next_value()?,
+                        "loop_concurrency" => task.loop_concurrency = map.next_value()?,
+                        "fail_fast" => task.fail_fast = map.next_value()?,
+                        "register" => task.register = map.next_value::<Option<_>>()?.unwrap_or_default(),
+                        "on_success" => task.on_success = map.next_value()?,
+                        "on_failure" => task.on_failure = map.next_value()?,
+                        "retries" => task.retries = map.next_value()?,
+                        "delay" => task.delay = map.next_value()?,
+                        "backoff" => task.backoff = map.next_value()?,
+                        "jitter" => task.jitter = map.next_value()?,
+                        "until" => task.until = map.next_value()?,
+                        "timeout" => task.timeout = map.next_value()?,
+                        "import_tasks" => task.import_tasks = Some(map.next_value()?),
+                        "block" => {
+                            task.block = Some(map.next_value::<Option<_>>()?.unwrap_or_default());
+                            groups.push(key);
+                        },
+                        "rescue" => {
+                            task.rescue = map.next_value::<Option<_>>()?.unwrap_or_default();
+                            groups.push(key);
+                        },
+                        "always" => {
+                            task.always = map.next_value::<Option<_>>()?.unwrap_or_default();
+                            groups.push(key);
+                        },
+                        _ => {
+                            let plugin = map.next_value_seed(PluginSeed(&key))?;
+                            task.params = plugin.params;
+                            plugins.push(key);
+                        },
+                    }
+                }
+
+                if task.import_tasks.is_some() {
+                    fields.extend(plugins);
+                    fields.extend(groups);
+
+                    if !fields.is_empty() {
+                        return Err(de::Error::custom(format!("import_tasks can not be used with other task fields, found: {}", fields.join(", "))));
+                    }
+
+                    return Ok(task);
+                }
+
+                if !groups.is_empty() {
+                    if !plugins.is_empty() {
+                        return Err(de::Error::custom(format!("task group can not specify a plugin, found: {}", plugins.join(", "))));
+                    }
+
+                    let invalid: Vec<String> = fields.into_iter().filter(|f| !GROUP_FIELDS.contains(&f.as_str())).collect();
+                    if !invalid.is_empty() {
+                        return Err(de::Error::custom(format!("task group can only specify the fields {}, found: {}", GROUP_FIELDS.join(", "), invalid.join(", "))));
+                    }
+
+                    if task.block.as_ref().map(|b| b.is_empty()).unwrap_or(true) {
+                        return Err(de::Error::custom("task group must specify a block with at least one task"));
+                    }
+
+                    return Ok(task);
+                }
+
+                match plugins.len() {
+                    0 => Err(de::Error::custom("task must specify a plugin with its params")),
+                    1 => {
+                        task.plugin = plugins.remove(0);
+                        Ok(task)
+                    },
+                    _ => Err(de::Error::custom(format!("task must specify only one plugin, found: {}", plugins.join(", ")))),
+                }
+            }
+        }
+
+        deserializer.deserialize_map(TaskVisitor)
+    }
+}
+
+// Empty values (`key:`) are handled as if the key was absent
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + Default,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// Loads included files and imported tasks relative to the flow directory.
+///
+/// Files being loaded are kept in a stack, so that cycles between files are reported
+/// instead of looping forever.
+pub(crate) struct Loader {
+    base_dir: PathBuf,
+    stack: Vec<PathBuf>,
+    // Set when another file, or a job template, has been used to build the flow
+    composed: bool,
+}
+
+impl Loader {
+    /// Creates a loader resolving paths relative to `base_dir`. `file` is the flow
+    /// file itself, if any, so that including it again is detected as a cycle.
+    pub(crate) fn new(base_dir: &Path, file: Option<&Path>) -> Self {
+        Loader {
+            base_dir: base_dir.to_path_buf(),
+            stack: file.and_then(|f| f.canonicalize().ok()).into_iter().collect(),
+            composed: false,
+        }
+    }
+
+    /// Returns true if the flow uses includes, task imports or job templates
+    pub(crate) fn is_composed(&self) -> bool {
+        self.composed
+    }
+
+    // Reads the definition in the file, then resolves it while the file is in the stack
+    fn load<T, R, F>(&mut self, file: &str, resolve: F) -> Result<R>
+    where
+        T: DeserializeOwned,
+        F: FnOnce(&mut Self, T) -> Result<R>,
+    {
+        let path = self.base_dir.join(file);
+        let canonical = path.canonicalize()
+            .map_err(|e| anyhow!("Cannot read the file {}: {}", path.display(), e))?;
+
+        if let Some(pos) = self.stack.iter().position(|p| *p == canonical) {
+            let chain: Vec<String> = self.stack[pos..].iter()
+                .chain(std::iter::once(&canonical))
+                .map(|p| p.display().to_string())
+                .collect();
+
+            return Err(anyhow!("Include cycle detected: {}", chain.join(" -> ")));
+        }
+
+        let content = std::fs::read_to_string(&canonical)
+            .map_err(|e| anyhow!("Cannot read the file {}: {}", path.display(), e))?;
+        let def: T = serde_yaml::from_str(&content).map_err(|e| anyhow!("{}: {}", file, e))?;
+
+        self.composed = true;
+        self.stack.push(canonical);
+        let res = resolve(self, def).map_err(|e| anyhow!("{}: {}", file, e));
+        self.stack.pop();
+
+        res
+    }
+}
+
+impl IncludeDef {
+    // Returns the definition with the content of its included files, which comes first
+    fn resolve(mut self, loader: &mut Loader) -> Result<IncludeDef> {
+        let mut resolved = IncludeDef::default();
+
+        for file in std::mem::take(&mut self.include) {
+            let included = loader.load(&file, |l, d: IncludeDef| d.resolve(l))?;
+            resolved.append(included)?;
+        }
+
+        let vars_files = std::mem::take(&mut self.vars_files);
+        resolved.append(self)?;
+
+        for file in vars_files {
+            let variables = read_vars_file(&loader.base_dir.join(file))?;
+            resolved.variables.extend(variables);
+        }
+
+        Ok(resolved)
+    }
+
+    // Variables of `other` override the existing ones, job templates must be unique
+    fn append(&mut self, other: IncludeDef) -> Result<()> {
+        self.variables.extend(other.variables);
+
+        for (name, template) in other.job_templates {
+            if self.job_templates.contains_key(&name) {
+                return Err(anyhow!("Job template {} is defined more than once", name));
+            }
+
+            self.job_templates.insert(name, template);
+        }
+
+        self.sources.extend(other.sources);
+        self.jobs.extend(other.jobs);
+        self.sinks.extend(other.sinks);
+
+        Ok(())
+    }
+}
+
+impl FlowDef {
+    /// Converts the definition into a flow. Dependencies between jobs are not checked.
+    pub(crate) fn into_flow(self, loader: &mut Loader) -> Result<Flow> {
+        let parts = IncludeDef {
+            include: self.include,
+            vars_files: self.vars_files,
+            variables: self.variables,
+            job_templates: self.job_templates,
+            sources: self.sources,
+            jobs: self.jobs,
+            sinks: self.sinks,
+        }.resolve(loader)?;
+
+        if let Some(c) = &self.checkpoint {
+            let declared = self.datastore.as_ref()
+                .map(|d| d.0.namespaces.iter().any(|ns| ns.name == c.namespace))
+                .unwrap_or_default();
+
+            if !declared {
+                return Err(anyhow!("checkpoint.namespace {} must be declared in datastore.namespaces", c.namespace));
+            }
+        }
+
+        for (name, s) in self.secrets.iter() {
+            s.validate().map_err(|e| anyhow!("secrets.{}: {}", name, e))?;
+        }
+
+        check_capacity(self.channel_capacity)?;
+
+        let mut flow = Flow {
+            name: self.name,
+            variables: parts.variables,
+            secrets: self.secrets,
+            kind: self.kind,
+            schedule: self.schedule,
+            datastore: self.datastore.map(|d| d.0),
+            remote_plugin_dir: self.remote_plugin_dir,
+            remote_exec_dir: self.remote_exec_dir,
+            inventory_file: self.inventory_file,
+            is_on_remote: self.is_on_remote,
+            timeout: self.timeout,
+            channel_capacity: self.channel_capacity,
+            checkpoint: self.checkpoint,
+            job_parallel: true,
+            ..Default::default()
+        };
+
+        for (i, s) in parts.sources.into_iter().enumerate() {
+            if s.plugin.is_empty() {
+                return Err(anyhow!("sources[{}]: Plugin name can not be empty!", i));
+            }
+
+            check_capacity(s.channel_capacity).map_err(|e| anyhow!("sources[{}]: {}", i, e))?;
+
+            flow.sources.push(Source {
+                name: s.name.unwrap_or_else(|| format!("src-{}", i + 1)),
+                plugin: s.plugin,
+                params: s.params,
+                channel_capacity: s.channel_capacity,
+                ..Default::default()
+            });
+        }
+
+        let mut job_names: HashSet<String> = HashSet::new();
+
+        for (i, j) in parts.jobs.into_iter().enumerate() {
+            let job = j.instantiate(&parts.job_templates, loader)
+                .and_then(|j| j.into_job(i, loader))
+                .map_err(|e| anyhow!("jobs[{}]: {}", i, e))?;
+
+            // Jobs can come from included files, their results are stored by name
+            if !job_names.insert(job.name.clone()) {
+                return Err(anyhow!("jobs[{}]: Job {} is defined more than once", i, job.name));
+            }
+
+            if !job.depends_on.is_empty() {
+                warn!("{} has dependent jobs: {:?}. Sequential mode is enabled", job.name, job.depends_on);
+                flow.job_parallel = false;
+            }
+
+            flow.jobs.push(job);
+        }
+
+        for (i, s) in parts.sinks.into_iter().enumerate() {
+            if s.plugin.is_empty() {
+                return Err(anyhow!("sinks[{}]: Plugin name can not be empty!", i));
+            }
+
+            check_capacity(s.channel_capacity).map_err(|e| anyhow!("sinks[{}]: {}", i, e))?;
+
+            flow.sinks.push(Sink {
+                name: s.name.unwrap_or_else(|| format!("sink-{}", i + 1)),
+                r#if: s.r#if,
+                plugin: s.plugin,
+                params: s.params,
+                from: s.from,
+                channel_capacity: s.channel_capacity,
+                ..Default::default()
+            });
+        }
+
+        Ok(flow)
+    }
+}
+
+impl JobDef {
+    // Builds the job from its template, if any. Fields set on the job override the
+    // template ones, and its vars are merged into the template vars.
+    fn instantiate(self, templates: &BTreeMap<String, JobDef>, loader: &mut Loader) -> Result<JobDef> {
+        let name = match &self.template {
+            Some(n) => n,
+            None => return Ok(self),
+        };
+
+        let template = templates.get(name)
+            .ok_or_else(|| anyhow!("Job template {} is not found", name))?;
+
+        if template.template.is_some() {
+            return Err(anyhow!("Job template {} can not use another template", name));
+        }
+
+        if template.name.is_some() {
+            return Err(anyhow!("Job template {} can not have a name, it is given by the job", name));
+        }
+
+        if !self.tasks.is_empty() {
+            return Err(anyhow!("A job using the template {} can not define tasks", name));
+        }
+
+        loader.composed = true;
+
+        let mut vars = template.vars.clone();
+        vars.extend(self.vars);
+
+        Ok(JobDef {
+            name: self.name,
+            template: None,
+            vars,
+            hosts: self.hosts.or_else(|| template.hosts.clone()),
+            r#if: self.r#if.or_else(|| template.r#if.clone()),
+            from: if self.from.is_empty() { template.from.clone() } else { self.from },
+            depends_on: if self.depends_on.is_empty() { template.depends_on.clone() } else { self.depends_on },
+            wait_interval: self.wait_interval.or(template.wait_interval),
+            wait_timeout: self.wait_timeout.or(template.wait_timeout),
+            timeout: self.timeout.or(template.timeout),
+            concurrency: self.concurrency.or(template.concurrency),
+            partition_key: self.partition_key.or_else(|| template.partition_key.clone()),
+            channel_capacity: self.channel_capacity.or(template.channel_capacity),
+            tasks: template.tasks.clone(),
+        })
+    }
+
+    fn into_job(self, index: usize, loader: &mut Loader) -> Result<Job> {
+        let name = self.name.unwrap_or_else(|| format!("job-{}", index + 1));
+
+        if self.concurrency == Some(0) {
+            return Err(anyhow!("concurrency must be greater than 0"));
+        }
+
+        check_capacity(self.channel_capacity)?;
+        let tasks = into_tasks(expand_tasks(self.tasks, loader)?, "tasks", None, &mut HashMap::new())?;
+
+        let mut context = Map::new();
+        if !self.vars.is_empty() {
+            context.insert("vars".to_string(), Value::Object(self.vars));
+        }
+
+        Ok(Job {
+            name,
+            hosts: self.hosts.unwrap_or_else(|| "localhost".to_string()),
+            r#if: self.r#if,
+            from: self.from,
+            depends_on: self.depends_on,
+            wait_interval: self.wait_interval.unwrap_or_else(default_wait_interval),
+            wait_timeout: self.wait_timeout.unwrap_or_else(default_wait_timeout),
+            timeout: self.timeout,
+            concurrency: self.concurrency,
+            partition_key: self.partition_key,
+            channel_capacity: self.channel_capacity,
+            tasks,
+            context,
+            ..Default::default()
+        })
+    }
+}
+
+fn check_capacity(capacity: Option<usize>) -> Result<()> {
+    if capacity == Some(0) {
+        return Err(anyhow!("channel_capacity must be greater than 0"));
+    }
+
+    Ok(())
+}
+
+// Replaces `import_tasks` entries by the tasks of the imported files
+fn expand_tasks(tasks: Vec<TaskDef>, loader: &mut Loader) -> Result<Vec<TaskDef>> {
+    let mut expanded: Vec<TaskDef> = Vec::new();
+
+    for mut t in tasks {
+        match &t.import_tasks {
+            Some(file) => {
+                let mut imported = loader.load(file, |l, d: Vec<TaskDef>| expand_tasks(d, l))?;
+                set_file(&mut imported, file);
+                expanded.extend(imported);
+            },
+            None => {
+                if let Some(block) = t.block.take() {
+                    t.block = Some(expand_tasks(block, loader)?);
+                }
+                t.rescue = expand_tasks(std::mem::take(&mut t.rescue), loader)?;
+                t.always = expand_tasks(std::mem::take(&mut t.always), loader)?;
+
+                expanded.push(t);
+            },
+        }
+    }
+
+    Ok(expanded)
+}
+
+// Sets the file of the imported tasks and of their groups, unless they come from a
+// file imported by this one
+fn set_file(tasks: &mut [TaskDef], file: &str) {
+    for t in tasks.iter_mut().filter(|t| t.file.is_none()) {
+        t.file = Some(file.to_string());
+
+        if let Some(block) = t.block.as_mut() {
+            set_file(block, file);
+        }
+        set_file(&mut t.rescue, file);
+        set_file(&mut t.always, file);
+    }
+}
+
+// Converts a list of task definitions. Default names of tasks in groups are prefixed by
+// the group name and section, e.g. `task-2-rescue-1`. Task names must be unique in the
+// job, including its groups: `names` keeps the file of each name already used.
+fn into_tasks(defs: Vec<TaskDef>, path: &str, group: Option<&str>, names: &mut HashMap<String, Option<String>>) -> Result<Vec<Task>> {
+    let mut tasks: Vec<Task> = Vec::new();
+
+    for (i, t) in defs.into_iter().enumerate() {
+        let name = t.name.unwrap_or_else(|| match group {
+            Some(g) => format!("{}-{}-{}", g, path.rsplit('.').next().unwrap_or_default(), i + 1),
+            None => format!("task-{}", i + 1),
+        });
+
+        if let Some(file) = names.insert(name.clone(), t.file.clone()) {
+            let origin = |f: &Option<String>| f.clone().unwrap_or_else(|| "the job".to_string());
+
+            return Err(anyhow!("{}[{}]: Task {} is defined more than once, in {} and in {}", path, i, name, origin(&file), origin(&t.file)));
+        }
+
+        let (block, rescue, always) = match t.block {
+            Some(block) => (
+                into_tasks(block, &format!("{}[{}].block", path, i), Some(&name), names)?,
+                into_tasks(t.rescue, &format!("{}[{}].rescue", path, i), Some(&name), names)?,
+                into_tasks(t.always, &format!("{}[{}].always", path, i), Some(&name), names)?,
+            ),
+            None => {
+                if t.plugin.is_empty() {
+                    return Err(anyhow!("{}[{}]: Plugin name can not be empty!", path, i));
+                }
+
+                (Vec::new(), Vec::new(), Vec::new())
+            },
+        };
+
+        let task = Task {
+            name,
+            r#if: t.r#if,
+            plugin: t.plugin,
+            params: t.params,
+            r#loop: t.r#loop,
+            loop_tempo: t.loop_tempo,
+            loop_concurrency: t.loop_concurrency,
+            fail_fast: t.fail_fast,
+            register: t.register,
+            on_success: t.on_success,
+            on_failure: t.on_failure,
+            retries: t.retries,
+            delay: t.delay,
+            backoff: t.backoff,
+            jitter: t.jitter,
+            until: t.until,
+            timeout: t.timeout,
+            block,
+            rescue,
+            always,
+        };
+
+        // Set on_success for the previous task to this one
+        if let Some(prev) = tasks.last_mut() {
+            if prev.on_success.is_empty() {
+                prev.on_success = task.name.clone();
+            }
+        }
+
+        tasks.push(task);
+    }
+
+    Ok(tasks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{secret, vault};
+
+    #[test]
+    fn test_flow_def_errors() {
+        let content = r#"
+name: flow1
+jobs:
+  - name: job1
+    wait_timout: 10
+"#;
+        let err = serde_yaml::from_str::<FlowDef>(content).unwrap_err().to_string();
+        assert!(err.starts_with("jobs[0]: unknown field `wait_timout`"), "{}", err);
+        assert!(err.ends_with("at line 5 column 5"), "{}", err);
+
+        let content = r#"
+name: flow1
+jobs:
+  - name: job1
+    tasks:
+    - builtin-shell:
+        params:
+          cmd: ls
+      on_sucess: task2
+"#;
+        let err = serde_yaml::from_str::<FlowDef>(content).unwrap_err().to_string();
+        assert!(err.starts_with("jobs[0].tasks[0].on_sucess: unknown field `on_sucess`, expected one of `name`, `if`"), "{}", err);
+        assert!(err.ends_with("at line 9 column 18"), "{}", err);
+
+        let content = r#"
+name: flow1
+jobs:
+  - tasks:
+    - name: task1
+"#;
+        let err = serde_yaml::from_str::<FlowDef>(content).unwrap_err().to_string();
+        assert!(err.starts_with("jobs[0].tasks[0]: task must specify a plugin with its params"), "{}", err);
+
+        let content = r#"
+name: flow1
+datastore:
+  kind: rocksdb
+  conn_str: /tmp/rocksdb
+"#;
+        let err = serde_yaml::from_str::<FlowDef>(content).unwrap_err().to_string();
+        assert!(err.starts_with("datastore: datastore.namespaces must not be empty"), "{}", err);
+
+        let content = r#"
+name: flow1
+secrets:
+  pg_password:
+    provider: vault
+    key: pg
+"#;
+        let err = serde_yaml::from_str::<FlowDef>(content).unwrap().into_flow(&mut Loader::new(Path::new("."), None)).unwrap_err().to_string();
+        assert_eq!("secrets.pg_password: provider vault is not supported, expected: env, file or keyfile", err);
+
+        let content = r#"
+name: flow1
+kind: stream
+jobs:
+  - name: job1
+    concurrency: 0
+sinks:
+  - plugin: builtin-shell
+    channel_capacity: 0
+"#;
+        let err = serde_yaml::from_str::<FlowDef>(content).unwrap().into_flow(&mut Loader::new(Path::new("."), None)).unwrap_err().to_string();
+        assert_eq!("jobs[0]: concurrency must be greater than 0", err);
+
+        let content = content.replace("concurrency: 0", "concurrency: 4");
+        let err = serde_yaml::from_str::<FlowDef>(&content).unwrap().into_flow(&mut Loader::new(Path::new("."), None)).unwrap_err().to_string();
+        assert_eq!("sinks[0]: channel_capacity must be greater than 0", err);
+    }
+
+    #[test]
+    fn test_flow_def_includes() {
+        let dir = std::env::temp_dir().join("flowrunner-test-flow-def-includes");
+        std::fs::create_dir_all(dir.join("common")).unwrap();
+
+        std::fs::write(dir.join("common/base.yaml"), r#"
+variables:
+  brokers: localhost:9092
+  topic: base
+job_templates:
+  pg_insert:
+    hosts: db
+    vars:
+      table: default
+    tasks:
+      - import_tasks: common/tasks.yaml
+"#).unwrap();
+        std::fs::write(dir.join("common/tasks.yaml"), r#"
+- name: insert
+  builtin-shell:
+    params:
+      cmd: "insert {{ context.vars.table }}"
+- name: notify
+  builtin-shell:
+    params:
+      cmd: echo done
+"#).unwrap();
+
+        let content = r#"
+name: flow1
+include:
+  - common/base.yaml
+variables:
+  topic: events
+jobs:
+  - name: job1
+    template: pg_insert
+    vars:
+      table: events
+  - tasks:
+    - name: task1
+      builtin-shell:
+        params:
+          cmd: ls
+    - import_tasks: common/tasks.yaml
+"#;
+        let mut loader = Loader::new(&dir, None);
+        let flow = serde_yaml::from_str::<FlowDef>(content).unwrap().into_flow(&mut loader).unwrap();
+
+        assert!(loader.is_composed());
+        assert_eq!(Some(&Value::String("localhost:9092".to_string())), flow.variables.get("brokers"));
+        assert_eq!(Some(&Value::String("events".to_string())), flow.variables.get("topic"));
+
+        assert_eq!("job1", flow.jobs[0].name);
+        assert_eq!("db", flow.jobs[0].hosts);
+        assert_eq!(Some(&serde_json::json!({ "table": "events" })), flow.jobs[0].context.get("vars"));
+        assert_eq!(vec!["insert", "notify"], flow.jobs[0].tasks.iter().map(|t| t.name.as_str()).collect::<Vec<&str>>());
+
+        assert_eq!("job-2", flow.jobs[1].name);
+        assert_eq!("localhost", flow.jobs[1].hosts);
+        assert_eq!(vec!["task1", "insert", "notify"], flow.jobs[1].tasks.iter().map(|t| t.name.as_str()).collect::<Vec<&str>>());
+        assert_eq!("insert", flow.jobs[1].tasks[0].on_success);
+
+        // Cycles between files
+        std::fs::write(dir.join("common/a.yaml"), "include: [common/b.yaml]").unwrap();
+        std::fs::write(dir.join("common/b.yaml"), "include: [common/a.yaml]").unwrap();
+
+        let content = "name: flow1\ninclude: [common/a.yaml]";
+        let err = serde_yaml::from_str::<FlowDef>(content).unwrap().into_flow(&mut Loader::new(&dir, None)).unwrap_err().to_string();
+        let d = dir.canonicalize().unwrap().display().to_string();
+        assert!(err.ends_with(&format!("Include cycle detected: {d}/common/a.yaml -> {d}/common/b.yaml -> {d}/common/a.yaml", d = d)), "{}", err);
+
+        // Unknown template and template with tasks
+        let content = "name: flow1\njobs:\n  - template: unknown";
+        let err = serde_yaml::from_str::<FlowDef>(content).unwrap().into_flow(&mut Loader::new(&dir, None)).unwrap_err().to_string();
+        assert_eq!("jobs[0]: Job template unknown is not found", err);
+
+        let content = r#"
+name: flow1
+jobs:
+  - tasks:
+    - import_tasks: common/tasks.yaml
+      name: task1
+"#;
+        let err = serde_yaml::from_str::<FlowDef>(content).unwrap_err().to_string();
+        assert!(err.starts_with("jobs[0].tasks[0]: import_tasks can not be used with other task fields, found: name"), "{}", err);
+
+        // Duplicate task names would make on_success jump back to the first task
+        let content = r#"
+name: flow1
+jobs:
+  - tasks:
+    - import_tasks: common/tasks.yaml
+    - import_tasks: common/tasks.yaml
+"#;
+        let err = serde_yaml::from_str::<FlowDef>(content).unwrap().into_flow(&mut Loader::new(&dir, None)).unwrap_err().to_string();
+        assert_eq!("jobs[0]: tasks[2]: Task insert is defined more than once, in common/tasks.yaml and in common/tasks.yaml", err);
+
+        let content = r#"
+name: flow1
+jobs:
+  - tasks:
+    - name: group1
+      block:
+      - name: notify
+        builtin-shell:
+          params:
+            cmd: ls
+    - import_tasks: common/tasks.yaml
+"#;
+        let err = serde_yaml::from_str::<FlowDef>(content).unwrap().into_flow(&mut Loader::new(&dir, None)).unwrap_err().to_string();
+        assert_eq!("jobs[0]: tasks[2]: Task notify is defined more than once, in the job and in common/tasks.yaml", err);
+
+        // Duplicate job names, the second one coming from an included file
+        std::fs::write(dir.join("common/jobs.yaml"), r#"
+jobs:
+  - name: job1
+    tasks:
+      - import_tasks: common/tasks.yaml
+"#).unwrap();
+
+        let content = r#"
+name: flow1
+include:
+  - common/jobs.yaml
+jobs:
+  - name: job1
+    tasks:
+      - builtin-shell:
+          params:
+            cmd: ls
+"#;
+        let err = serde_yaml::from_str::<FlowDef>(content).unwrap().into_flow(&mut Loader::new(&dir, None)).unwrap_err().to_string();
+        assert_eq!("jobs[1]: Job job1 is defined more than once", err);
+    }
+
+    #[test]
+    fn test_vars_files() {
+        let dir = std::env::temp_dir().join("flowrunner-test-vars-files");
+        std::fs::create_dir_all(dir.join("vars")).unwrap();
+
+        let _env = crate::utils::lock_env();
+        std::env::set_var(vault::PASSWORD_ENV, "vault-pass");
+
+        std::fs::write(dir.join("vars/common.yaml"), "env: prod\npg_user: flowrunner").unwrap();
+        std::fs::write(dir.join("vars/prod.yaml"), vault::encrypt(b"pg_password: vars-secret-1\npg_db: app", "vault-pass").unwrap()).unwrap();
+
+        let content = r#"
+name: flow1
+vars_files:
+  - vars/common.yaml
+  - vars/prod.yaml
+variables:
+  env: dev
+  pg_password: unset
+"#;
+        let flow = serde_yaml::from_str::<FlowDef>(content).unwrap().into_flow(&mut Loader::new(&dir, None)).unwrap();
+
+        assert_eq!(Some(&Value::String("prod".to_string())), flow.variables.get("env"));
+        assert_eq!(Some(&Value::String("flowrunner".to_string())), flow.variables.get("pg_user"));
+        assert_eq!(Some(&Value::String("vars-secret-1".to_string())), flow.variables.get("pg_password"));
+        assert_eq!(format!("password={}", secret::MASK), secret::mask("password=vars-secret-1"));
+        // Short values are not masked, they are rarely secrets
+        assert_eq!("db=app", secret::mask("db=app"));
+
+        std::fs::write(dir.join("vars/prod.yaml"), vault::encrypt(b"pg_password: vars-secret-1", "other-pass").unwrap()).unwrap();
+
+        let err = serde_yaml::from_str::<FlowDef>(content).unwrap().into_flow(&mut Loader::new(&dir, None)).unwrap_err().to_string();
+        assert_eq!(format!("{}: Cannot decrypt the content: the password is wrong or the content is corrupted", dir.join("vars/prod.yaml").display()), err);
+    }
+
+    #[test]
+    fn test_task_groups() {
+        let content = r#"
+name: flow1
+jobs:
+  - tasks:
+    - block:
+      - builtin-shell:
+          params:
+            cmd: hello
+      - builtin-shell:
+          params:
+            cmd: ls
+      rescue:
+      - builtin-shell:
+          params:
+            cmd: "echo {{ context.failed_task.error }}"
+    - builtin-shell:
+        params:
+          cmd: ls
+"#;
+        let flow = serde_yaml::from_str::<FlowDef>(content).unwrap().into_flow(&mut Loader::new(Path::new("."), None)).unwrap();
+        let tasks = &flow.jobs[0].tasks;
+
+        assert!(tasks[0].is_group());
+        assert_eq!("task-1", tasks[0].name);
+        assert_eq!("task-2", tasks[0].on_success);
+        assert_eq!(vec!["task-1-block-1", "task-1-block-2"], tasks[0].block.iter().map(|t| t.name.as_str()).collect::<Vec<&str>>());
+        assert_eq!("task-1-block-2", tasks[0].block[0].on_success);
+        assert_eq!("task-1-rescue-1", tasks[0].rescue[0].name);
+        assert!(tasks[0].always.is_empty());
+
+        let content = r#"
+name: flow1
+jobs:
+  - tasks:
+    - block:
+      - builtin-shell:
+          params:
+            cmd: ls
+      retries: 3
+"#;
+        let err = serde_yaml::from_str::<FlowDef>(content).unwrap_err().to_string();
+        assert!(err.starts_with("jobs[0].tasks[0]: task group can only specify the fields name, if, on_success, on_failure, found: retries"), "{}", err);
+
+        let content = "name: flow1\njobs:\n  - tasks:\n    - rescue: []\n";
+        let err = serde_yaml::from_str::<FlowDef>(content).unwrap_err().to_string();
+        assert!(err.starts_with("jobs[0].tasks[0]: task group must specify a block with at least one task"), "{}", err);
+    }
+}
@@ -1,11 +1,19 @@
+use std::path::Path;
+
 use clap::ArgMatches;
 
 use anyhow::{anyhow, Result};
+use chrono::Utc;
+use serde_json::{Map, Value};
 use tokio::signal;
-use log::{error, info};
+use log::{debug, error, info};
 
+use crate::checkpoint::RunState;
 use crate::config::Config;
 use crate::flow::{Flow, Kind};
+use crate::history::{History, Trigger};
+use crate::secret::{mask, mask_value};
+use crate::validate::validate_flow_str;
 
 pub async fn exec_cmd(config: &Config, matches: &ArgMatches<'_>) -> Result<()> {
 
@@ -14,7 +22,9 @@ pub async fn exec_cmd(config: &Config, matches: &ArgMatches<'_>) -> Result<()> {
         None => return Err(anyhow!("You must specify the flow file in the specified flow directory (--flow-dir)")),
     };
 
-    let mut flow = Flow::new_from_file(&(config.runner.flow_dir.as_str().to_owned() + "/" + file))?;
+    let mut flow = Flow::new_from_flow_dir(&config.runner.flow_dir, file)?;
+    flow.check = matches.is_present("check");
+    apply_overlays(config, &mut flow, matches)?;
 
     let kind = flow.kind;
 
@@ -38,12 +48,294 @@ pub async fn exec_cmd(config: &Config, matches: &ArgMatches<'_>) -> Result<()> {
     }
 
     // flow == action
-    match flow.run().await {
-        Ok(()) => {
-            info!("Flow: {}", serde_json::to_string_pretty(&flow).unwrap_or_else(|_| "Cannot to serialize flow to string".to_string()));
+    // Runs in check mode change nothing and are not recorded
+    let history = if flow.check { None } else { open_history(config)? };
+    let started_at = Utc::now();
+
+    let result = flow.run().await;
+    if let Some(h) = &history {
+        h.record_flow(&flow, Trigger::Exec, &started_at, &result);
+    }
+    result?;
+
+    if flow.check {
+        println!("{}", format_check_report(&flow));
+    }
+
+    report_flow(&flow)
+}
+
+/// Resumes a failed run of an action flow. Without flow file, the run is searched in
+/// the flows of the flow directory which have a checkpoint.
+pub async fn resume_cmd(config: &Config, matches: &ArgMatches<'_>) -> Result<()> {
+    let run_id = match matches.value_of("run-id") {
+        Some(r) => r,
+        None => return Err(anyhow!("You must specify the identifier of the run to resume")),
+    };
+
+    let mut flow = match matches.value_of("flow-file") {
+        Some(f) => Flow::new_from_flow_dir(&config.runner.flow_dir, f)?,
+        None => find_flow_by_run(config, run_id)?,
+    };
+
+    let history = open_history(config)?;
+    let started_at = Utc::now();
+
+    let result = flow.resume(run_id).await;
+    if let Some(h) = &history {
+        h.record_flow(&flow, Trigger::Resume, &started_at, &result);
+    }
+    result?;
+
+    report_flow(&flow)
+}
+
+/// Applies the variables and the payload given on the command line. Variables override
+/// the ones of the flow with the following precedence, from the lowest to the highest:
+/// - variables of the flow, its included files and its `vars_files`
+/// - `--env ENV`: variables of the file `variables.ENV.yaml` of the flow directory
+/// - `--vars-file FILE`: variables of the files, in the order of the command line
+/// - `--var KEY=VALUE`: values parsed as YAML, in the order of the command line
+///
+/// Strings of these variables are expanded with the environment variables `${VAR}`.
+/// Variable files can be encrypted with `flowrunner vars encrypt`.
+fn apply_overlays(config: &Config, flow: &mut Flow, matches: &ArgMatches<'_>) -> Result<()> {
+    if let Some(env) = matches.value_of("env") {
+        let path = Path::new(&config.runner.flow_dir).join(format!("variables.{}.yaml", env));
+        flow.override_variables_file(&path)?;
+    }
+
+    for file in matches.values_of("vars-file").into_iter().flatten() {
+        flow.override_variables_file(Path::new(file))?;
+    }
+
+    let mut vars = Map::new();
+    for var in matches.values_of("var").into_iter().flatten() {
+        let (k, v) = parse_var(var)?;
+        vars.insert(k, v);
+    }
+    flow.override_variables(vars);
+
+    if let Some(payload) = matches.value_of("payload") {
+        flow.user_payload = parse_payload(payload)?;
+    }
+
+    Ok(())
+}
+
+// Parses `KEY=VALUE`. The value is parsed as YAML so that `n=3` gives a number,
+// and is a string when it is not valid YAML.
+fn parse_var(var: &str) -> Result<(String, Value)> {
+    let (key, value) = match var.split_once('=') {
+        Some((k, v)) if !k.is_empty() => (k, v),
+        _ => return Err(anyhow!("Variable {} must be written KEY=VALUE", var)),
+    };
+
+    let value = match value {
+        "" => Value::String(String::new()),
+        v => serde_yaml::from_str(v).unwrap_or_else(|_| Value::String(v.to_string())),
+    };
+
+    Ok((key.to_string(), value))
+}
+
+// Parses the payload given as JSON, or read from a JSON file with `@FILE`
+fn parse_payload(payload: &str) -> Result<Value> {
+    match payload.strip_prefix('@') {
+        Some(file) => {
+            let content = std::fs::read_to_string(file)
+                .map_err(|e| anyhow!("Cannot read the payload file {}: {}", file, e))?;
+
+            serde_json::from_str(&content).map_err(|e| anyhow!("Payload file {} is not valid JSON: {}", file, e))
         },
-        Err(e) => error!("{e}"),
+        None => serde_json::from_str(payload).map_err(|e| anyhow!("Payload is not valid JSON: {}", e)),
+    }
+}
+
+/// Opens the run history when it is configured in the runner configuration
+pub fn open_history(config: &Config) -> Result<Option<History>> {
+    config.runner.history.as_ref().map(History::new).transpose()
+}
+
+fn report_flow(flow: &Flow) -> Result<()> {
+    let value = serde_json::to_value(flow).map(|v| mask_value(&v));
+    info!("Flow: {}", value.and_then(|v| serde_json::to_string_pretty(&v)).unwrap_or_else(|_| "Cannot to serialize flow to string".to_string()));
+
+    let failed_jobs = flow.get_failed_jobs();
+    if !failed_jobs.is_empty() {
+        if !flow.run_id.is_empty() {
+            return Err(anyhow!("Flow {} failed: jobs={:?}, run_id={}", flow.name, failed_jobs, flow.run_id));
+        }
+
+        return Err(anyhow!("Flow {} failed: jobs={:?}", flow.name, failed_jobs));
     }
 
     Ok(())
 }
+
+// Formats what each task would change in check mode, with the diffs returned by plugins
+fn format_check_report(flow: &Flow) -> String {
+    let mut lines: Vec<String> = Vec::new();
+
+    for j in flow.jobs.iter() {
+        lines.push(format!("{}:", j.name));
+
+        for (name, res) in j.result.iter() {
+            // Loop tasks have one result per item
+            let items = match res.get("output") {
+                Some(Value::Array(a)) => a.clone(),
+                _ => vec![res.clone()],
+            };
+
+            for (i, item) in items.iter().enumerate() {
+                let name = if items.len() > 1 { format!("{}[{}]", name, i) } else { name.clone() };
+                let error = item.get("error").and_then(|e| e.as_str()).unwrap_or_default();
+                let output = item.get("output").cloned().unwrap_or_default();
+
+                if let Some(s) = item.get("skipped").and_then(|s| s.as_str()) {
+                    lines.push(format!("  {}: {}", name, s));
+                } else if !error.is_empty() {
+                    lines.push(format!("  {}: failed: {}", name, error));
+                } else if output.get("changed").and_then(|c| c.as_bool()).unwrap_or_default() {
+                    lines.push(format!("  {}: changed", name));
+
+                    let diff = output.get("diff").and_then(|d| d.as_str()).unwrap_or_default();
+                    lines.extend(diff.lines().map(|l| format!("    {}", l)));
+                } else {
+                    lines.push(format!("  {}: unchanged", name));
+                }
+            }
+        }
+    }
+
+    mask(&lines.join("\n"))
+}
+
+// Returns the flow of the flow directory whose checkpoint contains the run
+fn find_flow_by_run(config: &Config, run_id: &str) -> Result<Flow> {
+    let paths = std::fs::read_dir(&config.runner.flow_dir)
+        .map_err(|e| anyhow!("Cannot read files in the directory {}: {}", config.runner.flow_dir, e))?;
+
+    for path in paths {
+        let path = path?.path();
+
+        if !path.is_file() {
+            continue;
+        }
+
+        let file = match path.file_name().and_then(|f| f.to_str()) {
+            Some(f) => f,
+            None => continue,
+        };
+
+        let flow = match Flow::new_from_flow_dir(&config.runner.flow_dir, file) {
+            Ok(f) => f,
+            Err(e) => {
+                debug!("Ignoring the flow file {}: {}", file, e);
+                continue;
+            },
+        };
+
+        if let (Some(c), Some(d)) = (&flow.checkpoint, &flow.datastore) {
+            if RunState::load(d, &c.namespace, run_id).is_ok() {
+                return Ok(flow);
+            }
+        }
+    }
+
+    Err(anyhow!("Run {} is not found in the flows of the directory {}", run_id, config.runner.flow_dir))
+}
+
+pub fn validate_cmd(config: &Config, matches: &ArgMatches<'_>) -> Result<()> {
+    let file = match matches.value_of("flow-file") {
+        Some(f) => f,
+        None => return Err(anyhow!("You must specify the flow file in the specified flow directory (--flow-dir)")),
+    };
+
+    let flow_dir = Path::new(&config.runner.flow_dir);
+    let path = flow_dir.join(file);
+    let content = std::fs::read_to_string(&path)
+        .map_err(|e| anyhow!("Cannot read the flow file {}: {}", path.display(), e))?;
+
+    let errors = validate_flow_str(&content, flow_dir);
+    for e in errors.iter() {
+        println!("{}:{}", path.display(), e);
+    }
+
+    if !errors.is_empty() {
+        return Err(anyhow!("Flow file {} has {} error(s)", path.display(), errors.len()));
+    }
+
+    println!("Flow file {} is valid", path.display());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    use flowrunner::job::Job;
+
+    #[test]
+    fn test_overlays() {
+        assert_eq!(("n".to_string(), json!(3)), parse_var("n=3").unwrap());
+        assert_eq!(("enabled".to_string(), json!(true)), parse_var("enabled=true").unwrap());
+        assert_eq!(("url".to_string(), json!("http://host:80/?a=b")), parse_var("url=http://host:80/?a=b").unwrap());
+        assert_eq!(("list".to_string(), json!(["a", "b"])), parse_var("list=[a, b]").unwrap());
+        assert_eq!(("empty".to_string(), json!("")), parse_var("empty=").unwrap());
+        assert_eq!("Variable novalue must be written KEY=VALUE", parse_var("novalue").unwrap_err().to_string());
+
+        let file = std::env::temp_dir().join("flowrunner-test-payload.json");
+        std::fs::write(&file, r#"{"id": 1}"#).unwrap();
+
+        assert_eq!(json!({ "id": 1 }), parse_payload(&format!("@{}", file.display())).unwrap());
+        assert_eq!(json!({ "id": 2 }), parse_payload(r#"{"id": 2}"#).unwrap());
+        assert!(parse_payload("{id").is_err());
+
+        let mut flow = Flow::default();
+        flow.variables.insert("env".to_string(), json!("dev"));
+        flow.variables.insert("user".to_string(), json!("flowrunner"));
+
+        // The variable is not set: its default value shows that the strings are expanded
+        let mut vars = Map::new();
+        vars.insert("env".to_string(), json!("prod"));
+        vars.insert("host".to_string(), json!("${FLOWRUNNER_TEST_OVERLAY_HOST:db1}:5432"));
+        flow.override_variables(vars);
+
+        assert_eq!(Some(&json!("prod")), flow.variables.get("env"));
+        assert_eq!(Some(&json!("flowrunner")), flow.variables.get("user"));
+        assert_eq!(Some(&json!("db1:5432")), flow.variables.get("host"));
+    }
+
+    #[test]
+    fn test_format_check_report() {
+        let mut job = Job::default();
+        job.name = "job1".to_string();
+        job.result.insert("task1".to_string(), json!({
+            "status": "Ok", "error": "", "output": { "changed": true, "diff": "-a\n+b" },
+        }));
+        job.result.insert("task2".to_string(), json!({
+            "status": "Ok", "error": "", "output": {}, "skipped": "skipped in check mode",
+        }));
+        job.result.insert("task3".to_string(), json!({
+            "status": "Ok", "error": "", "output": [
+                { "status": "Ok", "error": "", "output": { "changed": false, "diff": "" } },
+                { "status": "Ko", "error": "file does not exist", "output": {} },
+            ],
+        }));
+
+        let flow = Flow { jobs: vec![job], ..Default::default() };
+
+        let expected = r#"job1:
+  task1: changed
+    -a
+    +b
+  task2: skipped in check mode
+  task3[0]: unchanged
+  task3[1]: failed: file does not exist"#;
+
+        assert_eq!(expected, format_check_report(&flow));
+    }
+}
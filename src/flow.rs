@@ -1,35 +1,47 @@
 use log::*;
-use std::fs::File;
 
 use serde::{Deserialize, Serialize};
-use serde_yaml::{Mapping, Value as yamlValue};
-use serde_json::Map;
+use serde_json::{Map, json};
 use serde_json::Value as jsonValue;
 
 use anyhow::{anyhow, Result};
 
 use async_channel::*;
 use tokio::sync::*;
-use tokio::time::Duration;
+use tokio::time::{Duration, Instant, timeout_at};
+use futures::future::{abortable, AbortHandle};
+use futures::stream::{FuturesUnordered, StreamExt};
 
-use crate::datastore::store::StoreNamespace;
 use crate::{
-    job::{Task, Job},
-    utils,
+    job::{Task, Job, Status as JobStatus, find_resume_task},
     source::Source,
     sink::Sink,
 };
 
 use moka::future::Cache;
 use std::sync::{Arc, Mutex};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
 
 use crate::message::Message as FlowMessage;
 use crate::datastore::store::StoreConfig;
+use crate::remote::{RemoteConfig, exec_job_remote};
+use crate::definition::{FlowDef, Loader};
+use crate::checkpoint::{Checkpoint, CheckpointConfig, JobState, RunState, RunStatus, VarsOverlay};
+use crate::secret::{self, SecretDef};
+use crate::vault;
+use crate::template::TemplateCache;
+use crate::condition::Condition;
+use crate::utils::{expand_env_map, generate_uuid};
 
 #[derive(Clone, Serialize, Deserialize, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum Kind {
+    // Flow files use lowercase kinds
+    #[serde(alias = "action")]
     Action,
+    #[serde(alias = "stream")]
     Stream,
+    #[serde(alias = "cron")]
     Cron,
 }
 
@@ -48,8 +60,14 @@ pub struct Flow {
     pub name: String,
     #[serde(default)]
     pub variables:  Map<String, jsonValue>,
+    // Variables given when the flow is launched, saved in the run state
+    #[serde(skip_serializing, skip_deserializing)]
+    pub overlays: Vec<VarsOverlay>,
     #[serde(default)]
     pub user_payload:  jsonValue,
+    // Secrets readable by templates with `secret(name="...")`
+    #[serde(default)]
+    pub secrets: BTreeMap<String, SecretDef>,
 
     #[serde(default)]
     pub kind: Kind,
@@ -60,7 +78,7 @@ pub struct Flow {
     pub datastore: Option<StoreConfig>,
 
     #[serde(default = "default_parallel")]
-    job_parallel: bool,
+    pub(crate) job_parallel: bool,
 
     #[serde(default)]
     pub sources: Vec<Source>,
@@ -82,8 +100,34 @@ pub struct Flow {
 	// even if it is local
     #[serde(default)]
 	pub is_on_remote: bool,
+
+    // Run state saved in the datastore to resume failed runs
+    #[serde(default)]
+    pub checkpoint: Option<CheckpointConfig>,
+    // Identifier of the current run of an action or cron flow
+    #[serde(default)]
+    pub run_id: String,
+    // Check mode: plugins only report what tasks would change
+    #[serde(default)]
+    pub check: bool,
+
+    // Maximum duration in ms of an action or cron flow
+    #[serde(default)]
+    pub timeout: Option<u64>,
+
+    // Default capacity of the channels of a stream flow, which can be set for each
+    // source, job and sink receiving messages
+    #[serde(default)]
+    pub channel_capacity: Option<usize>,
+
+    // Templates of the flow compiled when it is loaded
+    #[serde(skip_serializing, skip_deserializing)]
+    pub templates: Option<Arc<TemplateCache>>,
 }
 
+/// Capacity of the channels of stream flows when it is not configured
+pub const DEFAULT_CHANNEL_CAPACITY: usize = 1024;
+
 fn default_parallel() -> bool {
     true
 }
@@ -107,18 +151,84 @@ fn default_parallel() -> bool {
 //}
 
 impl Flow {
+    /// Reads a flow file. Errors are prefixed by the file name and give the position
+    /// of the faulty element, for example:
+    /// `flow.yaml: jobs[0]: unknown field `wait_timout`, expected one of ... at line 5 column 5`.
+    ///
+    /// Included files, imported tasks and variable files are resolved relative to the directory
+    /// of the file.
     pub fn new_from_file(file: &str) -> Result<Flow>{
-        let f = File::open(file).unwrap();
-        let mapping = serde_yaml::from_reader(f).unwrap();
+        let path = Path::new(file);
+        let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+
+        Flow::read(path, base_dir)
+    }
+
+    /// Reads the flow file `file` of the flow directory. Included files, imported tasks and
+    /// variable files are resolved relative to the flow directory.
+    pub fn new_from_flow_dir(flow_dir: &str, file: &str) -> Result<Flow> {
+        let base_dir = Path::new(flow_dir);
 
-        parse(mapping)
+        Flow::read(&base_dir.join(file), base_dir)
     }
 
     #[allow(dead_code)]
     pub fn new_from_str(content: &str) -> Result<Flow> {
-        let mapping: Mapping = serde_yaml::from_str(content).unwrap();
+        parse(content, &mut Loader::new(Path::new("."), None))
+    }
+
+    fn read(path: &Path, base_dir: &Path) -> Result<Flow> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Cannot read the flow file {}: {}", path.display(), e))?;
+
+        parse(&content, &mut Loader::new(base_dir, Some(path))).map_err(|e| anyhow!("{}: {}", path.display(), e))
+    }
+
+    /// Compiles the templates of the sources, jobs and sinks in a cache shared by them,
+    /// so that they are not parsed again for each message or run.
+    pub fn compile_templates(&mut self) -> Result<()> {
+        let templates = TemplateCache::new(&self.secrets, self.datastore.as_ref());
+
+        templates.compile(&serde_json::to_value(&self.sources)?)?;
+        templates.compile(&serde_json::to_value(&self.jobs)?)?;
+        templates.compile(&serde_json::to_value(&self.sinks)?)?;
+
+        // Invalid conditions are reported by check_conditions
+        for (_, cond) in conditions(self) {
+            let _ = templates.compile_condition(cond);
+        }
+
+        self.templates = Some(Arc::new(templates));
+
+        Ok(())
+    }
+
+    /// Overrides the variables of the flow with variables given when it is launched.
+    /// Their strings are expanded with the environment variables `${VAR}`, like task params.
+    pub fn override_variables(&mut self, mut variables: Map<String, jsonValue>) {
+        expand_env_map(&mut variables);
+
+        self.overlays.push(VarsOverlay::Variables(variables.clone()));
+        self.variables.extend(variables);
+    }
+
+    /// Overrides the variables of the flow with the variables of a file, which can be
+    /// encrypted. Only the path of an encrypted file is kept in the saved run state.
+    pub fn override_variables_file(&mut self, path: &Path) -> Result<()> {
+        let (mut variables, encrypted) = read_vars(path)?;
+
+        if !encrypted {
+            self.override_variables(variables);
+            return Ok(());
+        }
+
+        expand_env_map(&mut variables);
 
-        parse(mapping)
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.overlays.push(VarsOverlay::EncryptedFile(path.display().to_string()));
+        self.variables.extend(variables);
+
+        Ok(())
     }
 
     pub async fn run(&mut self) -> Result<()> {
@@ -134,6 +244,10 @@ impl Flow {
                     return Err(anyhow!("At least one source must be specified when using flow stream"));
                 }
 
+                if self.check {
+                    return Err(anyhow!("Check mode is only supported by action and cron flows"));
+                }
+
                 // Init a cache for job results in sequential mode
                 let cache = if !self.job_parallel {
                     Some(Cache::<String, Arc<Mutex<Map<String, jsonValue>>>>::builder()
@@ -144,19 +258,27 @@ impl Flow {
                     None
                 };
 
-                // Create boundeds from sources to jobs according to
-                // the number of jobs. Each job will receive messages from all sources
+                let default_capacity = self.channel_capacity.unwrap_or(DEFAULT_CHANNEL_CAPACITY);
+
+                // Create boundeds from sources to jobs according to the number of jobs.
+                // Each job receives messages from the sources given in `from`, or from all
+                // sources if it is empty.
                 let jobs = self.jobs.clone();
                 for (i, mut job) in jobs.into_iter().enumerate() {
                     // Set job cache
                     job.cache = cache.clone();
 
-                    let (rx_src_job, tx_src_job) = bounded::<FlowMessage>(1024);
+                    let capacity = job.channel_capacity.unwrap_or(default_capacity);
+                    job.channel_capacity = Some(capacity);
+
+                    let (rx_src_job, tx_src_job) = bounded::<FlowMessage>(capacity);
                     job.tx.push(tx_src_job);
 
                     // Report global flow settings in context
                     job.context.insert("variables".to_string(), jsonValue::from(self.variables.clone()));
                     job.context.insert("user_payload".to_string(), self.user_payload.clone());
+                    job.context.insert("secrets".to_string(), serde_json::to_value(&self.secrets)?);
+                    job.templates = self.templates.clone();
 
                     self.jobs[i] = job;
 
@@ -165,7 +287,11 @@ impl Flow {
                         // Report global flow settings in context
                         src.context.insert("variables".to_string(), jsonValue::from(self.variables.clone()));
                         src.context.insert("user_payload".to_string(), self.user_payload.clone());
-                        src.rx.push(rx_src_job.clone());
+                        src.context.insert("secrets".to_string(), serde_json::to_value(&self.secrets)?);
+                        src.templates = self.templates.clone();
+                        if self.jobs[i].receives_from(&src.name) {
+                            src.rx.push(rx_src_job.clone());
+                        }
                         self.sources[j] = src;
                     }
                 }
@@ -173,18 +299,22 @@ impl Flow {
                 // Prepare messaging channels between jobs & sinks
                 let sinks = self.sinks.clone();
                 for (i, mut sink) in sinks.into_iter().enumerate() {
-                    let (rx_job_sink, tx_job_sink) = bounded::<FlowMessage>(1024);
+                    let (rx_job_sink, tx_job_sink) = bounded::<FlowMessage>(sink.channel_capacity.unwrap_or(default_capacity));
                     sink.tx.push(tx_job_sink);
 
                     // Report global flow settings in context
                     sink.context.insert("variables".to_string(), jsonValue::from(self.variables.clone()));
                     sink.context.insert("user_payload".to_string(), self.user_payload.clone());
+                    sink.context.insert("secrets".to_string(), serde_json::to_value(&self.secrets)?);
+                    sink.templates = self.templates.clone();
 
                     self.sinks[i] = sink;
 
                     let jobs = self.jobs.clone();
                     for (j, mut job) in jobs.into_iter().enumerate() {
-                        job.rx.push(rx_job_sink.clone());
+                        if self.sinks[i].receives_from(&job.name) {
+                            job.rx.push(rx_job_sink.clone());
+                        }
                         self.jobs[j] = job;
                     }
                 }
@@ -194,18 +324,19 @@ impl Flow {
                 // such as http-server, we need set the same job's receiver for source and sink.
                 let srcs = self.sources.clone();
                 for (i, mut src) in srcs.into_iter().enumerate() {
-                    if src.params.get("is_also_sink")
-                        .and_then(|v| v.as_bool())
-                        .unwrap_or_default() {
+                    if src.is_also_sink() {
 
-                        let (rx_job_src, tx_job_src) = bounded::<FlowMessage>(1024);
+                        let (rx_job_src, tx_job_src) = bounded::<FlowMessage>(src.channel_capacity.unwrap_or(default_capacity));
                         src.tx.push(tx_job_src);
 
                         self.sources[i] = src;
 
+                        // Jobs reply to the sources they receive messages from
                         let jobs = self.jobs.clone();
                         for (j, mut job) in jobs.into_iter().enumerate() {
-                            job.rx.push(rx_job_src.clone());
+                            if job.receives_from(&self.sources[i].name) {
+                                job.rx.push(rx_job_src.clone());
+                            }
                             self.jobs[j] = job;
                         }
                     }
@@ -226,20 +357,113 @@ impl Flow {
                     info!("Flow kind: Action");
                 }
 
-                let jobs = self.jobs.clone();
-                for (i, mut job) in jobs.into_iter().enumerate() {
-                    // Report global flow settings in job context
-                    job.context.insert("variables".to_string(), jsonValue::from(self.variables.clone()));
-                    job.context.insert("user_payload".to_string(), self.user_payload.clone());
+                self.run_jobs(None).await?;
+            },
+        }
 
-                    self.jobs[i] = job;
-                }
+        Ok(())
+    }
 
-                self.launch_job_threads().await;
+    /// Resumes a run of an action or cron flow saved with `checkpoint`. Jobs which succeeded
+    /// are not executed again. Failed jobs restart from their failed task with their results
+    /// and registered variables. The payload and the variables given to the resumed run are
+    /// applied again, encrypted variable files being read again.
+    pub async fn resume(&mut self, run_id: &str) -> Result<()> {
+        let (checkpoint, datastore) = match (&self.checkpoint, &self.datastore) {
+            (Some(c), Some(d)) => (c, d),
+            _ => return Err(anyhow!("Flow {} has no checkpoint configured", self.name)),
+        };
+
+        if self.kind == Kind::Stream {
+            return Err(anyhow!("Only action and cron flows can be resumed"));
+        }
+
+        let state = RunState::load(datastore, &checkpoint.namespace, run_id)?;
+
+        if state.flow != self.name {
+            return Err(anyhow!("Run {} belongs to the flow {}", run_id, state.flow));
+        }
+
+        if state.status == RunStatus::Succeeded {
+            return Err(anyhow!("Run {} already succeeded", run_id));
+        }
+
+        info!("Resuming the flow run: flow={}, run_id={}", self.name, run_id);
+
+        self.run_id = state.run_id.clone();
+        self.user_payload = state.user_payload.clone();
+
+        for overlay in state.overlays.iter() {
+            match overlay {
+                VarsOverlay::Variables(v) => {
+                    self.overlays.push(overlay.clone());
+                    self.variables.extend(v.clone());
+                },
+                VarsOverlay::EncryptedFile(f) => self.override_variables_file(Path::new(f))?,
+            }
+        }
+
+        self.run_jobs(Some(state)).await
+    }
+
+    // Runs the jobs of an action or cron flow with a new run identifier. When a checkpoint is
+    // configured, the run state is saved after each job, starting from `state` when the run
+    // is resumed.
+    async fn run_jobs(&mut self, state: Option<RunState>) -> Result<()> {
+        let jobs = self.jobs.clone();
+        for (i, mut job) in jobs.into_iter().enumerate() {
+            // Report global flow settings in job context
+            job.context.insert("variables".to_string(), jsonValue::from(self.variables.clone()));
+            job.context.insert("user_payload".to_string(), self.user_payload.clone());
+            job.context.insert("secrets".to_string(), serde_json::to_value(&self.secrets)?);
+            job.templates = self.templates.clone();
+            job.check = self.check;
+
+            self.jobs[i] = job;
+        }
+
+        // A resumed run keeps its identifier
+        if state.is_none() {
+            self.run_id = generate_uuid();
+        }
+
+        info!("Flow run started: flow={}, run_id={}", self.name, self.run_id);
+
+        // Runs in check mode change nothing, so there is nothing to resume
+        let mut checkpoint = match (&self.checkpoint, &self.datastore) {
+            (Some(c), Some(d)) if !self.check => {
+                let state = state.unwrap_or_else(|| RunState::new(&self.run_id, &self.name, &self.overlays, &self.user_payload));
+
+                let mut cp = Checkpoint::new(d, &c.namespace, state);
+                cp.state.status = RunStatus::Running;
+                cp.save();
+
+                Some(cp)
             },
+            _ => None,
+        };
+
+        let resumed = checkpoint.as_ref().map(|c| c.state.jobs.clone()).unwrap_or_default();
+
+        // Jobs are executed following their dependency graph
+        let remote = RemoteConfig::new(&self.inventory_file, &self.remote_plugin_dir, &self.remote_exec_dir)?;
+
+        let res = run_job_graph(&mut self.jobs, self.datastore.clone(), remote, resumed, checkpoint.as_mut(), self.timeout).await;
+
+        // The run is also finished when the flow times out, its cancelled jobs being Ko
+        if let Some(cp) = checkpoint.as_mut() {
+            cp.finish(if res.is_ok() && self.get_failed_jobs().is_empty() { RunStatus::Succeeded } else { RunStatus::Failed });
         }
 
-        Ok(())
+        res.map_err(|e| anyhow!("Flow {}: {}", self.name, e))
+    }
+
+    /// Returns the names of jobs that ended with the status `Ko`
+    pub fn get_failed_jobs(&self) -> Vec<String> {
+        self.jobs.iter()
+            .filter(|j| j.status == JobStatus::Ko)
+            .map(|j| j.name.clone())
+            .collect()
     }
 
     async fn launch_source_threads(&self) {
@@ -328,452 +552,462 @@ async fn run_all_jobs(
     for (i, j) in jobs.iter().enumerate() {
         info!("Executing job {}", j.name);
 
-        exec_job(&mut js, i, datastore.clone()).await?;
+        exec_job(&mut js[i], datastore.clone(), None).await?;
     }
 
     Ok(js.to_vec())
 }
 
-async fn exec_job(
-    jobs: &mut [Job],
-    idx: usize,
-    datastore: Option<StoreConfig>
+/// Runs jobs of an action or cron flow following their dependency graph.
+///
+/// A job is launched as soon as all jobs listed in its `depends_on` are finished, so
+/// independent branches run concurrently. Before running, the status and result of
+/// all its upstream jobs (direct and transitive) are set in `context.job_results`.
+///
+/// When the flow `timeout` (in ms) is reached, the running jobs are cancelled, which kills
+/// the commands they started, and are recorded as `Ko`.
+async fn run_job_graph(
+    js: &mut [Job],
+    datastore: Option<StoreConfig>,
+    remote: RemoteConfig,
+    resumed: BTreeMap<String, JobState>,
+    mut checkpoint: Option<&mut Checkpoint>,
+    timeout: Option<u64>,
 ) -> Result<()> {
-    let mut job = jobs[idx].clone();
+    let upstreams = get_upstream_jobs(js);
+    let deadline = timeout.map(|t| Instant::now() + Duration::from_millis(t));
+
+    let mut pending: Vec<usize> = (0..js.len()).collect();
+    let mut job_results: Map<String, jsonValue> = Map::new();
+    let mut running = FuturesUnordered::new();
+    // Running jobs can be cancelled with their abort handle
+    let mut aborts: BTreeMap<usize, AbortHandle> = BTreeMap::new();
+
+    loop {
+        // Launch all jobs whose dependencies are satisfied
+        let (ready, waiting): (Vec<usize>, Vec<usize>) = pending.into_iter()
+            .partition(|i| js[*i].depends_on.iter().all(|d| job_results.contains_key(d)));
+        pending = waiting;
+
+        // Jobs which succeeded in the resumed run are not executed again
+        let mut skipped = false;
+
+        for i in ready {
+            if let Some(state) = resumed.get(&js[i].name).filter(|s| s.status == JobStatus::Ok) {
+                info!("Job already succeeded in the resumed run: job={}", js[i].name);
+
+                js[i].status = state.status;
+                js[i].result = state.result.clone();
+                job_results.insert(js[i].name.clone(), json!({
+                    "status": js[i].status,
+                    "result": js[i].result
+                }));
+
+                skipped = true;
+                continue;
+            }
 
-    if job.hosts.is_empty() || job.hosts == "localhost" || job.hosts == "127.0.0.1" {
-        if let Err(e) = exec_job_local(&mut job, datastore.clone()).await {
-            error!("exec_job_local: {e}");
-        }
+            let mut job = js[i].clone();
 
-        jobs[idx] = job.clone();
+            // Failed jobs restart from their failed task with the same results and registered variables
+            if let Some(state) = resumed.get(&job.name) {
+                job.result = state.result.clone();
 
-        debug!("Job: {:?}", jobs[idx]);
+                if !state.register.is_null() {
+                    job.context.insert("register".to_string(), state.register.clone());
+                }
 
-        return Ok(());
-    }
+                if let Some(name) = state.failed_task.as_ref() {
+                    match find_resume_task(&job.tasks, name) {
+                        Some(t) => job.start = Some(t),
+                        None => {
+                            // Restarting from the first task could run completed tasks again
+                            error!("Failed task of the resumed run is not found: job={}, task={}", job.name, name);
+
+                            js[i].status = JobStatus::Ko;
+                            js[i].result = job.result.clone();
+                            js[i].failed_task = Some(name.clone());
+                            job_results.insert(js[i].name.clone(), json!({
+                                "status": js[i].status,
+                                "result": js[i].result
+                            }));
+
+                            if let Some(cp) = checkpoint.as_mut() {
+                                cp.job_done(&js[i]);
+                            }
 
-    if let Err(e) = exec_job_remote(&mut job) {
-        error!("exec_job_remote: {e}");
-    }
+                            skipped = true;
+                            continue;
+                        },
+                    }
+                }
+            }
 
-    jobs[idx] = job;
+            if !job.depends_on.is_empty() {
+                let results: Map<String, jsonValue> = job_results.clone().into_iter()
+                    .filter(|(k, _)| upstreams.get(&job.name).map(|u| u.contains(k)).unwrap_or(false))
+                    .collect();
 
-    Ok(())
-}
+                job.context.insert("job_results".to_string(), jsonValue::Object(results));
+            }
 
-async fn exec_job_local(
-    job: &mut Job,
-    datastore: Option<StoreConfig>
-) -> Result<()> {
-    info!("Executing locally the job {}", job.name);
+            info!("Executing job: job={}, depends_on={:?}", job.name, job.depends_on);
+
+            let datastore_cloned = datastore.clone();
+            let remote_cloned = remote.clone();
+            let (exec, abort) = abortable(async move {
+                if let Err(e) = exec_job(&mut job, datastore_cloned, Some(remote_cloned)).await {
+                    error!("Failed to execute job: job={}, err={e}", job.name);
+                }
+
+                job
+            });
+
+            aborts.insert(i, abort);
+            running.push(async move {
+                let handle = tokio::spawn(exec);
 
-    let mut job_cloned = job.clone();
-    let datastore_cloned = datastore.clone();
-    tokio::spawn(async move {
-        match job_cloned.run(None, datastore_cloned).await {
-            Ok(()) => (),
-            Err(e) => error!("{}", e.to_string()),
+                (i, handle.await)
+            });
         }
-    });
 
-    Ok(())
-}
+        // Skipped jobs may satisfy the dependencies of pending jobs
+        if skipped {
+            continue;
+        }
 
-fn exec_job_remote(job: &mut Job) -> Result<()> {
-    info!("Executing remotely the job {}", job.name);
+        let next = match deadline {
+            Some(d) => match timeout_at(d, running.next()).await {
+                Ok(next) => next,
+                Err(_) => {
+                    let mut names: Vec<String> = Vec::new();
 
-    Ok(())
-}
+                    for (i, abort) in aborts.iter() {
+                        abort.abort();
 
-fn parse(mapping: Mapping) -> Result<Flow> {
-    let mut flow = Flow::default();
-    flow.job_parallel = true;
+                        error!("Job cancelled by the flow timeout: job={}", js[*i].name);
+                        js[*i].status = JobStatus::Ko;
+                        names.push(js[*i].name.clone());
 
-    if let Some(s) = mapping.get(&yamlValue::String("name".to_string())) {
-        if let Some(v) = s.as_str() {
-            flow.name = v.to_string();
-        }
-    } else {
-        return Err(anyhow!("Flow name must be specified!"))
-    }
+                        if let Some(cp) = checkpoint.as_mut() {
+                            cp.job_done(&js[*i]);
+                        }
+                    }
 
-    if let Some(s) = mapping.get(&yamlValue::String("kind".to_string())) {
-        if let Some(v) = s.as_str() {
-            if v == "stream" {
-                flow.kind = Kind::Stream;
-            }
+                    return Err(anyhow!("timed out after {}ms, cancelled jobs: {:?}", timeout.unwrap_or_default(), names));
+                },
+            },
+            None => running.next().await,
+        };
+
+        match next {
+            Some((i, res)) => {
+                aborts.remove(&i);
+
+                match res {
+                    Ok(Ok(job)) => js[i] = job,
+                    Ok(Err(e)) => {
+                        error!("Job cancelled: job={}, err={e}", js[i].name);
+                        js[i].status = JobStatus::Ko;
+                    },
+                    Err(e) => {
+                        error!("Job task aborted: job={}, err={e}", js[i].name);
+                        js[i].status = JobStatus::Ko;
+                    },
+                }
+
+                job_results.insert(js[i].name.clone(), json!({
+                    "status": js[i].status,
+                    "result": js[i].result
+                }));
+
+                if let Some(cp) = checkpoint.as_mut() {
+                    cp.job_done(&js[i]);
+                }
+            },
+            None => break,
         }
     }
 
-    if let Some(k) = mapping.get(&yamlValue::String("kind".to_string()))
-        .and_then(|s| s.as_str())
-        .and_then(|v| {
-            match v {
-                "stream" => Some(Kind::Stream),
-                "cron" => Some(Kind::Cron),
-                _ => Some(Kind::Action),
-            }
-        }) {
-        flow.kind = k;
+    if !pending.is_empty() {
+        let names: Vec<String> = pending.iter().map(|i| js[*i].name.clone()).collect();
+        return Err(anyhow!("jobs cannot be scheduled because of unsatisfied dependencies: jobs={:?}", names));
     }
 
-    if let Some(s) = mapping.get(&yamlValue::String("schedule".to_string()))
-        .and_then(|s| s.as_str()) {
-        flow.schedule = s.to_string();
-    }
+    Ok(())
+}
 
-    if let Some(variables) = mapping.get(&yamlValue::String("variables".to_string())) {
-        if let Some(vars) = variables.as_mapping() {
-            for (k, v) in vars.iter() {
-                let key = k.as_str().ok_or_else(|| anyhow!("Error parsing variables' key"))?;
-                let val = utils::convert_value_yaml_to_json(v)?;
-                flow.variables.insert(key.to_string(), val);
-            }
-        }
-    }
+async fn exec_job(
+    job: &mut Job,
+    datastore: Option<StoreConfig>,
+    remote: Option<RemoteConfig>
+) -> Result<()> {
+    // Expose inventory variables to templates. `context.host` is overridden by
+    // each remote host when the job is executed remotely.
+    let hostvars = remote.as_ref()
+        .map(|r| r.inventory.get_all_host_vars())
+        .unwrap_or_default();
 
-    // Parsing Datastore
-    if let Some(ds) = mapping.get(&yamlValue::String("datastore".to_string())) {
-        if let Some(m) = ds.as_mapping() {
-            let mut sc = StoreConfig::default();
+    job.context.insert("host".to_string(), jsonValue::String("localhost".to_string()));
+    job.context.insert("hostvars".to_string(), jsonValue::Object(hostvars));
 
-            // Check kind
-            if let Some(v) = m.get(&yamlValue::String("kind".to_string())) {
-                sc.kind = v.as_str().unwrap_or("").to_string();
-            }
+    if job.hosts.is_empty() || job.hosts == "localhost" || job.hosts == "127.0.0.1" {
+        if let Err(e) = exec_job_local(job, datastore.clone()).await {
+            error!("exec_job_local: {e}");
+            job.status = JobStatus::Ko;
+        }
 
-            if sc.kind.is_empty() {
-                return Err(anyhow!("datastore.kind must not be empty!"));
-            }
+        debug!("Job: {:?}", job);
 
-            // Check conn_str
-            if let Some(v) = m.get(&yamlValue::String("conn_str".to_string())) {
-                sc.conn_str = v.as_str().unwrap_or("").to_string();
-            }
+        return Ok(());
+    }
 
-            if sc.conn_str.is_empty() {
-                return Err(anyhow!("datastore.conn_str must not be empty!"));
+    match remote {
+        Some(r) => {
+            if let Err(e) = exec_job_remote(job, &r).await {
+                error!("exec_job_remote: {e}");
+                job.status = JobStatus::Ko;
             }
+        },
+        None => {
+            error!("Remote execution is only supported for action and cron flows: job={}, hosts={}", job.name, job.hosts);
+            job.status = JobStatus::Ko;
+        },
+    }
 
-            // Check options
-            if let Some(v1) = m.get(&yamlValue::String("options".to_string())) {
-                let mut options = Map::new();
+    Ok(())
+}
 
-                if let Some(v2) = v1.as_mapping() {
-                    for (k, v) in v2.iter() {
-                        options.insert(k.as_str().unwrap_or("").to_string(), utils::convert_value_yaml_to_json(v)?);
-                    }
-                }
+async fn exec_job_local(
+    job: &mut Job,
+    datastore: Option<StoreConfig>
+) -> Result<()> {
+    info!("Executing locally the job {}", job.name);
 
-                sc.options = options;
+    // Stream jobs loop forever on their receiver so they are detached
+    if !job.tx.is_empty() {
+        let mut job_cloned = job.clone();
+        let datastore_cloned = datastore.clone();
+        tokio::spawn(async move {
+            match job_cloned.run(None, datastore_cloned).await {
+                Ok(()) => (),
+                Err(e) => error!("{}", e.to_string()),
             }
+        });
 
-            // Check TTL
-            if let Some(v1) = m.get(&yamlValue::String("ttl".to_string())) {
-                sc.ttl = v1.as_u64().unwrap_or(0);
-            }
+        return Ok(());
+    }
 
-            // Check Namespaces
-            if let Some(v1) = m.get(&yamlValue::String("namespaces".to_string())) {
-                if let Some(seq) = v1.as_sequence() {
-                    let mut namespaces: Vec<StoreNamespace> = Vec::new();
+    job.run(None, datastore).await
+}
 
-                    for s in seq.iter() {
-                        let mut ns = StoreNamespace::default();
-                        // Check name
-                        if let Some(v) = s.get(&yamlValue::String("name".to_string())) {
-                            ns.name = v.as_str().unwrap_or("").to_string();
-                        }
+/// Returns for each job the names of all jobs it depends on, directly or transitively.
+fn get_upstream_jobs(jobs: &[Job]) -> HashMap<String, HashSet<String>> {
+    let deps: HashMap<&str, &Vec<String>> = jobs.iter().map(|j| (j.name.as_str(), &j.depends_on)).collect();
+    let mut upstreams: HashMap<String, HashSet<String>> = HashMap::new();
 
-                        if ns.name.is_empty() {
-                            return Err(anyhow!("datastore namespace's name must not be empty!"));
-                        }
+    for j in jobs.iter() {
+        let mut visited: HashSet<String> = HashSet::new();
+        let mut stack: Vec<String> = j.depends_on.clone();
 
-                        // Check prefix_len
-                        if let Some(v) = s.get(&yamlValue::String("prefix_len".to_string())) {
-                            ns.prefix_len = Some(v.as_u64().unwrap_or(0) as usize);
-                        }
+        while let Some(n) = stack.pop() {
+            if visited.insert(n.clone()) {
+                if let Some(d) = deps.get(n.as_str()) {
+                    stack.extend(d.iter().cloned());
+                }
+            }
+        }
 
-                        // Check options
-                        if let Some(v1) = s.get(&yamlValue::String("options".to_string())) {
-                            let mut options = Map::new();
+        upstreams.insert(j.name.clone(), visited);
+    }
 
-                            if let Some(v2) = v1.as_mapping() {
-                                for (k, v) in v2.iter() {
-                                    options.insert(k.as_str().unwrap_or("").to_string(), utils::convert_value_yaml_to_json(v)?);
-                                }
-                            }
+    upstreams
+}
 
-                            ns.options = options;
-                        }
+/// Checks that job dependencies form a directed acyclic graph.
+///
+/// In case of cycle, the error names all jobs in the loop, for example:
+/// `job1 -> job2 -> job1`.
+pub(crate) fn check_job_cycles(jobs: &[Job]) -> Result<()> {
+    let deps: HashMap<&str, &Vec<String>> = jobs.iter().map(|j| (j.name.as_str(), &j.depends_on)).collect();
+    // 1: being visited, 2: visited
+    let mut states: HashMap<&str, u8> = HashMap::new();
+
+    fn visit<'a>(
+        name: &'a str,
+        deps: &HashMap<&'a str, &'a Vec<String>>,
+        states: &mut HashMap<&'a str, u8>,
+        path: &mut Vec<&'a str>,
+    ) -> Result<()> {
+        match states.get(name) {
+            Some(2) => return Ok(()),
+            Some(1) => {
+                let start = path.iter().position(|n| *n == name).unwrap_or(0);
+                let mut cycle: Vec<&str> = path[start..].to_vec();
+                cycle.push(name);
+
+                return Err(anyhow!("Cyclic dependencies detected between jobs: {}", cycle.join(" -> ")));
+            },
+            _ => (),
+        }
 
-                        namespaces.push(ns);
-                    }
+        states.insert(name, 1);
+        path.push(name);
 
-                    sc.namespaces = namespaces;
-                }
+        if let Some(d) = deps.get(name).copied() {
+            for n in d.iter() {
+                visit(n.as_str(), deps, states, path)?;
             }
+        }
 
-            if sc.namespaces.is_empty() {
-                return Err(anyhow!("datastore.namespaces must not be empty"));
-            }
+        path.pop();
+        states.insert(name, 2);
 
-            flow.datastore = Some(sc);
-        }
+        Ok(())
     }
 
-    // Parse SOURCES
-    flow.sources = match mapping.get(&yamlValue::String("sources".to_string())) {
-        Some(srcs) => {
-            match srcs.as_sequence() {
-                Some(seq) => {
-                    let mut sources: Vec<Source> = Vec::new();
-                    let mut src_count = 1;
+    for j in jobs.iter() {
+        let mut path: Vec<&str> = Vec::new();
+        visit(j.name.as_str(), &deps, &mut states, &mut path)?;
+    }
 
-                    for src in seq.iter() {
-                        let mut s = Source::default();
+    Ok(())
+}
 
-                        let default_srcname = "src-".to_owned() + &src_count.to_string();
+/// Reads the variables of a variable file, decrypted with the vault password if it was
+/// encrypted by `flowrunner vars encrypt`. Values of encrypted files are masked in logs
+/// and outputs like secrets.
+pub fn read_vars_file(path: &Path) -> Result<Map<String, jsonValue>> {
+    read_vars(path).map(|(variables, _)| variables)
+}
 
-                        // Check name
-                        if let Some(v) = src.get(yamlValue::String("name".to_string())) {
-                            s.name = v.as_str().unwrap_or(&default_srcname).to_string();
-                        } else {
-                            s.name = default_srcname.clone();
-                        }
+// Returns the variables of the file and whether it is encrypted
+fn read_vars(path: &Path) -> Result<(Map<String, jsonValue>, bool)> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("Cannot read the variable file {}: {}", path.display(), e))?;
 
-                        // Check plugin
-                        if let Some(v) = src.get(yamlValue::String("plugin".to_string())) {
-                            s.plugin = v.as_str().unwrap_or("").to_string();
-                        }
+    if !vault::is_encrypted(&content) {
+        let variables = serde_yaml::from_str(&content).map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+        return Ok((variables, false));
+    }
 
-                        if s.plugin.is_empty() {
-                            return Err(anyhow!("Plugin name can not be empty!"));
-                        }
+    let plain = vault::decrypt(&content, &vault::password()?).map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+    let variables: Map<String, jsonValue> = serde_yaml::from_slice(&plain).map_err(|e| anyhow!("{}: {}", path.display(), e))?;
 
-                        if let Some(v1) = src.get(yamlValue::String("params".to_string())) {
-                            let mut params = Map::new();
+    secret::register_value(&jsonValue::Object(variables.clone()));
 
-                            if let Some(v2) = v1.as_mapping() {
-                                for (k, v) in v2.iter() {
-                                    params.insert(k.as_str().unwrap_or("").to_string(), utils::convert_value_yaml_to_json(v)?);
-                                }
-                            }
+    Ok((variables, true))
+}
 
-                            s.params = params;
-                        }
+/// Checks the routes between the sources, jobs and sinks of a flow: names given in
+/// `from` must exist, every source must send its messages to at least one job and,
+/// when the flow has sinks, every job must send its results to at least one of them or
+/// to a source which is also a sink.
+/// Returns the errors with the JSON pointer of the faulty element.
+pub(crate) fn check_routes(flow: &Flow) -> Vec<(String, String)> {
+    let mut errors: Vec<(String, String)> = Vec::new();
+
+    let routed = flow.jobs.iter().any(|j| !j.from.is_empty()) || flow.sinks.iter().any(|s| !s.from.is_empty());
+    if !routed {
+        return errors;
+    }
 
-                        sources.push(s);
+    if flow.kind != Kind::Stream {
+        let path = match flow.jobs.iter().position(|j| !j.from.is_empty()) {
+            Some(i) => format!("/jobs/{}/from", i),
+            None => format!("/sinks/{}/from", flow.sinks.iter().position(|s| !s.from.is_empty()).unwrap_or_default()),
+        };
+        errors.push((path, "from is only supported by stream flows".to_string()));
 
-                        src_count += src_count;
-                    }
+        return errors;
+    }
 
-                    sources
-                },
-                None => Vec::new(),
-            }
-        },
-        None => Vec::new(),
-    };
+    let source_names: HashSet<&str> = flow.sources.iter().map(|s| s.name.as_str()).collect();
+    let job_names: HashSet<&str> = flow.jobs.iter().map(|j| j.name.as_str()).collect();
 
-    // Parse JOBS
-    flow.jobs = match mapping.get(&yamlValue::String("jobs".to_string())) {
-        Some(js) => {
-            match js.as_sequence() {
-                Some(seq) => {
-                    let mut jobs: Vec<Job> = Vec::new();
-                    let mut job_count = 1;
+    for (i, j) in flow.jobs.iter().enumerate() {
+        for (k, s) in j.from.iter().enumerate() {
+            if !source_names.contains(s.as_str()) {
+                errors.push((format!("/jobs/{}/from/{}", i, k), format!("Job {} receives messages from the unknown source {}", j.name, s)));
+            }
+        }
+    }
 
-                    for job in seq.iter() {
-                        let mut j = Job::default();
+    for (i, s) in flow.sinks.iter().enumerate() {
+        for (k, j) in s.from.iter().enumerate() {
+            if !job_names.contains(j.as_str()) {
+                errors.push((format!("/sinks/{}/from/{}", i, k), format!("Sink {} receives results from the unknown job {}", s.name, j)));
+            }
+        }
+    }
 
-                        let default_jobname = "job-".to_owned() + &job_count.to_string();
+    for (i, s) in flow.sources.iter().enumerate() {
+        if !flow.jobs.iter().any(|j| j.receives_from(&s.name)) {
+            errors.push((format!("/sources/{}", i), format!("Source {} is not connected to any job", s.name)));
+        }
+    }
 
-                        if let Some(v) = job.get(yamlValue::String("name".to_string())) {
-                            j.name = v.as_str().unwrap_or(&default_jobname).to_string();
-                        } else {
-                            j.name = default_jobname.clone();
-                        }
+    if !flow.sinks.is_empty() {
+        for (i, j) in flow.jobs.iter().enumerate() {
+            let connected = flow.sinks.iter().any(|s| s.receives_from(&j.name))
+                || flow.sources.iter().any(|s| s.is_also_sink() && j.receives_from(&s.name));
 
-                        if let Some(v) = job.get(yamlValue::String("hosts".to_string())) {
-                            j.hosts = v.as_str().unwrap_or("localhost").to_string();
-                        } else {
-                            j.hosts = "localhost".to_string();
-                        }
+            if !connected {
+                errors.push((format!("/jobs/{}", i), format!("Job {} is not connected to any sink", j.name)));
+            }
+        }
+    }
 
-                        // Check if condition
-                        let yaml_value_if = yamlValue::String("if".to_string());
-                        if let Some(v) = job.get(yaml_value_if) {
-                            j.r#if = v.as_str().map(|s| s.to_string());
-                        }
+    errors
+}
 
-                        // Check dependent jobs
-                        if let Some(v) = job.get(yamlValue::String("depends_on".to_string())) {
-                            j.depends_on = v.as_sequence()
-                                .and_then(|s| {
-                                    Some(s.iter().map(|v| v.as_str().unwrap_or_default().to_string()).collect())
-                                })
-                                .unwrap_or_default();
-                        }
+/// Checks that the `if` and `until` conditions of the jobs, tasks and sinks of a flow parse.
+/// Returns the errors with the JSON pointer of the faulty condition.
+pub(crate) fn check_conditions(flow: &Flow) -> Vec<(String, String)> {
+    conditions(flow)
+        .into_iter()
+        .filter_map(|(path, cond)| Condition::parse(cond).err().map(|e| (path, e.to_string())))
+        .collect()
+}
 
-                        if !j.depends_on.is_empty() {
-                            warn!("{} has dependent jobs: {:?}. Sequential mode is enabled", j.name, j.depends_on);
-                            flow.job_parallel = false;
-                        }
+// Returns the conditions of the flow with their JSON pointer
+fn conditions(flow: &Flow) -> Vec<(String, &str)> {
+    let mut conditions: Vec<(String, &str)> = Vec::new();
 
-                        // Check wait_timeout
-                        j.wait_timeout = job.get(yamlValue::String("wait_timeout".to_string()))
-                            .and_then(|v| v.as_u64())
-                            .unwrap_or(300000);
-
-                        // Check wait_timeout
-                        j.wait_interval = job.get(yamlValue::String("wait_interval".to_string()))
-                            .and_then(|v| v.as_u64())
-                            .unwrap_or(3000);
-
-                        let mut tasks: Vec<Task> = Vec::new();
-
-                        if let Some(v) = job.get(yamlValue::String("tasks".to_string())) {
-                            if let Some (v1) = v.as_sequence() {
-                                let mut task_count = 1;
-
-                                for i in v1.iter() {
-                                    let mut t = Task::default();
-                                    let mut task = i.clone();
-
-                                    let default_taskname = "task-".to_owned() + &task_count.to_string();
-
-                                    if let Some(v2) = task.as_mapping_mut() {
-                                        // Check task name
-                                        if let Some(n) = v2.get(&yamlValue::String("name".to_string())) {
-                                            t.name = n.as_str().unwrap_or(&default_taskname).to_string();
-                                            v2.remove(&yamlValue::String("name".to_string()));
-                                        } else {
-                                            t.name = default_taskname.clone();
-                                        }
-
-                                        // Check if condition
-                                        let yaml_value_if = yamlValue::String("if".to_string());
-                                        if let Some(n) = v2.get(&yaml_value_if) {
-                                            t.r#if = n.as_str().map(|s| s.to_string());
-
-                                            v2.remove(&yaml_value_if);
-                                        }
-
-                                        // Check loop
-                                        let yaml_value_loop = yamlValue::String("loop".to_string());
-                                        if let Some(n) = v2.get(&yaml_value_loop) {
-                                            t.r#loop = match n.as_sequence() {
-                                                Some(seq) => {
-                                                    let mut items: Vec<jsonValue> = Vec::new();
-
-                                                    for s in seq.iter() {
-                                                        items.push(utils::convert_value_yaml_to_json(s)?);
-                                                    }
-
-                                                    Some(jsonValue::Array(items))
-                                                },
-                                                None => n.as_str().map(|s| jsonValue::String(s.to_string())),
-                                            };
-
-                                            v2.remove(&yaml_value_loop);
-                                        }
-
-                                        // Check loop_tempo
-                                        let yaml_value_loop_tempo = yamlValue::String("loop_tempo".to_string());
-                                        t.loop_tempo = v2.get(&yaml_value_loop_tempo)
-                                            .and_then(|v| v.as_u64())
-                                            .map(|v| {
-                                                v2.remove(&yaml_value_loop_tempo);
-                                                v
-                                            });
-
-                                        // Check register
-                                        let yaml_value_register = yamlValue::String("register".to_string());
-                                        t.register = v2.get(&yaml_value_register)
-                                            .and_then(|v| v.as_mapping())
-                                            .map(|v| {
-                                                let mut vars = Map::new();
-                                                for (k1, v1) in v.iter() {
-                                                    vars.insert(
-                                                        k1.as_str().unwrap_or("").to_string(),
-                                                        utils::convert_value_yaml_to_json(v1).unwrap_or(jsonValue::Null)
-                                                    );
-                                                }
-                                                vars
-                                            })
-                                            .unwrap_or_default();
-
-                                        v2.remove(&yaml_value_register);
-
-                                        // Check on_success
-                                        let yaml_value_on_success = yamlValue::String("on_success".to_string());
-                                        if let Some(v) = v2.get(&yaml_value_on_success) {
-                                            t.on_success = v.as_str().unwrap_or("").to_string();
-
-                                            v2.remove(&yaml_value_on_success);
-                                        }
-
-                                        // Check on_failure
-                                        let yaml_value_on_failure = yamlValue::String("on_failure".to_string());
-                                        if let Some(v) = v2.get(&yaml_value_on_failure) {
-                                            t.on_failure = v.as_str().unwrap_or("").to_string();
-
-                                            v2.remove(&yaml_value_on_failure);
-                                        }
-
-                                        // Check plugin
-                                        if let Some((k, v)) = v2.iter().next() {
-                                            t.plugin = k.as_str().unwrap_or("").to_string();
-                                            if t.plugin.is_empty() {
-                                                return Err(anyhow!("Plugin name can not be empty!"));
-                                            }
-
-                                            if let Some(v3) = v.get (yamlValue::String("params".to_string())) {
-                                                let mut params = Map::new();
-
-                                                if let Some(v4) = v3.as_mapping() {
-                                                    for (k, v) in v4.iter() {
-                                                        params.insert(k.as_str().unwrap_or("").to_string(), utils::convert_value_yaml_to_json(v)?);
-                                                    }
-                                                }
-
-                                                t.params = params;
-                                            } else {
-                                                return Err(anyhow!("Plugin params is missing!"));
-                                            }
-                                        } else {
-                                            return Err(anyhow!("Cannot parse the job {}, task {}", j.name, default_taskname));
-                                        }
-
-                                        // Set on_success for the previous task to this one
-                                        if task_count > 1 && tasks[task_count-2].on_success.is_empty() {
-                                            tasks[task_count-2].on_success = t.name.to_owned();
-                                        }
-                                    }
-
-                                    tasks.push(t);
-
-                                    task_count += 1;
-                                }
-                            }
-                        }
+    for (i, j) in flow.jobs.iter().enumerate() {
+        if let Some(c) = &j.r#if {
+            conditions.push((format!("/jobs/{}/if", i), c.as_str()));
+        }
+        task_conditions(&j.tasks, &format!("/jobs/{}/tasks", i), &mut conditions);
+    }
 
-                        j.tasks = tasks;
+    for (i, s) in flow.sinks.iter().enumerate() {
+        if let Some(c) = &s.r#if {
+            conditions.push((format!("/sinks/{}/if", i), c.as_str()));
+        }
+    }
 
-                        jobs.push(j);
+    conditions
+}
 
-                        job_count += 1;
-                    }
+fn task_conditions<'a>(tasks: &'a [Task], path: &str, conditions: &mut Vec<(String, &'a str)>) {
+    for (k, t) in tasks.iter().enumerate() {
+        let task_path = format!("{}/{}", path, k);
 
-                    jobs
-                },
-                None => Vec::new(),
+        for (field, cond) in [("if", &t.r#if), ("until", &t.until)] {
+            if let Some(c) = cond {
+                conditions.push((format!("{}/{}", task_path, field), c.as_str()));
             }
-        },
-        None => Vec::new(),
-    };
+        }
+
+        for (section, group_tasks) in [("block", &t.block), ("rescue", &t.rescue), ("always", &t.always)] {
+            task_conditions(group_tasks, &format!("{}/{}", task_path, section), conditions);
+        }
+    }
+}
+
+fn parse(content: &str, loader: &mut Loader) -> Result<Flow> {
+    let mut flow = parse_unchecked(content, loader)?;
 
     // Check if dependent jobs specified in each job exist
     let job_names: Vec<String> = flow.jobs.iter().map(|v| v.name.clone()).collect();
@@ -785,97 +1019,35 @@ fn parse(mapping: Mapping) -> Result<Flow> {
         return Err(anyhow!(format!("{} has unknown dependent jobs: depends_on={:?}", j.name, j.depends_on)));
     }
 
-    // Parse SINKS
-    flow.sinks = match mapping.get(&yamlValue::String("sinks".to_string())) {
-        Some(sks) => {
-            match sks.as_sequence() {
-                Some(seq) => {
-                    let mut sinks: Vec<Sink> = Vec::new();
-                    let mut sink_count = 1;
-
-                    for sk in seq.iter() {
-                        let mut s = Sink::default();
-
-                        let default_sinkname = "sink-".to_owned() + &sink_count.to_string();
-
-                        // Check name
-                        if let Some(v) = sk.get(yamlValue::String("name".to_string())) {
-                            s.name = v.as_str().unwrap_or(&default_sinkname).to_string();
-                        } else {
-                            s.name = default_sinkname.clone();
-                        }
-
-                        // Check if condition
-                        let yaml_value_if = yamlValue::String("if".to_string());
-                        if let Some(v) = sk.get(yaml_value_if) {
-                            s.r#if = v.as_str().map(|s| s.to_string());
-                        }
-
-                        // Check plugin
-                        if let Some(v) = sk.get(yamlValue::String("plugin".to_string())) {
-                            s.plugin = v.as_str().unwrap_or("").to_string();
-                        }
-
-                        if s.plugin.is_empty() {
-                            return Err(anyhow!("Plugin name can not be empty!"));
-                        }
-
-                        if let Some(v1) = sk.get(yamlValue::String("params".to_string())) {
-                            let mut params = Map::new();
-
-                            if let Some(v2) = v1.as_mapping() {
-                                for (k, v) in v2.iter() {
-                                    params.insert(k.as_str().unwrap_or("").to_string(), utils::convert_value_yaml_to_json(v)?);
-                                }
-                            }
-
-                            s.params = params;
-                        }
+    // Check that there is no loop between dependent jobs
+    check_job_cycles(&flow.jobs)?;
 
-                        sinks.push(s);
-
-                        sink_count += 1;
-                    }
-
-                    sinks
-                },
-                None => Vec::new(),
-            }
-        },
-        None => Vec::new(),
-    };
-
-    if let Some(s) = mapping.get(&yamlValue::String("remote_plugin_dir".to_string())) {
-        if let Some(v) = s.as_str() {
-            flow.remote_plugin_dir = v.to_string();
-        }
+    if let Some((_, e)) = check_routes(&flow).into_iter().next() {
+        return Err(anyhow!(e));
     }
 
-    if let Some(s) = mapping.get(&yamlValue::String("remote_exec_dir".to_string())) {
-        if let Some(v) = s.as_str() {
-            flow.remote_exec_dir = v.to_string();
-        }
+    if let Some((path, e)) = check_conditions(&flow).into_iter().next() {
+        return Err(anyhow!("{}: {}", path, e));
     }
 
-    if let Some(s) = mapping.get(&yamlValue::String("inventory_file".to_string())) {
-        if let Some(v) = s.as_str() {
-            flow.inventory_file = v.to_string();
-        }
-    }
-
-    if let Some(s) = mapping.get(&yamlValue::String("is_on_remote".to_string())) {
-        if let Some(v) = s.as_bool() {
-            flow.is_on_remote = v;
-        }
-    }
+    flow.compile_templates()?;
 
     Ok(flow)
 }
 
+// Builds the flow from its YAML definition without checking dependencies between
+// jobs, so that `validate` can report all errors of a flow at once.
+pub(crate) fn parse_unchecked(content: &str, loader: &mut Loader) -> Result<Flow> {
+    let def: FlowDef = serde_yaml::from_str(content)?;
+
+    def.into_flow(loader)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::plugin::PluginRegistry;
+    use crate::datastore::store::StoreNamespace;
     use ::futures::TryFutureExt;
     use tokio::time::{sleep, Duration};
     use serde_json::{Number, json};
@@ -980,7 +1152,7 @@ sinks:
       fetch: "one"
 "#;
 
-        let flow =  Flow::new_from_str(content);
+        let flow =  Flow::new_from_str(content).unwrap();
 
         let mut variables = Map::new();
         variables.insert("var1".to_string(), jsonValue::String("val1".to_string()));
@@ -1015,6 +1187,8 @@ sinks:
             context: Map::new(),
             rx: vec![],
             tx: vec![],
+            channel_capacity: None,
+            templates: None,
         });
 
         sources.push(Source {
@@ -1024,6 +1198,8 @@ sinks:
             context: Map::new(),
             rx: vec![],
             tx: vec![],
+            channel_capacity: None,
+            templates: None,
         });
 
         let params_sink1 = json_map!(
@@ -1044,9 +1220,12 @@ sinks:
             r#if: Some("job == job1".to_string()),
             plugin: "builtin-pgql-sqlx".to_string(),
             params: params_sink1,
+            from: vec![],
             context: Map::new(),
             rx: vec![],
             tx: vec![],
+            channel_capacity: None,
+            templates: None,
         });
 
         // Task / Job
@@ -1074,7 +1253,8 @@ sinks:
                 jsonValue::String("item1".to_string())
             ])),
             on_success: "".to_string(),
-            on_failure: "".to_string()
+            on_failure: "".to_string(),
+            ..Default::default()
         });
 
         let mut job2_tasks = Vec::new();
@@ -1085,7 +1265,8 @@ sinks:
             params: params_task1.clone(),
             r#loop: None,
             on_success: "task-2".to_string(),
-            on_failure: "task-3".to_string()
+            on_failure: "task-3".to_string(),
+            ..Default::default()
         });
 
         job2_tasks.push(Task {
@@ -1095,7 +1276,8 @@ sinks:
             params: params_task2.clone(),
             r#loop: None,
             on_success: "task-4".to_string(),
-            on_failure: "task-4".to_string()
+            on_failure: "task-4".to_string(),
+            ..Default::default()
         });
 
         job2_tasks.push(Task {
@@ -1105,7 +1287,8 @@ sinks:
             params: params_task3.clone(),
             r#loop: None,
             on_success: "task-4".to_string(),
-            on_failure: "".to_string()
+            on_failure: "".to_string(),
+            ..Default::default()
         });
 
         job2_tasks.push(Task {
@@ -1115,7 +1298,8 @@ sinks:
             params: params_task4.clone(),
             r#loop: None,
             on_success: "".to_string(),
-            on_failure: "".to_string()
+            on_failure: "".to_string(),
+            ..Default::default()
         });
 
         let mut job3_tasks = Vec::new();
@@ -1126,7 +1310,8 @@ sinks:
             params: params_task1.clone(),
             r#loop: Some(jsonValue::String("{{ array }}".to_string())),
             on_success: "".to_string(),
-            on_failure: "".to_string()
+            on_failure: "".to_string(),
+            ..Default::default()
         });
 
         let mut jobs = Vec::new();
@@ -1140,6 +1325,7 @@ sinks:
             result: Map::new(),
             rx: vec![],
             tx: vec![],
+            ..Default::default()
         });
 
         jobs.push(Job {
@@ -1152,6 +1338,7 @@ sinks:
             result: Map::new(),
             rx: vec![],
             tx: vec![],
+            ..Default::default()
         });
 
         jobs.push(Job {
@@ -1164,6 +1351,7 @@ sinks:
             result: Map::new(),
             rx: vec![],
             tx: vec![],
+            ..Default::default()
         });
 
         let mut datastore = StoreConfig::default();
@@ -1177,7 +1365,9 @@ sinks:
         let expected = Flow {
             name: "flow1".to_string(),
             variables,
+            overlays: Vec::new(),
             user_payload: jsonValue::Null,
+            secrets: BTreeMap::new(),
             kind: Kind::Action,
             datastore: Some(datastore),
             sources,
@@ -1187,9 +1377,421 @@ sinks:
             remote_exec_dir: "".to_string(),
             inventory_file: "".to_string(),
             is_on_remote: false,
+            schedule: "".to_string(),
+            job_parallel: true,
+            timeout: None,
+            channel_capacity: None,
+            checkpoint: None,
+            run_id: "".to_string(),
+            check: false,
+            templates: flow.templates.clone(),
         };
 
-        assert_eq!(flow.unwrap(), expected);
+        assert_eq!(flow, expected);
+        assert!(flow.templates.map(|t| !t.is_empty()).unwrap_or_default());
+    }
+
+    #[test]
+    fn test_new_from_file_errors() {
+        let err = Flow::new_from_file("/nonexistent/flow.yaml").unwrap_err().to_string();
+        assert!(err.starts_with("Cannot read the flow file /nonexistent/flow.yaml"), "{}", err);
+
+        let content = r#"
+name: flow1
+jobs:
+  - name: job1
+    tasks:
+    - name: task1
+      builtin-shell:
+        params:
+          cmd: ls
+      on_success: task2
+      loop_temp: 3
+"#;
+        let file = std::env::temp_dir().join("flowrunner-test-new-from-file-errors.yaml");
+        std::fs::write(&file, content).unwrap();
+
+        let path = file.display().to_string();
+        let err = Flow::new_from_file(&path).unwrap_err().to_string();
+        assert!(err.starts_with(&format!("{}: jobs[0].tasks[0].loop_temp: invalid type", path)), "{}", err);
+        assert!(err.ends_with("at line 11 column 18"), "{}", err);
+    }
+
+    #[test]
+    fn test_check_routes() {
+        let content = r#"
+name: flow1
+kind: stream
+sources:
+  - name: src-a
+    plugin: builtin-kafka-consumer
+  - name: src-b
+    plugin: builtin-kafka-consumer
+jobs:
+  - name: job-a
+    from: [src-a]
+    tasks:
+    - builtin-shell:
+        params:
+          cmd: "echo a"
+  - name: job-b
+    from: [src-b]
+    tasks:
+    - builtin-shell:
+        params:
+          cmd: "echo b"
+sinks:
+  - name: sink-a
+    plugin: builtin-kafka-producer
+    from: [job-a]
+  - name: sink-b
+    plugin: builtin-kafka-producer
+    from: [job-b]
+"#;
+        let flow = Flow::new_from_str(content).unwrap();
+        assert!(flow.jobs[0].receives_from("src-a"));
+        assert!(!flow.jobs[0].receives_from("src-b"));
+        assert!(flow.sinks[0].receives_from("job-a"));
+        assert!(!flow.sinks[0].receives_from("job-b"));
+
+        let err = Flow::new_from_str(&content.replace("[job-a]", "[job-c]")).unwrap_err().to_string();
+        assert_eq!("Sink sink-a receives results from the unknown job job-c", err);
+
+        let err = Flow::new_from_str(&content.replace("from: [src-b]", "from: [src-a]")).unwrap_err().to_string();
+        assert_eq!("Source src-b is not connected to any job", err);
+
+        let err = Flow::new_from_str(&content.replace("kind: stream", "kind: action")).unwrap_err().to_string();
+        assert_eq!("from is only supported by stream flows", err);
+
+        let err = Flow::new_from_str(&content.replace("from: [job-b]", "from: [job-a]")).unwrap_err().to_string();
+        assert_eq!("Job job-b is not connected to any sink", err);
+
+        // A sink without from receives the results of all jobs
+        assert!(check_routes(&Flow::new_from_str(&content.replace("    from: [job-b]\n", "")).unwrap()).is_empty());
+
+        // A job replying to a source which is also a sink is connected
+        let content = content.replace("  - name: src-b\n    plugin: builtin-kafka-consumer", "  - name: src-b\n    plugin: builtin-httpserver\n    params:\n      is_also_sink: true");
+        assert!(Flow::new_from_str(&content.replace("from: [job-b]", "from: [job-a]")).is_ok());
+    }
+
+    #[test]
+    fn test_check_conditions() {
+        let content = r#"
+name: flow1
+jobs:
+  - name: job1
+    if: "context.variables.x == 1"
+    tasks:
+    - name: group1
+      block:
+      - name: task1
+        until: "result.task1.status == 'Ok'"
+        builtin-shell:
+          params:
+            cmd: "echo a"
+"#;
+        let flow = Flow::new_from_str(content).unwrap();
+        assert!(check_conditions(&flow).is_empty());
+
+        let err = Flow::new_from_str(&content.replace("result.task1.status == 'Ok'", "{{ result.task1.status }}")).unwrap_err().to_string();
+        assert!(err.starts_with("/jobs/0/tasks/0/block/0/until: condition \"{{ result.task1.status }}\": template tags are not supported"), "{}", err);
+    }
+
+    #[test]
+    fn test_check_job_cycles() {
+        let content = r#"
+name: flow1
+
+jobs:
+  - name: job1
+    depends_on:
+    - job3
+    tasks:
+    - builtin-shell:
+        params:
+          cmd: "echo task1"
+
+  - name: job2
+    depends_on:
+    - job1
+    tasks:
+    - builtin-shell:
+        params:
+          cmd: "echo task1"
+
+  - name: job3
+    depends_on:
+    - job2
+    tasks:
+    - builtin-shell:
+        params:
+          cmd: "echo task1"
+"#;
+
+        assert_eq!(
+            "Cyclic dependencies detected between jobs: job1 -> job3 -> job2 -> job1",
+            Flow::new_from_str(content).unwrap_err().to_string()
+        );
+
+        let content = r#"
+name: flow1
+
+jobs:
+  - name: job1
+    tasks:
+    - builtin-shell:
+        params:
+          cmd: "echo task1"
+
+  - name: job2
+    depends_on:
+    - job1
+    tasks:
+    - builtin-shell:
+        params:
+          cmd: "echo task1"
+
+  - name: job3
+    depends_on:
+    - job1
+    - job2
+    tasks:
+    - builtin-shell:
+        params:
+          cmd: "echo task1"
+"#;
+
+        let flow = Flow::new_from_str(content).unwrap();
+        let upstreams = get_upstream_jobs(&flow.jobs);
+
+        assert!(upstreams["job1"].is_empty());
+        assert_eq!(HashSet::from(["job1".to_string()]), upstreams["job2"]);
+        assert_eq!(HashSet::from(["job1".to_string(), "job2".to_string()]), upstreams["job3"]);
+    }
+
+    #[tokio::test]
+    async fn test_flow_run_with_dependencies() {
+        let _ =  env_logger::try_init();
+
+        let content = r#"
+name: flow1
+
+kind: action
+jobs:
+  - name: job1
+    tasks:
+    - builtin-shell:
+        params:
+          cmd: "echo job1"
+
+  - name: job2
+    depends_on:
+    - job1
+    tasks:
+    - builtin-shell:
+        params:
+          cmd: "echo {{ context.job_results.job1.result['task-1'].output.stdout }}"
+"#;
+
+        let mut flow =  Flow::new_from_str(content).unwrap();
+        PluginRegistry::load_plugins("target/debug").await;
+
+        flow.run().await.unwrap();
+
+        let result_expected_2 = json!({
+            "task-1": {
+                "status": "Ok",
+                "error": "",
+                "output": {
+                    "rc": 0,
+                    "stdout": "job1\n\n"
+                }
+            }
+        });
+
+        assert_eq!(result_expected_2.as_object().unwrap().to_owned(), flow.jobs[1].result);
+        assert!(flow.get_failed_jobs().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_flow_run_failed_jobs() {
+        let _ =  env_logger::try_init();
+
+        let content = r#"
+name: flow1
+
+kind: action
+jobs:
+  - name: job1
+    tasks:
+    - builtin-shell:
+        params:
+          cmd: "echo job1"
+
+  - name: job2
+    tasks:
+    - builtin-shell:
+        params:
+          cmd: "hello"
+"#;
+
+        let mut flow =  Flow::new_from_str(content).unwrap();
+        PluginRegistry::load_plugins("target/debug").await;
+
+        flow.run().await.unwrap();
+
+        assert_eq!(JobStatus::Ok, flow.jobs[0].status);
+        assert_eq!(JobStatus::Ko, flow.jobs[1].status);
+        assert_eq!(vec!["job2".to_string()], flow.get_failed_jobs());
+    }
+
+    #[tokio::test]
+    async fn test_flow_resume() {
+        let _ =  env_logger::try_init();
+
+        let dir = std::env::temp_dir().join("flowrunner-test-flow-resume");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let content = format!(r#"
+name: flow1
+kind: action
+datastore:
+  kind: rocksdb
+  conn_str: {dir}/rocksdb
+  options:
+    create_if_missing: true
+    create_missing_column_families: true
+  namespaces:
+    - name: runs
+checkpoint:
+  namespace: runs
+jobs:
+  - name: job1
+    tasks:
+    - name: task1
+      builtin-shell:
+        params:
+          cmd: "echo job1"
+  - name: job2
+    tasks:
+    - name: task1
+      builtin-shell:
+        params:
+          cmd: "mkdir {dir}/job2"
+    - name: task2
+      builtin-shell:
+        params:
+          cmd: "ls {dir}/job2/marker"
+"#, dir = dir.display());
+
+        let mut flow = Flow::new_from_str(&content).unwrap();
+        PluginRegistry::load_plugins("target/debug").await;
+
+        let mut vars = Map::new();
+        vars.insert("env".to_string(), json!("prod"));
+        flow.override_variables(vars);
+
+        flow.run().await.unwrap();
+        assert_eq!(vec!["job2".to_string()], flow.get_failed_jobs());
+
+        let run_id = flow.run_id.clone();
+        assert!(!run_id.is_empty());
+
+        // task1 of job2 is not executed again, otherwise mkdir would fail
+        std::fs::write(dir.join("job2/marker"), "").unwrap();
+
+        let mut flow = Flow::new_from_str(&content).unwrap();
+        flow.resume(&run_id).await.unwrap();
+
+        assert!(flow.get_failed_jobs().is_empty());
+        assert_eq!(run_id, flow.run_id);
+        assert!(flow.jobs[1].result.contains_key("task1"));
+        assert_eq!(Some(&json!("prod")), flow.variables.get("env"));
+
+        let state = RunState::load(flow.datastore.as_ref().unwrap(), "runs", &run_id).unwrap();
+        assert_eq!(RunStatus::Succeeded, state.status);
+
+        let err = flow.resume(&run_id).await.unwrap_err().to_string();
+        assert_eq!(format!("Run {} already succeeded", run_id), err);
+    }
+
+    #[test]
+    fn test_override_variables_file() {
+        let dir = std::env::temp_dir().join("flowrunner-test-override-variables-file");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let _env = crate::utils::lock_env();
+        std::env::set_var(vault::PASSWORD_ENV, "vault-pass");
+
+        std::fs::write(dir.join("plain.yaml"), "env: prod").unwrap();
+        std::fs::write(dir.join("secret.yaml"), vault::encrypt(b"pg_password: overlay-secret-1", "vault-pass").unwrap()).unwrap();
+
+        let mut flow = Flow::default();
+        flow.override_variables_file(&dir.join("plain.yaml")).unwrap();
+        flow.override_variables_file(&dir.join("secret.yaml")).unwrap();
+
+        assert_eq!(Some(&json!("prod")), flow.variables.get("env"));
+        assert_eq!(Some(&json!("overlay-secret-1")), flow.variables.get("pg_password"));
+
+        // Decrypted variables are not saved in the run state
+        let state = RunState::new("run1", "flow1", &flow.overlays, &jsonValue::Null);
+        assert_eq!(vec![
+            VarsOverlay::Variables(json!({ "env": "prod" }).as_object().unwrap().to_owned()),
+            VarsOverlay::EncryptedFile(dir.join("secret.yaml").canonicalize().unwrap().display().to_string()),
+        ], state.overlays);
+        assert!(!serde_json::to_string(&state).unwrap().contains("overlay-secret-1"));
+    }
+
+    #[tokio::test]
+    async fn test_flow_timeout() {
+        let _ =  env_logger::try_init();
+
+        let dir = std::env::temp_dir().join("flowrunner-test-flow-timeout");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let content = format!(r#"
+name: flow1
+kind: action
+timeout: 200
+datastore:
+  kind: rocksdb
+  conn_str: {dir}/rocksdb
+  options:
+    create_if_missing: true
+    create_missing_column_families: true
+  namespaces:
+    - name: runs
+checkpoint:
+  namespace: runs
+jobs:
+  - name: job1
+    tasks:
+    - builtin-shell:
+        params:
+          cmd: "echo job1"
+  - name: job2
+    tasks:
+    - builtin-shell:
+        params:
+          cmd: "sleep 5"
+"#, dir = dir.display());
+
+        let mut flow = Flow::new_from_str(&content).unwrap();
+        PluginRegistry::load_plugins("target/debug").await;
+
+        let started = std::time::Instant::now();
+        let err = flow.run().await.unwrap_err().to_string();
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!("Flow flow1: timed out after 200ms, cancelled jobs: [\"job2\"]", err);
+        assert_eq!(vec!["job2".to_string()], flow.get_failed_jobs());
+
+        let state = RunState::load(flow.datastore.as_ref().unwrap(), "runs", &flow.run_id).unwrap();
+        assert_eq!(RunStatus::Failed, state.status);
+        assert_eq!(JobStatus::Ok, state.jobs["job1"].status);
+        assert_eq!(JobStatus::Ko, state.jobs["job2"].status);
     }
 
     #[tokio::test]
@@ -1245,7 +1847,7 @@ jobs:
         //run_all_jobs(flow.kind.clone(), flow.jobs.clone(), None).await.unwrap();
         flow.run().await.unwrap();
 
-        // Because hosts is not local and we are not supporting remote host
+        // Because host1 is not local and there is no inventory to resolve it
         let result_expected_1 = json!({});
 
         let result_expected_2 = json!({
@@ -0,0 +1,299 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use log::{debug, error};
+
+use crate::checkpoint::RunStatus;
+use crate::datastore::store::{BoxStore, StoreConfig};
+use crate::flow::Flow;
+use crate::job::Status as JobStatus;
+use crate::secret::{mask, mask_value};
+
+/// History configuration of the runner
+///
+/// Runs are recorded in the namespace `namespace` of the datastore `datastore`. The namespace
+/// must not define a `prefix_len` so that all runs can be listed.
+#[derive(Default, Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct HistoryConfig {
+    pub datastore: StoreConfig,
+    #[serde(default = "default_namespace")]
+    pub namespace: String,
+    #[serde(default)]
+    pub retention: Retention,
+}
+
+fn default_namespace() -> String {
+    "runs".to_string()
+}
+
+/// Retention of the recorded runs. Older runs are removed after each new run.
+#[derive(Default, Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Retention {
+    // Maximum number of runs kept
+    #[serde(default)]
+    pub max_runs: Option<usize>,
+    // Maximum age in days of the runs kept
+    #[serde(default)]
+    pub max_days: Option<u64>,
+}
+
+/// What started a run
+#[derive(Clone, Serialize, Deserialize, Copy, Debug, PartialEq)]
+pub enum Trigger {
+    Exec,
+    Resume,
+    Server,
+    Cron,
+}
+
+impl Default for Trigger {
+    fn default() -> Self {
+        Trigger::Exec
+    }
+}
+
+/// Run of a job with the results of its tasks
+#[derive(Default, Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct JobRecord {
+    pub name: String,
+    #[serde(default)]
+    pub status: JobStatus,
+    #[serde(default)]
+    pub failed_task: Option<String>,
+    // Status, error and output of each task
+    #[serde(default)]
+    pub tasks: Map<String, Value>,
+}
+
+/// Run of a flow. A resumed run replaces the record of the run.
+#[derive(Default, Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct RunRecord {
+    pub run_id: String,
+    pub flow: String,
+    #[serde(default)]
+    pub trigger: Trigger,
+    #[serde(default)]
+    pub status: RunStatus,
+    #[serde(default)]
+    pub started_at: String,
+    #[serde(default)]
+    pub ended_at: String,
+    #[serde(default)]
+    pub error: Option<String>,
+    #[serde(default)]
+    pub jobs: Vec<JobRecord>,
+}
+
+impl RunRecord {
+    /// Creates the record of a finished run of the flow. `result` is the result of the run.
+    pub fn new(flow: &Flow, trigger: Trigger, started_at: &DateTime<Utc>, result: &Result<()>) -> Self {
+        let failed = result.is_err() || !flow.get_failed_jobs().is_empty();
+
+        RunRecord {
+            run_id: flow.run_id.clone(),
+            flow: flow.name.clone(),
+            trigger,
+            status: if failed { RunStatus::Failed } else { RunStatus::Succeeded },
+            started_at: format_time(started_at),
+            ended_at: format_time(&Utc::now()),
+            error: result.as_ref().err().map(|e| mask(&e.to_string())),
+            jobs: flow.jobs.iter().map(|j| JobRecord {
+                name: j.name.clone(),
+                status: j.status,
+                failed_task: j.failed_task.clone(),
+                tasks: j.result.iter().map(|(k, v)| (k.clone(), mask_value(v))).collect(),
+            }).collect(),
+        }
+    }
+}
+
+/// Filter of the listed runs
+#[derive(Default, Clone, Debug, PartialEq)]
+pub struct RunFilter {
+    pub flow: Option<String>,
+    pub status: Option<RunStatus>,
+    pub limit: Option<usize>,
+}
+
+/// History of the flow runs
+#[derive(Clone)]
+pub struct History {
+    store: BoxStore,
+    namespace: String,
+    retention: Retention,
+}
+
+impl History {
+    pub fn new(config: &HistoryConfig) -> Result<Self> {
+        if !config.datastore.namespaces.iter().any(|n| n.name == config.namespace) {
+            return Err(anyhow!("history.namespace {} must be declared in history.datastore.namespaces", config.namespace));
+        }
+
+        Ok(History {
+            store: config.datastore.new_store()?,
+            namespace: config.namespace.clone(),
+            retention: config.retention.clone(),
+        })
+    }
+
+    /// Records the run and removes the runs out of the retention
+    pub fn record(&self, run: &RunRecord) -> Result<()> {
+        self.store.set(&self.namespace, &run.run_id, &serde_json::to_string(run)?)?;
+
+        self.prune()
+    }
+
+    /// Records the finished run of the flow. Errors are only logged so that the history
+    /// does not change the result of the run.
+    pub fn record_flow(&self, flow: &Flow, trigger: Trigger, started_at: &DateTime<Utc>, result: &Result<()>) {
+        if flow.run_id.is_empty() {
+            return;
+        }
+
+        if let Err(e) = self.record(&RunRecord::new(flow, trigger, started_at, result)) {
+            error!("Failed to record the run in the history: run_id={}, err={}", flow.run_id, e);
+        }
+    }
+
+    /// Returns the run `run_id`
+    pub fn get(&self, run_id: &str) -> Result<RunRecord> {
+        let content = self.store.get(&self.namespace, run_id)?;
+        if content.is_empty() {
+            return Err(anyhow!("Run {} is not found", run_id));
+        }
+
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    /// Returns the runs matching the filter, the most recent first
+    pub fn list(&self, filter: &RunFilter) -> Result<Vec<RunRecord>> {
+        let runs = self.all()?.into_iter()
+            .filter(|r| filter.flow.as_ref().map(|f| &r.flow == f).unwrap_or(true))
+            .filter(|r| filter.status.map(|s| r.status == s).unwrap_or(true))
+            .take(filter.limit.unwrap_or(usize::MAX))
+            .collect();
+
+        Ok(runs)
+    }
+
+    // Returns all runs, the most recent first
+    fn all(&self) -> Result<Vec<RunRecord>> {
+        let mut runs = Vec::new();
+
+        for (k, v) in self.store.find(&self.namespace, "")? {
+            match serde_json::from_value::<RunRecord>(v) {
+                Ok(r) => runs.push(r),
+                Err(e) => debug!("Ignoring the run {}: {}", k, e),
+            }
+        }
+
+        runs.sort_by(|a, b| b.started_at.cmp(&a.started_at));
+
+        Ok(runs)
+    }
+
+    fn prune(&self) -> Result<()> {
+        if self.retention.max_runs.is_none() && self.retention.max_days.is_none() {
+            return Ok(());
+        }
+
+        let min_started_at = self.retention.max_days
+            .map(|d| format_time(&(Utc::now() - Duration::days(d as i64))));
+
+        for (i, r) in self.all()?.iter().enumerate() {
+            let too_many = self.retention.max_runs.map(|m| i >= m).unwrap_or(false);
+            let too_old = min_started_at.as_ref().map(|m| &r.started_at < m).unwrap_or(false);
+
+            if too_many || too_old {
+                debug!("Removing the run from the history: run_id={}", r.run_id);
+                self.store.delete(&self.namespace, &r.run_id)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Times have a fixed format so that they are ordered as strings
+fn format_time(t: &DateTime<Utc>) -> String {
+    t.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastore::store::StoreNamespace;
+    use serde_json::json;
+
+    fn new_history(name: &str, retention: Retention) -> History {
+        let dir = std::env::temp_dir().join(name);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let mut options = Map::new();
+        options.insert("create_if_missing".to_string(), json!(true));
+        options.insert("create_missing_column_families".to_string(), json!(true));
+
+        History::new(&HistoryConfig {
+            datastore: StoreConfig {
+                kind: "rocksdb".to_string(),
+                conn_str: dir.display().to_string(),
+                options,
+                namespaces: vec![StoreNamespace { name: "runs".to_string(), ..Default::default() }],
+                ..Default::default()
+            },
+            namespace: "runs".to_string(),
+            retention,
+        }).unwrap()
+    }
+
+    fn new_run(run_id: &str, flow: &str, status: RunStatus, started_at: &DateTime<Utc>) -> RunRecord {
+        RunRecord {
+            run_id: run_id.to_string(),
+            flow: flow.to_string(),
+            status,
+            started_at: format_time(started_at),
+            ended_at: format_time(started_at),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_history() {
+        let history = new_history("flowrunner-test-history", Retention::default());
+        let now = Utc::now();
+
+        history.record(&new_run("run1", "flow1", RunStatus::Succeeded, &(now - Duration::seconds(2)))).unwrap();
+        history.record(&new_run("run2", "flow2", RunStatus::Failed, &(now - Duration::seconds(1)))).unwrap();
+        history.record(&new_run("run3", "flow1", RunStatus::Failed, &now)).unwrap();
+
+        let ids = |runs: Vec<RunRecord>| runs.into_iter().map(|r| r.run_id).collect::<Vec<String>>();
+
+        assert_eq!(vec!["run3", "run2", "run1"], ids(history.list(&RunFilter::default()).unwrap()));
+        assert_eq!(vec!["run3", "run1"], ids(history.list(&RunFilter { flow: Some("flow1".to_string()), ..Default::default() }).unwrap()));
+        assert_eq!(vec!["run3", "run2"], ids(history.list(&RunFilter { status: Some(RunStatus::Failed), ..Default::default() }).unwrap()));
+        assert_eq!(vec!["run3"], ids(history.list(&RunFilter { limit: Some(1), ..Default::default() }).unwrap()));
+
+        assert_eq!("flow2", history.get("run2").unwrap().flow);
+        assert_eq!("Run run4 is not found", history.get("run4").unwrap_err().to_string());
+    }
+
+    #[test]
+    fn test_history_retention() {
+        let history = new_history("flowrunner-test-history-retention", Retention { max_runs: Some(2), max_days: Some(1) });
+        let now = Utc::now();
+
+        history.record(&new_run("run1", "flow1", RunStatus::Succeeded, &(now - Duration::days(2)))).unwrap();
+        history.record(&new_run("run2", "flow1", RunStatus::Succeeded, &(now - Duration::seconds(2)))).unwrap();
+        assert_eq!(1, history.list(&RunFilter::default()).unwrap().len());
+
+        history.record(&new_run("run3", "flow1", RunStatus::Succeeded, &(now - Duration::seconds(1)))).unwrap();
+        history.record(&new_run("run4", "flow1", RunStatus::Succeeded, &now)).unwrap();
+
+        let runs = history.list(&RunFilter::default()).unwrap();
+        assert_eq!(vec!["run4", "run3"], runs.into_iter().map(|r| r.run_id).collect::<Vec<String>>());
+    }
+}
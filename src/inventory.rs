@@ -1,29 +1,47 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use anyhow::{anyhow, Result};
+
+use glob::Pattern;
 
 /// Describes structure of a host
-#[derive(Debug ,Serialize, Deserialize, PartialEq, Clone)]
+#[derive(Default, Debug ,Serialize, Deserialize, PartialEq, Clone)]
 pub struct Host {
+    #[serde(default)]
 	pub name: String,
 
+    #[serde(default)]
     pub groups: Vec<String>,
-	pub vars: HashMap<String, String>
+    #[serde(default)]
+	pub vars: Map<String, Value>
 }
 
 /// Describes attributes of a group
-#[derive(Debug ,Serialize, Deserialize, PartialEq, Clone)]
+#[derive(Default, Debug ,Serialize, Deserialize, PartialEq, Clone)]
 pub struct Group {
+    #[serde(default)]
 	pub name: String,
 
+    #[serde(default)]
 	pub hosts: Vec<String>,
-	pub vars: HashMap<String, String>
+    #[serde(default)]
+	pub children: Vec<String>,
+    #[serde(default)]
+	pub vars: Map<String, Value>
 }
 
 /// Describes attributes of a host inventory
 #[derive(Debug ,Serialize, Deserialize, PartialEq, Clone)]
 pub struct Inventory {
-	pub global: HashMap<String, String>,
+    #[serde(default)]
+	pub global: Map<String, Value>,
+    #[serde(default)]
 	pub hosts: HashMap<String, Host>,
+    #[serde(default)]
 	pub groups: HashMap<String, Group>
 }
 
@@ -31,7 +49,7 @@ pub struct Inventory {
 impl Default for Inventory {
     fn default() -> Self {
         Inventory {
-            global: HashMap::new(),
+            global: Map::new(),
             hosts: HashMap::new(),
             groups: HashMap::new()
         }
@@ -40,13 +58,449 @@ impl Default for Inventory {
 
 ////////// DEFINITION OF ALL FUNCTIONS ///////////////////////////
 
-// NewInventory instancies a new Inventory
-//impl Inventory {
-    //pub fn new() -> Self {
-        //Inventory {
-            //global: HashMap::new(),
-            //hosts: HashMap::new(),
-            //groups: HashMap::new()
-        //}
-    //}
-//}
+impl Inventory {
+    /// Loads an inventory from a YAML or INI file.
+    ///
+    /// Files with the extension `.ini` or whose first significant line is a section
+    /// header (`[group]`) are parsed as INI, others as YAML:
+    ///
+    /// ```yaml
+    /// global:
+    ///   ssh_user: flowrunner
+    /// hosts:
+    ///   web1:
+    ///     vars:
+    ///       ssh_host: 10.0.0.1
+    /// groups:
+    ///   web:
+    ///     hosts:
+    ///     - web1
+    ///   prod:
+    ///     children:
+    ///     - web
+    /// ```
+    ///
+    /// ```ini
+    /// [all:vars]
+    /// ssh_user=flowrunner
+    ///
+    /// [web]
+    /// web1 ssh_host=10.0.0.1
+    ///
+    /// [prod:children]
+    /// web
+    /// ```
+    pub fn new_from_file(file: &str) -> Result<Inventory> {
+        let content = fs::read_to_string(file)
+            .map_err(|e| anyhow!("Cannot open the inventory file {}: {}", file, e))?;
+
+        let is_ini = file.ends_with(".ini") || content.lines()
+            .map(|l| l.trim())
+            .find(|l| !l.is_empty() && !l.starts_with('#') && !l.starts_with(';'))
+            .map(|l| l.starts_with('['))
+            .unwrap_or(false);
+
+        let inventory = if is_ini {
+            Inventory::new_from_ini_str(&content)
+        } else {
+            Inventory::new_from_str(&content)
+        };
+
+        inventory.map_err(|e| anyhow!("Cannot parse the inventory file {}: {}", file, e))
+    }
+
+    pub fn new_from_str(content: &str) -> Result<Inventory> {
+        let inventory: Inventory = serde_yaml::from_str(content)?;
+
+        inventory.normalize()
+    }
+
+    pub fn new_from_ini_str(content: &str) -> Result<Inventory> {
+        let mut inventory = Inventory::default();
+        // Hosts defined outside of any section are ungrouped
+        let mut section = ("".to_string(), "hosts".to_string());
+
+        for (n, line) in content.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                if !line.ends_with(']') {
+                    return Err(anyhow!("line {}: invalid section header {}", n + 1, line));
+                }
+
+                let header = &line[1..line.len()-1];
+                section = match header.split_once(':') {
+                    Some((g, k)) if k == "vars" || k == "children" => (g.to_string(), k.to_string()),
+                    Some((_, k)) => return Err(anyhow!("line {}: unknown section kind {}", n + 1, k)),
+                    None => (header.to_string(), "hosts".to_string()),
+                };
+
+                if section.0 != "all" {
+                    inventory.groups.entry(section.0.clone()).or_default();
+                }
+
+                continue;
+            }
+
+            match (section.0.as_str(), section.1.as_str()) {
+                ("all", "vars") => {
+                    let (k, v) = parse_ini_var(line).ok_or_else(|| anyhow!("line {}: invalid variable {}", n + 1, line))?;
+                    inventory.global.insert(k, v);
+                },
+                (g, "vars") => {
+                    let (k, v) = parse_ini_var(line).ok_or_else(|| anyhow!("line {}: invalid variable {}", n + 1, line))?;
+                    inventory.groups.entry(g.to_string()).or_default().vars.insert(k, v);
+                },
+                (g, "children") => {
+                    inventory.groups.entry(g.to_string()).or_default().children.push(line.to_string());
+                },
+                (g, _) => {
+                    let mut parts = line.split_whitespace();
+                    let name = parts.next().unwrap_or_default().to_string();
+
+                    let host = inventory.hosts.entry(name.clone()).or_default();
+                    for p in parts {
+                        let (k, v) = parse_ini_var(p).ok_or_else(|| anyhow!("line {}: invalid variable {}", n + 1, p))?;
+                        host.vars.insert(k, v);
+                    }
+
+                    if !g.is_empty() && g != "all" {
+                        inventory.groups.entry(g.to_string()).or_default().hosts.push(name);
+                    }
+                },
+            }
+        }
+
+        inventory.normalize()
+    }
+
+    // Sets names from map keys and makes group membership known on both sides
+    // so that hosts only declared in a group are part of the inventory too.
+    fn normalize(mut self) -> Result<Self> {
+        for (n, h) in self.hosts.iter_mut() {
+            h.name = n.clone();
+        }
+
+        for (n, g) in self.groups.iter_mut() {
+            g.name = n.clone();
+        }
+
+        let groups = self.groups.clone();
+        for g in groups.values() {
+            for h in g.hosts.iter() {
+                let host = self.hosts.entry(h.clone()).or_insert_with(|| Host {
+                    name: h.clone(),
+                    ..Default::default()
+                });
+
+                if !host.groups.contains(&g.name) {
+                    host.groups.push(g.name.clone());
+                }
+            }
+        }
+
+        let hosts = self.hosts.clone();
+        for h in hosts.values() {
+            for n in h.groups.iter() {
+                let group = self.groups.entry(n.clone()).or_insert_with(|| Group {
+                    name: n.clone(),
+                    ..Default::default()
+                });
+
+                if !group.hosts.contains(&h.name) {
+                    group.hosts.push(h.name.clone());
+                }
+            }
+        }
+
+        for g in self.groups.values() {
+            for c in g.children.iter() {
+                if !self.groups.contains_key(c) {
+                    return Err(anyhow!("Child group {} of {} is not defined", c, g.name));
+                }
+            }
+        }
+
+        // Group depths are computed once to detect loops between child groups
+        let mut names: Vec<&String> = self.groups.keys().collect();
+        names.sort();
+
+        for g in names {
+            self.get_group_depth(g, &mut Vec::new())?;
+        }
+
+        Ok(self)
+    }
+
+    // Returns the length of the longest chain of parent groups
+    fn get_group_depth(&self, name: &str, path: &mut Vec<String>) -> Result<usize> {
+        if path.iter().any(|p| p == name) {
+            path.push(name.to_string());
+            return Err(anyhow!("Cyclic child groups detected: {}", path.join(" -> ")));
+        }
+
+        path.push(name.to_string());
+
+        let mut depth = 0;
+        for p in self.groups.values().filter(|g| g.children.iter().any(|c| c == name)) {
+            depth = depth.max(self.get_group_depth(&p.name, path)? + 1);
+        }
+
+        path.pop();
+
+        Ok(depth)
+    }
+
+    /// Returns all hosts of a group including those of its child groups
+    pub fn get_group_hosts(&self, name: &str) -> Vec<String> {
+        let mut hosts: Vec<String> = Vec::new();
+        let mut stack: Vec<String> = vec![name.to_string()];
+        let mut visited: HashSet<String> = HashSet::new();
+
+        while let Some(n) = stack.pop() {
+            if !visited.insert(n.clone()) {
+                continue;
+            }
+
+            if let Some(g) = self.groups.get(&n) {
+                for h in g.hosts.iter() {
+                    if !hosts.contains(h) {
+                        hosts.push(h.clone());
+                    }
+                }
+
+                stack.extend(g.children.iter().rev().cloned());
+            }
+        }
+
+        hosts
+    }
+
+    /// Returns all groups of a host including parent groups, ordered by precedence:
+    /// parent groups come before their children and groups of the same depth are
+    /// sorted by name.
+    pub fn get_host_groups(&self, name: &str) -> Vec<String> {
+        let mut groups: Vec<(usize, String)> = self.groups.values()
+            .filter(|g| self.get_group_hosts(&g.name).iter().any(|h| h == name))
+            .map(|g| (self.get_group_depth(&g.name, &mut Vec::new()).unwrap_or(0), g.name.clone()))
+            .collect();
+
+        groups.sort();
+
+        groups.into_iter().map(|(_, n)| n).collect()
+    }
+
+    /// Returns the names of hosts matching a host pattern.
+    ///
+    /// A pattern is a list of terms separated by `:` or `,`. Each term is a host name,
+    /// a group name, `all` or a glob (`web*`) matching host and group names. Terms
+    /// prefixed by `&` restrict the selection to their hosts and terms prefixed by
+    /// `!` exclude their hosts, for example `web*:&prod:!web3`.
+    pub fn resolve_hosts(&self, pattern: &str) -> Result<Vec<String>> {
+        let mut included: Vec<String> = Vec::new();
+        let mut intersections: Vec<Vec<String>> = Vec::new();
+        let mut excluded: Vec<String> = Vec::new();
+
+        for t in pattern.split(|c| c == ':' || c == ',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+            if let Some(n) = t.strip_prefix('!') {
+                excluded.extend(self.match_hosts(n)?);
+            } else if let Some(n) = t.strip_prefix('&') {
+                intersections.push(self.match_hosts(n)?);
+            } else {
+                for h in self.match_hosts(t)? {
+                    if !included.contains(&h) {
+                        included.push(h);
+                    }
+                }
+            }
+        }
+
+        Ok(included.into_iter()
+            .filter(|h| intersections.iter().all(|i| i.contains(h)))
+            .filter(|h| !excluded.contains(h))
+            .collect())
+    }
+
+    fn match_hosts(&self, term: &str) -> Result<Vec<String>> {
+        if term == "all" || term == "*" {
+            let mut hosts: Vec<String> = self.hosts.keys().cloned().collect();
+            hosts.sort();
+
+            return Ok(hosts);
+        }
+
+        if self.groups.contains_key(term) {
+            return Ok(self.get_group_hosts(term));
+        }
+
+        if self.hosts.contains_key(term) {
+            return Ok(vec![term.to_string()]);
+        }
+
+        if term.contains(|c| c == '*' || c == '?' || c == '[') {
+            let p = Pattern::new(term).map_err(|e| anyhow!("Invalid host pattern {}: {}", term, e))?;
+            let mut hosts: Vec<String> = self.hosts.keys().filter(|h| p.matches(h)).cloned().collect();
+
+            let mut groups: Vec<&String> = self.groups.keys().filter(|g| p.matches(g)).collect();
+            groups.sort();
+
+            for g in groups {
+                hosts.extend(self.get_group_hosts(g));
+            }
+
+            hosts.sort();
+            hosts.dedup();
+
+            return Ok(hosts);
+        }
+
+        Err(anyhow!("Host or group {} not found in the inventory", term))
+    }
+
+    /// Returns host variables merged with the following precedence, from the lowest
+    /// to the highest: global variables, group variables (parent groups first, see
+    /// `get_host_groups`) and host variables.
+    pub fn get_host_vars(&self, name: &str) -> Map<String, Value> {
+        let mut vars = self.global.clone();
+
+        for g in self.get_host_groups(name).iter().filter_map(|g| self.groups.get(g)) {
+            vars.extend(g.vars.clone());
+        }
+
+        if let Some(h) = self.hosts.get(name) {
+            vars.extend(h.vars.clone());
+        }
+
+        vars
+    }
+
+    /// Returns merged variables of all hosts indexed by host name
+    pub fn get_all_host_vars(&self) -> Map<String, Value> {
+        self.hosts.keys()
+            .map(|h| (h.clone(), Value::Object(self.get_host_vars(h))))
+            .collect()
+    }
+}
+
+// Parses a `key=value` INI variable. Unquoted values are parsed as JSON scalars
+// when possible (numbers, booleans), quoted values are always kept as strings.
+fn parse_ini_var(s: &str) -> Option<(String, Value)> {
+    let (k, v) = s.split_once('=')?;
+    let (k, v) = (k.trim(), v.trim());
+
+    if k.is_empty() {
+        return None;
+    }
+
+    let quoted = v.len() >= 2 && ((v.starts_with('"') && v.ends_with('"')) || (v.starts_with('\'') && v.ends_with('\'')));
+    let val = if quoted {
+        Value::String(v[1..v.len() - 1].to_string())
+    } else {
+        match serde_json::from_str::<Value>(v) {
+            Ok(Value::Number(n)) => Value::Number(n),
+            Ok(Value::Bool(b)) => Value::Bool(b),
+            _ => Value::String(v.to_string()),
+        }
+    };
+
+    Some((k.to_string(), val))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_resolve_hosts() {
+        let content = r#"
+global:
+  ssh_user: flowrunner
+  ssh_port: 22
+
+hosts:
+  web1:
+    vars:
+      ssh_host: 10.0.0.1
+  db1:
+    groups:
+    - db
+    vars:
+      ssh_port: 2222
+
+groups:
+  web:
+    hosts:
+    - web1
+    - web2
+    - web3
+    vars:
+      ssh_user: www
+  prod:
+    children:
+    - web
+    - db
+    vars:
+      ssh_user: prod
+      env: prod
+"#;
+
+        let inventory = Inventory::new_from_str(content).unwrap();
+
+        assert_eq!(vec!["web1", "web2", "web3"], inventory.resolve_hosts("web").unwrap());
+        assert_eq!(vec!["db1", "web1", "web2", "web3"], inventory.resolve_hosts("db1, web").unwrap());
+        assert_eq!(vec!["web1", "web2"], inventory.resolve_hosts("web*:!web3").unwrap());
+        assert_eq!(vec!["db1"], inventory.resolve_hosts("all:&prod:!web").unwrap());
+        assert_eq!("Host or group app not found in the inventory", inventory.resolve_hosts("app").unwrap_err().to_string());
+
+        assert_eq!(vec!["prod", "web"], inventory.get_host_groups("web1"));
+        assert_eq!(
+            json!({"ssh_user": "www", "ssh_port": 22, "ssh_host": "10.0.0.1", "env": "prod"}).as_object().unwrap().to_owned(),
+            inventory.get_host_vars("web1")
+        );
+        assert_eq!(
+            json!({"ssh_user": "prod", "ssh_port": 2222, "env": "prod"}).as_object().unwrap().to_owned(),
+            inventory.get_host_vars("db1")
+        );
+    }
+
+    #[test]
+    fn test_new_from_ini_str() {
+        let content = r#"
+# Ungrouped hosts
+bastion ssh_host=192.168.0.1
+
+[all:vars]
+ssh_user=flowrunner
+
+[web]
+web1 ssh_host=10.0.0.1 ssh_port=2222
+web2
+
+[web:vars]
+http_port=8080
+tls="true"
+
+[prod:children]
+web
+"#;
+
+        let inventory = Inventory::new_from_ini_str(content).unwrap();
+
+        assert_eq!(vec!["bastion", "web1", "web2"], inventory.resolve_hosts("all").unwrap());
+        assert_eq!(vec!["web1", "web2"], inventory.resolve_hosts("prod").unwrap());
+        assert_eq!(
+            json!({"ssh_user": "flowrunner", "ssh_host": "10.0.0.1", "ssh_port": 2222, "http_port": 8080, "tls": "true"}).as_object().unwrap().to_owned(),
+            inventory.get_host_vars("web1")
+        );
+
+        assert_eq!(
+            "Cyclic child groups detected: a -> b -> a",
+            Inventory::new_from_ini_str("[a:children]\nb\n[b:children]\na\n").unwrap_err().to_string()
+        );
+    }
+}
@@ -3,21 +3,27 @@ use serde_json::{Map, Number, Value, json};
 
 use anyhow::{Result, anyhow};
 use std::collections::HashMap;
-
-use evalexpr::*;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 
 use log::{info, debug, error, warn};
 
+use rand::Rng;
+
 //use tokio::sync::mpsc::*;
 use tokio::time::{sleep, Duration, timeout};
 use async_channel::*;
+use futures::future::BoxFuture;
+use futures::stream::{self, StreamExt};
 
 use moka::future::Cache;
 use std::sync::{Arc, Mutex};
 
 use crate::datastore::store::{BoxStore, StoreConfig};
-use crate::plugin::{PluginRegistry, Status as PluginStatus};
+use crate::flow::DEFAULT_CHANNEL_CAPACITY;
+use crate::plugin::{BoxPlugin, PluginExecResult, PluginRegistry, Status as PluginStatus};
 use crate::message::Message as FlowMessage;
+use crate::template::TemplateCache;
 use crate::utils::*;
 
 #[macro_export]
@@ -71,6 +77,10 @@ pub struct Job {
     #[serde(skip_serializing, skip_deserializing)]
 	pub tx: Vec<Receiver<FlowMessage>>,
 
+    // Stream flows: sources sending their messages to the job, all sources if empty
+    #[serde(default)]
+    pub from: Vec<String>,
+
     // Options to control sequential execution mode
     #[serde(default)]
     pub depends_on: Vec<String>,
@@ -78,8 +88,39 @@ pub struct Job {
     pub wait_interval: u64,
     #[serde(default = "default_wait_timeout")]
     pub wait_timeout: u64,
+
+    // Maximum duration in ms to run the tasks of the job
+    #[serde(default)]
+    pub timeout: Option<u64>,
+
+    // Stream flows: number of messages processed at the same time. Messages whose
+    // `partition_key` template renders the same key are processed in their order.
+    #[serde(default)]
+    pub concurrency: Option<usize>,
+    #[serde(default)]
+    pub partition_key: Option<String>,
+    // Stream flows: capacity of the channel of messages received from sources
+    #[serde(default)]
+    pub channel_capacity: Option<usize>,
+
+    // Last task which failed, from which the job restarts when its flow run is resumed.
+    // When it is a task group whose block failed, this is the failed task of the block.
+    #[serde(default)]
+    pub failed_task: Option<String>,
+    // Failed block task of the last task group which failed
+    #[serde(skip_serializing, skip_deserializing)]
+    pub(crate) failed_block_task: Option<String>,
+
+    // Check mode: plugins only report what tasks would change
+    #[serde(default)]
+    pub check: bool,
+
+    #[serde(skip_serializing, skip_deserializing)]
+	pub cache: Option<Cache<String, Arc<Mutex<Map<String, Value>>>>>,
+
+    // Compiled templates shared with the flow
     #[serde(skip_serializing, skip_deserializing)]
-	pub cache: Option<Cache<String, Arc<Mutex<Map<String, Value>>>>>
+    pub templates: Option<Arc<TemplateCache>>,
 }
 
 fn default_wait_interval() -> u64 {
@@ -123,6 +164,12 @@ pub struct Task {
 	pub r#loop: Option<Value>,
     #[serde(default)]
 	pub loop_tempo: Option<u64>,
+    // Maximum number of loop items executed at the same time
+    #[serde(default)]
+    pub loop_concurrency: Option<usize>,
+    // Stop executing loop items after the first failure
+    #[serde(default)]
+    pub fail_fast: bool,
 
 	#[serde(default)]
 	pub register: Map<String, Value>,
@@ -130,7 +177,46 @@ pub struct Task {
     #[serde(default)]
 	pub on_success: String,
     #[serde(default)]
-	pub on_failure: String
+	pub on_failure: String,
+
+    // Options to retry the task while it fails or while `until` is not satisfied.
+    // The delay in ms between attempts is multiplied by `backoff` after each attempt
+    // and randomized by +/- `jitter` (ratio between 0 and 1).
+    #[serde(default)]
+    pub retries: u32,
+    #[serde(default)]
+    pub delay: Option<u64>,
+    #[serde(default)]
+    pub backoff: Option<f64>,
+    #[serde(default)]
+    pub jitter: Option<f64>,
+    // Condition evaluated after each successful attempt, `result` being the attempt result
+    #[serde(default)]
+    pub until: Option<String>,
+
+    // Maximum duration in ms of each attempt. The task fails when it is reached.
+    #[serde(default)]
+    pub timeout: Option<u64>,
+
+    // Task group without plugin: the block tasks are run, then the rescue tasks if one
+    // of them failed, then the always tasks in any case
+    #[serde(default)]
+    pub block: Vec<Task>,
+    #[serde(default)]
+    pub rescue: Vec<Task>,
+    #[serde(default)]
+    pub always: Vec<Task>,
+}
+
+impl Task {
+    /// Returns true if the task is a group of tasks
+    pub fn is_group(&self) -> bool {
+        !self.block.is_empty()
+    }
+}
+
+fn default_retry_delay() -> u64 {
+    1000
 }
 
 impl PartialEq for Job {
@@ -144,6 +230,11 @@ impl PartialEq for Job {
 }
 
 impl Job {
+    /// Returns true if the job receives the messages of the source
+    pub fn receives_from(&self, source: &str) -> bool {
+        self.from.is_empty() || self.from.iter().any(|s| s == source)
+    }
+
     pub async fn run(&mut self, tasks: Option<&str>, datastore: Option<StoreConfig>) -> Result<()> {
         info!("JOB RUN STARTED: job={}, hosts={}, nb_rx={}, nb_tx={}", self.name, self.hosts, self.rx.len(), self.tx.len());
         debug!("Job context: {:?}", self.context);
@@ -151,128 +242,203 @@ impl Job {
         // Check the name of all tasks indicated in taskflow
         self.check_tasks()?;
 
+        // Jobs run alone, without the cache of a flow, compile their own templates once
+        if self.templates.is_none() {
+            self.compile_templates()?;
+        }
+
         let ts = tasks.unwrap_or("");
 
-        if !self.tx.is_empty() {
-            loop {
-                // Reinit the current keys added when receiving each message
-                debug!("Reinitialize job's status, result & context: job={}, status={:?}", self.name, self.status);
-                self.status = Status::default();
-                self.result.clear();
-                let _ = self.context.remove("msg_id");
-                let _ = self.context.remove("register");
-                let _ = self.context.remove("user_payload");
-
-                match self.tx[0].recv().await {
-                    // Add message received as data in job context
-                    Ok(msg) => {
-                        let uuid = match msg {
-                            FlowMessage::JsonWithSender{ uuid: id, sender: s, source: src, value: v } => {
-                                let mut msg_id = self.context
-                                    .get("msg_id")
-                                    .and_then(|v| v.as_object())
-                                    .unwrap_or(&Map::new()).to_owned();
-                                msg_id.insert("uuid".to_string(), Value::String(id.clone()));
-                                msg_id.insert("sender".to_string(), Value::String(s));
-                                msg_id.insert("source".to_string(), Value::String(src.unwrap_or_else(|| "".to_string())));
-                                msg_id.insert("data".to_string(), v);
-
-                                self.context.insert("msg_id".to_string(), Value::Object(msg_id));
-
-                                id
-                            },
-                            _ => {
-                                error!("Message received is not Message::Json type: job={}", self.name);
-                                continue;
-                            },
-                       };
-
-                        // Wait for all dependent jobs be executed
-                        if let Err(e) = timeout(Duration::from_millis(self.wait_timeout),
-                                                self.wait_dependend_jobs(&uuid))
-                            .await {
-                            error!("Timeout to wait for dependent jobs getting executed: job={}, timeout={}, err={e}",
-                                   self.name,
-                                   self.wait_timeout);
-                            continue;
-                        }
+        if self.tx.is_empty() {
+            return self.run_tasks(ts, datastore).await;
+        }
 
-                        // Run certain tasks given in parameter
-                        let (res, msg_err) = if !ts.is_empty() {
-                            (self.run_task_by_task(ts, datastore.clone()).await,
-                            "failed to run task by task".to_string())
-                        } else {
-                            // Run complete taskflow by running the first task
-                            (self.run_all_tasks(self.start.clone(), datastore.clone()).await,
-                            "failed to run all tasks".to_string())
-                        };
+        let concurrency = self.concurrency.unwrap_or(1).max(1);
+        if concurrency == 1 {
+            let tx = self.tx[0].clone();
+            self.process_messages(&tx, ts, datastore).await;
 
-                        // Cache the result
-                        if let Some(cache) = self.cache.clone() {
-                            let uuid_cloned = uuid.clone();
-                            let name_cloned = self.name.clone();
-                            let status_cloned = self.status;
-                            let result_cloned = self.result.clone();
-                            let mut job_result = cache.get_with(uuid_cloned, async move {
-                                let mut jr = Map::new();
-                                jr.insert(name_cloned, json!({
-                                    "status": status_cloned,
-                                    "result": result_cloned
-                                }));
-                                Arc::new(Mutex::new(jr))
-                            }).await;
-
-                            debug!("Getting current cache value before update: job={}, uuid={}, value={:?}",
-                                   self.name, uuid, job_result.lock().unwrap());
-
-                            job_result.lock().unwrap().insert(self.name.clone(), json!({
-                                "status": self.status,
-                                "result": self.result
-                            }));
+            return Ok(());
+        }
 
-                            info!("Updating job results cache: job={}, uuid={}", self.name, uuid);
-                            cache.insert(uuid.clone(), job_result).await;
+        info!("Processing messages with workers: job={}, concurrency={}, partition_key={:?}",
+              self.name, concurrency, self.partition_key);
 
-                            job_result = cache.get(&uuid).unwrap_or(Arc::new(Mutex::new(Map::new())));
-                            debug!("Getting cache value after update: job={}, uuid={}, value={:?}",
-                                   self.name, uuid, job_result.lock().unwrap());
-                        }
+        // Without partition key, workers receive the messages from the same channel.
+        // Otherwise, each worker has its own channel fed with the messages of its keys.
+        let mut inputs: Vec<Sender<FlowMessage>> = Vec::new();
+        let mut workers = Vec::new();
 
-                        // If job run encounter errors, zap to next message
-                        if let Err(e) = res {
-                            error!("{}: job={}, err={e}", msg_err, self.name);
-                            continue;
-                        }
+        for _ in 0..concurrency {
+            let tx = match self.partition_key {
+                Some(_) => {
+                    let (rx, tx) = bounded::<FlowMessage>(self.channel_capacity.unwrap_or(DEFAULT_CHANNEL_CAPACITY));
+                    inputs.push(rx);
+                    tx
+                },
+                None => self.tx[0].clone(),
+            };
 
-                        for rx1 in self.rx.iter() {
-                            let msg = FlowMessage::JsonWithSender {
-                                uuid: uuid.clone(),
-                                sender: self.name.clone(),
-                                source: None,
-                                value: Value::Object(self.result.clone()),
-                            };
-                            match rx1.send(msg).await {
-                                Ok(()) => (),
-                                Err(e) => error!("failed to send job's result: job={}, err={}", self.name, e.to_string()),
-                            };
-                        }
-                    },
-                    Err(e) => { error!("{}", e.to_string()); break; },
+            let mut worker = self.clone();
+            let ts_cloned = ts.to_string();
+            let datastore_cloned = datastore.clone();
+            workers.push(tokio::spawn(async move {
+                worker.process_messages(&tx, &ts_cloned, datastore_cloned).await
+            }));
+        }
+
+        if let Some(key) = self.partition_key.clone() {
+            while let Ok(msg) = self.tx[0].recv().await {
+                let i = self.partition(&key, &msg, concurrency);
+
+                if let Err(e) = inputs[i].send(msg).await {
+                    error!("failed to dispatch message to worker: job={}, worker={}, err={}", self.name, i, e);
+                    break;
                 }
             }
-        } else {
-            // Run certain tasks given in parameter
-            if !ts.is_empty() {
-                self.run_task_by_task(ts, datastore).await?;
-            } else {
-                // Run complete taskflow by running the first task
-                self.run_all_tasks(self.start.clone(), datastore).await?;
+
+            // Closes the channels of workers so that they stop once their messages are processed
+            inputs.clear();
+        }
+
+        for w in workers {
+            if let Err(e) = w.await {
+                error!("worker failed: job={}, err={}", self.name, e);
             }
         }
 
         Ok(())
     }
 
+    // Processes the messages received on the channel until it is closed
+    async fn process_messages(&mut self, tx: &Receiver<FlowMessage>, ts: &str, datastore: Option<StoreConfig>) {
+        loop {
+            // Reinit the current keys added when receiving each message
+            debug!("Reinitialize job's status, result & context: job={}, status={:?}", self.name, self.status);
+            self.status = Status::default();
+            self.result.clear();
+            let _ = self.context.remove("msg_id");
+            let _ = self.context.remove("register");
+            let _ = self.context.remove("user_payload");
+
+            match tx.recv().await {
+                // Add message received as data in job context
+                Ok(msg) => {
+                    let uuid = match message_id(&msg) {
+                        Some((id, msg_id)) => {
+                            self.context.insert("msg_id".to_string(), msg_id);
+                            id
+                        },
+                        None => {
+                            error!("Message received is not Message::Json type: job={}", self.name);
+                            continue;
+                        },
+                    };
+
+                    // Wait for all dependent jobs be executed
+                    if let Err(e) = timeout(Duration::from_millis(self.wait_timeout),
+                                            self.wait_dependend_jobs(&uuid))
+                        .await {
+                        error!("Timeout to wait for dependent jobs getting executed: job={}, timeout={}, err={e}",
+                               self.name,
+                               self.wait_timeout);
+                        continue;
+                    }
+
+                    // Run certain tasks given in parameter or complete taskflow
+                    let res = self.run_tasks(ts, datastore.clone()).await;
+                    let msg_err = if !ts.is_empty() {
+                        "failed to run task by task".to_string()
+                    } else {
+                        "failed to run all tasks".to_string()
+                    };
+
+                    // Cache the result
+                    if let Some(cache) = self.cache.clone() {
+                        let uuid_cloned = uuid.clone();
+                        let name_cloned = self.name.clone();
+                        let status_cloned = self.status;
+                        let result_cloned = self.result.clone();
+                        let mut job_result = cache.get_with(uuid_cloned, async move {
+                            let mut jr = Map::new();
+                            jr.insert(name_cloned, json!({
+                                "status": status_cloned,
+                                "result": result_cloned
+                            }));
+                            Arc::new(Mutex::new(jr))
+                        }).await;
+
+                        debug!("Getting current cache value before update: job={}, uuid={}, value={:?}",
+                               self.name, uuid, job_result.lock().unwrap());
+
+                        job_result.lock().unwrap().insert(self.name.clone(), json!({
+                            "status": self.status,
+                            "result": self.result
+                        }));
+
+                        info!("Updating job results cache: job={}, uuid={}", self.name, uuid);
+                        cache.insert(uuid.clone(), job_result).await;
+
+                        job_result = cache.get(&uuid).unwrap_or(Arc::new(Mutex::new(Map::new())));
+                        debug!("Getting cache value after update: job={}, uuid={}, value={:?}",
+                               self.name, uuid, job_result.lock().unwrap());
+                    }
+
+                    // If job run encounter errors, zap to next message
+                    if let Err(e) = res {
+                        error!("{}: job={}, err={e}", msg_err, self.name);
+                        continue;
+                    }
+
+                    for rx1 in self.rx.iter() {
+                        let msg = FlowMessage::JsonWithSender {
+                            uuid: uuid.clone(),
+                            sender: self.name.clone(),
+                            source: None,
+                            value: Value::Object(self.result.clone()),
+                        };
+                        match rx1.send(msg).await {
+                            Ok(()) => (),
+                            Err(e) => error!("failed to send job's result: job={}, err={}", self.name, e.to_string()),
+                        };
+                    }
+                },
+                Err(e) => { error!("{}", e.to_string()); break; },
+            }
+        }
+    }
+
+    // Runs certain tasks or the complete taskflow within the job timeout if set.
+    // When the timeout is reached, the running task is cancelled and the job is Ko.
+    async fn run_tasks(&mut self, tasks: &str, datastore: Option<StoreConfig>) -> Result<()> {
+        let job_timeout = match self.timeout {
+            Some(t) => t,
+            None => return self.run_tasks_untimed(tasks, datastore).await,
+        };
+
+        let res = timeout(Duration::from_millis(job_timeout), self.run_tasks_untimed(tasks, datastore)).await;
+
+        match res {
+            Ok(r) => r,
+            Err(_) => {
+                error!("Job timed out: job={}, timeout={}ms", self.name, job_timeout);
+                self.status = Status::Ko;
+
+                Ok(())
+            },
+        }
+    }
+
+    async fn run_tasks_untimed(&mut self, tasks: &str, datastore: Option<StoreConfig>) -> Result<()> {
+        // Run certain tasks given in parameter
+        if !tasks.is_empty() {
+            self.run_task_by_task(tasks, datastore).await
+        } else {
+            // Run complete taskflow by running the first task
+            self.run_all_tasks(self.start.clone(), datastore).await
+        }
+    }
+
     async fn wait_dependend_jobs(&mut self, uuid: &String) {
         // We need to verify if the current job has any dependant jobs and all of
         // them are already executed.
@@ -317,196 +483,339 @@ impl Job {
     }
 
     async fn run_all_tasks(&mut self, start: Option<Task>, datastore: Option<StoreConfig>) -> Result<()> {
-        let mut next_task: Option<Task> = match start {
-            Some(task) => Some(task),
-            None => Some(self.tasks[0].clone()),
-        };
-
         // If job condition is not satisfied, then exit
         if !self.render_job_and_eval()? {
             return Ok(());
         }
 
-        // Execute task
-        while let Some(mut t) = next_task.to_owned() {
-            info!("Task will be executed: name={}, params={:?}, register={:?}",
-                  t.name, t.params, t.register);
+        let tasks = self.tasks.clone();
+        self.failed_block_task = None;
 
-            let mut task_result = PluginStatus::Ok;
+        let failed = self.run_task_list(&tasks, start, &datastore).await?;
 
-            // If task condition is not satisfied, then move to next task as when the task is
-            // correctly executed
-            let vec_params = self.render_task_template(&mut t)?;
-            if vec_params.is_empty() {
-                next_task = match t.on_success.len() {
-                    n if n > 0 => self.get_task_by_name(t.on_success.as_str()),
-                    _ => None,
-                };
+        // The failed block task is only kept if it belongs to the group which failed
+        let block_task = self.failed_block_task.take()
+            .filter(|b| failed.as_ref().and_then(|f| find_task(&tasks, f)).map(|g| contains_task(&g.block, b)).unwrap_or(false));
 
-                continue;
-            }
+        self.failed_task = block_task.or(failed);
 
-            debug!("Task's params array: len={}, params={:?}", vec_params.len(), vec_params);
+        // Update job's status to Ko when a task failed
+        if self.failed_task.is_some() {
+            self.status = Status::Ko;
+        }
 
-            // Init datastore if configured
-            let mut bs: Option<BoxStore> = None;
+        Ok(())
+    }
 
-            if let Some(ds) = datastore.clone() {
-                bs = Some(ds.new_store()?);
+    async fn run_task_by_task(&mut self, tasks: &str, datastore: Option<StoreConfig>) -> Result<()> {
+        // If job condition is not satisfied then exit
+        if !self.render_job_and_eval()? {
+            return Ok(())
+        }
+
+        for s in tasks.split(',') {
+            match self.get_task_by_name(s) {
+                Some(t) => {
+                    // Update job's status to Ko when a task failed
+                    if self.run_task(t, &datastore).await? == PluginStatus::Ko {
+                        self.status = Status::Ko;
+                    }
+                },
+                None => warn!("Task {} not found => ignored!", s),
             }
+        }
 
-            match PluginRegistry::get_plugin(&t.plugin) {
-                Some(mut plugin) => {
-                    let mut vec_res: Vec<Value> = Vec::new();
+        Ok(())
+    }
 
-                    for p in vec_params.iter() {
-                        debug!("Treating params array item: p={:?}", p);
-                        plugin.validate_params(p.clone())?;
-                        plugin.set_datastore(bs.clone());
-                        let res = plugin.func(Some(self.name.clone()), &self.rx, &self.tx).await;
-                        vec_res.push(serde_json::to_value(res.clone())?);
+    // Runs the tasks of a list from `start`, or the first one, by following on_success and
+    // on_failure within the list. Returns the name of the last task which failed, if any.
+    fn run_task_list<'a>(
+        &'a mut self,
+        tasks: &'a [Task],
+        start: Option<Task>,
+        datastore: &'a Option<StoreConfig>,
+    ) -> BoxFuture<'a, Result<Option<String>>> {
+        Box::pin(async move {
+            let mut next_task = start.or_else(|| tasks.first().cloned());
+            let mut failed: Option<String> = None;
+
+            while let Some(t) = next_task {
+                // Move to next task on success when PluginStatus::Ok, or to the task of failure
+                next_task = if self.run_task(t.clone(), datastore).await? == PluginStatus::Ok {
+                    find_task(tasks, &t.on_success)
+                } else {
+                    failed = Some(t.name.clone());
+                    find_task(tasks, &t.on_failure)
+                };
+            }
 
-                        info!("Task result: name {}, res: {:?}",  t.name.clone(), res);
+            Ok(failed)
+        })
+    }
 
-                        if res.status == PluginStatus::Ko {
-                            task_result = PluginStatus::Ko;
-                        }
+    // Executes a task or a task group and records its result. Tasks whose condition is not
+    // satisfied, or whose plugin is not found, are handled as correctly executed.
+    async fn run_task(&mut self, mut t: Task, datastore: &Option<StoreConfig>) -> Result<PluginStatus> {
+        info!("Task will be executed: name={}, params={:?}, register={:?}",
+              t.name, t.params, t.register);
 
-                        // Check if loop_tempo is set
-                        if let Some(tempo) = t.loop_tempo {
-                            debug!("Waiting for the next execution: tempo={}", tempo);
-                            std::thread::sleep(Duration::from_millis(tempo));
-                        }
-                    }
+        let vec_params = self.render_task_template(&mut t)?;
+        if vec_params.is_empty() {
+            return Ok(PluginStatus::Ok);
+        }
 
-                    if vec_params.len() == 1 {
-                        self.result.insert(t.name.clone(), vec_res[0].clone());
-                    } else {
-                        self.result.insert(t.name.clone(), json!({
-                            "status": task_result,
-                            "error": "",
-                            "output": Value::Array(vec_res)
-                        }));
-                    }
+        if t.is_group() {
+            return self.run_task_group(&t, datastore).await;
+        }
 
-                    // Render register if not empty
-                    if !t.register.is_empty() {
-                        self.render_register(&t.register)?;
-                    }
-                },
-                None => error!("No plugin found"),
-            };
+        debug!("Task's params array: len={}, params={:?}", vec_params.len(), vec_params);
 
-             // Move to next task on success when PluginStatus::Ok or no plugin found
-            if task_result == PluginStatus::Ok {
-                next_task = match t.on_success.len() {
-                    n if n > 0 => self.get_task_by_name(t.on_success.as_str()),
-                    _ => None,
-                };
-            } else {
-                // Go the task of Failure if specified
-                next_task = match t.on_failure.len() {
-                    n if n == 0 => None,
-                    _ => self.get_task_by_name(t.on_failure.as_str()),
-                };
-                // Update job's status to Ko when a task failed
-                self.status = Status::Ko;
-            }
+        if PluginRegistry::get_plugin(&t.plugin).is_none() {
+            error!("No plugin with the name {} found", t.plugin);
+            return Ok(PluginStatus::Ok);
         }
 
-        Ok(())
+        // Init datastore if configured
+        let mut bs: Option<BoxStore> = None;
+
+        if let Some(ds) = datastore.clone() {
+            bs = Some(ds.new_store()?);
+        }
+
+        let (status, vec_res) = self.exec_task_items(&t, &vec_params, bs).await?;
+
+        if vec_params.len() == 1 {
+            self.result.insert(t.name.clone(), vec_res[0].clone());
+        } else {
+            self.result.insert(t.name.clone(), json!({
+                "status": status,
+                "error": "",
+                "output": Value::Array(vec_res)
+            }));
+        }
+
+        // Render register if not empty
+        if !t.register.is_empty() {
+            self.render_register(&t.register)?;
+        }
+
+        Ok(status)
     }
 
-    async fn run_task_by_task(&mut self, tasks: &str, datastore: Option<StoreConfig>) -> Result<()> {
-        // If job condition is not satisfied then exit
-        if !self.render_job_and_eval()? {
-            return Ok(())
+    // Runs the block of a task group. When one of its tasks fails, the rescue tasks are run
+    // with the failed task in `context.failed_task`, and the group succeeds if they do.
+    // The always tasks are run in any case.
+    async fn run_task_group(&mut self, t: &Task, datastore: &Option<StoreConfig>) -> Result<PluginStatus> {
+        let mut status = PluginStatus::Ok;
+        let mut error = String::new();
+        let mut block_task: Option<String> = None;
+
+        self.failed_block_task = None;
+
+        if let Some(failed) = self.run_task_list(&t.block, None, datastore).await? {
+            status = PluginStatus::Ko;
+            error = format!("task {} failed", failed);
+
+            // The failure may come from a nested group
+            block_task = self.failed_block_task.take()
+                .filter(|b| find_task(&t.block, &failed).map(|g| contains_task(&g.block, b)).unwrap_or(false))
+                .or_else(|| Some(failed.clone()));
+
+            if !t.rescue.is_empty() {
+                let failed_result = self.result.get(&failed).cloned().unwrap_or_default();
+                self.context.insert("failed_task".to_string(), json!({
+                    "name": failed,
+                    "error": failed_result.get("error").cloned().unwrap_or_default(),
+                    "result": failed_result,
+                }));
+
+                let rescue = self.run_task_list(&t.rescue, None, datastore).await;
+                let _ = self.context.remove("failed_task");
+
+                match rescue? {
+                    Some(f) => error = format!("rescue task {} failed", f),
+                    None => {
+                        status = PluginStatus::Ok;
+                        error.clear();
+                    },
+                }
+            }
         }
 
-        for s in tasks.split(',') {
-            match self.get_task_by_name(s) {
-                Some(mut t) => {
-                    info!("Task executed: name={}, params={:?}, register={:?}",
-                          t.name, t.params, t.register);
-
-                    // If task condition is not satisfied then move to next one
-                    let vec_params = self.render_task_template(&mut t)?;
-                    if vec_params.is_empty() {
-                        continue
-                    }
+        if let Some(f) = self.run_task_list(&t.always, None, datastore).await? {
+            status = PluginStatus::Ko;
+            error = format!("always task {} failed", f);
+        }
 
-                    // Init datastore if configured
-                    let mut bs: Option<BoxStore> = None;
+        self.failed_block_task = if status == PluginStatus::Ko { block_task } else { None };
 
-                    if let Some(ds) = datastore.clone() {
-                        bs = Some(ds.new_store()?);
-                    }
+        info!("Task group result: name {}, status: {:?}, error: {}", t.name, status, error);
+        self.result.insert(t.name.clone(), json!({
+            "status": status,
+            "error": error,
+            "output": {}
+        }));
 
-                    match PluginRegistry::get_plugin(&t.plugin) {
-                        Some(mut plugin) => {
-                            let mut vec_res: Vec<Value> = Vec::new();
+        Ok(status)
+    }
 
-                            for p in vec_params.iter() {
-                                plugin.validate_params(p.clone())?;
-                                plugin.set_datastore(bs.clone());
-                                let res = plugin.func(Some(self.name.clone()), &self.rx, &self.tx).await;
-                                self.result.insert(t.name.clone(), serde_json::to_value(res.clone())?);
+    // Executes the task once per params item, i.e. once per loop item. Up to `loop_concurrency`
+    // items run at the same time and their starts are spaced by `loop_tempo`. Results keep the
+    // order of the items. With `fail_fast`, no more items are started after a failure and the
+    // running ones are cancelled.
+    async fn exec_task_items(
+        &self,
+        t: &Task,
+        vec_params: &[Map<String, Value>],
+        bs: Option<BoxStore>,
+    ) -> Result<(PluginStatus, Vec<Value>)> {
+        let concurrency = t.loop_concurrency.unwrap_or(1).max(1);
+
+        let mut results = Box::pin(stream::iter(vec_params.iter().enumerate())
+            .then(|(idx, p)| async move {
+                // Check if loop_tempo is set
+                if let (Some(tempo), true) = (t.loop_tempo, idx > 0) {
+                    debug!("Waiting for the next execution: tempo={}", tempo);
+                    sleep(Duration::from_millis(tempo)).await;
+                }
 
-                                vec_res.push(serde_json::to_value(res.clone())?);
+                p
+            })
+            .map(|p| {
+                let bs = bs.clone();
 
-                                // Update job's status to Ko when a task failed
-                                if res.status == PluginStatus::Ko {
-                                    self.status = Status::Ko;
-                                }
+                async move {
+                    debug!("Treating params array item: p={:?}", p);
+                    let mut plugin = PluginRegistry::get_plugin(&t.plugin)
+                        .ok_or_else(|| anyhow!("Plugin {} is not found", t.plugin))?;
 
-                                info!("Task result: name {}, res: {:?}",  t.name.clone(), res);
-                            }
+                    plugin.validate_params(p.clone())?;
+                    plugin.set_datastore(bs);
 
-                            if vec_params.len() == 1 {
-                                self.result.insert(t.name.clone(), vec_res[0].clone());
-                            } else {
-                                self.result.insert(t.name.clone(), json!({
-                                    "status": self.status,
-                                    "error": "",
-                                    "output": Value::Array(vec_res)
-                                }));
-                            }
+                    self.exec_task_plugin(t, &mut plugin).await
+                }
+            })
+            .buffered(concurrency));
 
-                            // Render register if not empty
-                            if !t.register.is_empty() {
-                                self.render_register(&t.register)?;
-                            }
-                        },
-                        None => error!("No plugin with the name {} found", t.name),
-                    }
-                },
-                None => warn!("Task {} not found => ignored!", s),
+        let mut task_result = PluginStatus::Ok;
+        let mut vec_res: Vec<Value> = Vec::new();
+
+        while let Some(r) = results.next().await {
+            let (status, res) = r?;
+
+            info!("Task result: name {}, res: {:?}",  t.name.clone(), res);
+            vec_res.push(res);
+
+            if status == PluginStatus::Ko {
+                task_result = PluginStatus::Ko;
+
+                if t.fail_fast && vec_res.len() < vec_params.len() {
+                    warn!("Task failed, remaining loop items are skipped: name={}, done={}/{}",
+                          t.name, vec_res.len(), vec_params.len());
+                    break;
+                }
             }
         }
 
-        Ok(())
+        Ok((task_result, vec_res))
     }
 
-    // Checks all tasks to see if the name given for task on
-    // failure or on success matches valid task names
-    fn check_tasks(&self) -> Result<()> {
-        let map: HashMap<_, _> = self.tasks.iter().map(|t| (t.name.clone(), t.clone())).collect();
+    // Executes the plugin of the task, whose params are already set. The plugin is run
+    // again, up to `retries` times, while it fails, times out or while `until` is not satisfied.
+    // All attempts are recorded in the result of tasks which can be retried.
+    async fn exec_task_plugin(&self, t: &Task, plugin: &mut BoxPlugin) -> Result<(PluginStatus, Value)> {
+        if self.check {
+            return self.check_task_plugin(t, plugin).await;
+        }
 
-        for t in self.tasks.iter() {
-            if PluginRegistry::get_plugin(&t.plugin).is_none() {
-                return Err(anyhow!("{}", format!("Plugin {} is not found", t.plugin)));
-            }
+        let mut attempts: Vec<Value> = Vec::new();
+
+        loop {
+            let mut res = match t.timeout {
+                Some(ms) => timeout(Duration::from_millis(ms), plugin.func(Some(self.name.clone()), &self.rx, &self.tx)).await
+                    .unwrap_or_else(|_| PluginExecResult {
+                        status: PluginStatus::Ko,
+                        error: format!("Task timed out after {}ms", ms),
+                        ..Default::default()
+                    }),
+                None => plugin.func(Some(self.name.clone()), &self.rx, &self.tx).await,
+            };
 
-            if !t.on_failure.is_empty() && !map.contains_key(&t.on_failure) {
-                return Err(anyhow!("task ".to_owned() + &t.on_failure + " is not found"));
+            if res.status == PluginStatus::Ok && !self.eval_until(t, &res)? {
+                res.status = PluginStatus::Ko;
+                res.error = format!("until condition is not satisfied: {}", t.until.clone().unwrap_or_default());
             }
 
-            if !t.on_failure.is_empty() && !map.contains_key(&t.on_success) {
-                return Err(anyhow!("task ".to_owned() + &t.on_success + " is not found"));
+            // Secrets must not reach the job results, which are logged, returned and saved
+            res.mask_secrets();
+
+            attempts.push(json!({ "status": res.status, "error": res.error }));
+
+            if res.status == PluginStatus::Ok || attempts.len() as u32 > t.retries {
+                let mut value = serde_json::to_value(&res)?;
+
+                if t.retries > 0 {
+                    if let Some(v) = value.as_object_mut() {
+                        v.insert("attempts".to_string(), Value::Array(attempts));
+                    }
+                }
+
+                return Ok((res.status, value));
             }
+
+            let delay = retry_delay(t, attempts.len() as u32);
+            warn!("Task failed, retrying: name={}, attempt={}/{}, delay={:?}, error={}",
+                  t.name, attempts.len(), t.retries + 1, delay, res.error);
+
+            sleep(delay).await;
         }
+    }
 
-        Ok(())
+    // Asks the plugin of the task what it would change. Tasks whose plugin does not support
+    // the check mode are skipped and handled as correctly executed.
+    async fn check_task_plugin(&self, t: &Task, plugin: &mut BoxPlugin) -> Result<(PluginStatus, Value)> {
+        match plugin.func_check(Some(self.name.clone()), &self.rx, &self.tx).await {
+            Some(mut res) => {
+                res.mask_secrets();
+
+                Ok((res.status, serde_json::to_value(&res)?))
+            },
+            None => {
+                info!("Task skipped in check mode: name={}, plugin={}", t.name, t.plugin);
+
+                Ok((PluginStatus::Ok, json!({
+                    "status": PluginStatus::Ok,
+                    "error": "",
+                    "output": {},
+                    "skipped": "skipped in check mode",
+                })))
+            },
+        }
+    }
+
+    fn eval_until(&self, t: &Task, res: &PluginExecResult) -> Result<bool> {
+        let until = match t.until.as_ref() {
+            Some(u) => u,
+            None => return Ok(true),
+        };
+
+        let mut data: Map<String, Value> = Map::new();
+
+        data.insert("context".to_string(), Value::Object(self.context.clone()));
+        data.insert("result".to_string(), serde_json::to_value(res)?);
+
+        expand_env_map(&mut data);
+
+        self.templates()?
+            .eval_condition(t.name.as_str(), until, &data)
+            .map_err(|e| anyhow!("{}: until: {}", t.name, e))
+    }
+
+    // Checks all tasks to see if the name given for task on
+    // failure or on success matches valid task names
+    fn check_tasks(&self) -> Result<()> {
+        check_task_list(&self.tasks)
     }
 
     fn get_task_by_name(&self, name: &str) -> Option<Task> {
@@ -519,6 +828,8 @@ impl Job {
         None
     }
 
+    // Evaluates task's condition and renders its loop and params. They have access to `context`,
+    // including `context.host` and `context.hostvars` set from the inventory, and `result`.
     fn render_task_template(&self, task: &mut Task) -> Result<Vec<Map<String, Value>>> {
         let mut vec_params: Vec<Map<String, Value>> = Vec::new();
         let mut data: Map<String, Value> = Map::new();
@@ -529,32 +840,26 @@ impl Job {
 
         expand_env_map(&mut data);
 
-        if let Some(mut txt) = task.r#if.clone() {
-            // Expand job if condition
-            render_text_template(component, &mut txt, &data)?;
+        let templates = self.templates()?;
 
-            match eval_boolean(&txt) {
-                Ok(b) => {
-                    if !b {
-                        info!("{}", format!("task ignored: {}, if: {:?}, eval: {:?}", component, txt, b));
-                        return Ok(vec_params);
-                    }
-                },
-                Err(e) => return Err(anyhow!(e)),
-            };
+        if let Some(cond) = task.r#if.as_ref() {
+            if !templates.eval_condition(component, cond, &data)? {
+                info!("{}", format!("task ignored: {}, if: {:?}, eval: false", component, cond));
+                return Ok(vec_params);
+            }
         }
 
         // Render loop if exists
         let mut r#loop: Value = Value::Null;
         if let Some(l) = task.r#loop.clone() {
-            r#loop = render_loop_template(component, &l, &data)?;
+            r#loop = templates.render_loop(component, &l, &data)?;
         }
 
         if r#loop == Value::Null {
             let mut params = Map::new();
 
             for (n, v) in task.params.clone().into_iter() {
-                params.insert(n.to_string(), render_value_template(component, &n, &v, &data)?);
+                params.insert(n.to_string(), templates.render_value(component, &n, &v, &data)?);
             }
 
             vec_params.push(params);
@@ -571,7 +876,7 @@ impl Job {
                 let mut params = Map::new();
 
                 for (n, v) in task.params.clone().into_iter() {
-                    params.insert(n.to_string(), render_value_template(component, &n, &v, &data)?);
+                    params.insert(n.to_string(), templates.render_value(component, &n, &v, &data)?);
                 }
 
                 vec_params.push(params);
@@ -581,8 +886,50 @@ impl Job {
         Ok(vec_params)
     }
 
+    /// Compiles the templates of the job in its own cache, for the secrets of its context.
+    /// It is used when the job runs alone, for example on a remote host, since the cache
+    /// of its flow is not sent with it.
+    pub fn compile_templates(&mut self) -> Result<()> {
+        let templates = TemplateCache::get_or_create(&mut self.templates, &self.context)?;
+
+        templates.compile(&serde_json::to_value(&self.tasks)?)?;
+        if let Some(key) = &self.partition_key {
+            templates.compile(&Value::String(key.clone()))?;
+        }
+
+        Ok(())
+    }
+
+    // Returns the templates compiled for the flow or by compile_templates
+    fn templates(&self) -> Result<Arc<TemplateCache>> {
+        TemplateCache::shared(&self.templates, &self.name)
+    }
+
+    // Returns the index of the worker processing the message. Messages with the same
+    // partition key are always processed by the same worker.
+    fn partition(&self, key: &str, msg: &FlowMessage, workers: usize) -> usize {
+        let mut context = self.context.clone();
+        if let Some((_, msg_id)) = message_id(msg) {
+            context.insert("msg_id".to_string(), msg_id);
+        }
+
+        let mut data: Map<String, Value> = Map::new();
+        data.insert("context".to_string(), Value::Object(context));
+
+        let mut txt = key.to_string();
+        if let Err(e) = self.templates().and_then(|t| t.render_text(self.name.as_str(), &mut txt, &data)) {
+            warn!("Partition key not rendered, message processed by the first worker: job={}, err={}", self.name, e);
+            return 0;
+        }
+
+        let mut hasher = DefaultHasher::new();
+        txt.hash(&mut hasher);
+
+        (hasher.finish() % workers as u64) as usize
+    }
+
     fn render_job_and_eval(&self) -> Result<bool> {
-        if let Some(mut txt) = self.r#if.clone() {
+        if let Some(cond) = self.r#if.as_ref() {
             let component = self.name.as_str();
             let mut data: Map<String, Value> = Map::new();
 
@@ -590,20 +937,10 @@ impl Job {
 
             expand_env_map(&mut data);
 
-            // Expand job if condition
-            render_text_template(component, &mut txt, &data)?;
-
-            match eval_boolean(&txt) {
-                Ok(b) => {
-                    if !b {
-                        info!("{}", format!("job ignored: {}, if: {:?}, eval: {:?}", component, txt, b));
-                        return Ok(false);
-                    }
-
-                    return Ok(true);
-                },
-                Err(e) => return Err(anyhow!(e)),
-            };
+            if !self.templates()?.eval_condition(component, cond, &data)? {
+                info!("{}", format!("job ignored: {}, if: {:?}, eval: false", component, cond));
+                return Ok(false);
+            }
         }
 
         Ok(true)
@@ -633,8 +970,9 @@ impl Job {
 
         expand_env_map(&mut data);
 
+        let templates = self.templates()?;
         for (k, v) in reg_vars.clone().into_iter() {
-            let val = render_value_template("register", &k, &v, &data)
+            let val = templates.render_value("register", &k, &v, &data)
                 .and_then(|v| {
                     match v.as_str() {
                         Some(v) => match serde_json::from_str(v) {
@@ -656,6 +994,110 @@ impl Job {
 
 }
 
+// Returns the uuid of the message and its description set in `context.msg_id`
+fn message_id(msg: &FlowMessage) -> Option<(String, Value)> {
+    match msg {
+        FlowMessage::JsonWithSender{ uuid, sender, source, value } => Some((uuid.clone(), json!({
+            "uuid": uuid,
+            "sender": sender,
+            "source": source.clone().unwrap_or_default(),
+            "data": value,
+        }))),
+        _ => None,
+    }
+}
+
+// Checks the tasks of a list and of its groups. Targets of on_success and on_failure
+// must be in the same list.
+fn check_task_list(tasks: &[Task]) -> Result<()> {
+    let map: HashMap<_, _> = tasks.iter().map(|t| (t.name.clone(), t.clone())).collect();
+
+    for t in tasks.iter() {
+        if t.is_group() {
+            check_task_list(&t.block)?;
+            check_task_list(&t.rescue)?;
+            check_task_list(&t.always)?;
+        } else if PluginRegistry::get_plugin(&t.plugin).is_none() {
+            return Err(anyhow!("{}", format!("Plugin {} is not found", t.plugin)));
+        }
+
+        if !t.on_failure.is_empty() && !map.contains_key(&t.on_failure) {
+            return Err(anyhow!("task ".to_owned() + &t.on_failure + " is not found"));
+        }
+
+        if !t.on_success.is_empty() && !map.contains_key(&t.on_success) {
+            return Err(anyhow!("task ".to_owned() + &t.on_success + " is not found"));
+        }
+    }
+
+    Ok(())
+}
+
+fn find_task(tasks: &[Task], name: &str) -> Option<Task> {
+    if name.is_empty() {
+        return None;
+    }
+
+    tasks.iter().find(|t| t.name == name).cloned()
+}
+
+// Returns true if the task `name` is in the list or in one of its groups
+fn contains_task(tasks: &[Task], name: &str) -> bool {
+    tasks.iter().any(|t| t.name == name || [&t.block, &t.rescue, &t.always].iter().any(|g| contains_task(g, name)))
+}
+
+/// Returns the task from which a job restarts when its failed task is `name`.
+///
+/// A task nested in the block of a group restarts the group with its block starting from
+/// this task. A task of a rescue or always section restarts its whole group, since these
+/// sections depend on the outcome of the block. Returns `None` if no task has this name.
+pub fn find_resume_task(tasks: &[Task], name: &str) -> Option<Task> {
+    for t in tasks.iter() {
+        if t.name == name {
+            return Some(t.clone());
+        }
+
+        if !t.is_group() {
+            continue;
+        }
+
+        for (k, b) in t.block.iter().enumerate() {
+            if let Some(start) = find_resume_task(std::slice::from_ref(b), name) {
+                let mut group = t.clone();
+                group.block = std::iter::once(start)
+                    .chain(t.block.iter().enumerate().filter(|(j, _)| *j != k).map(|(_, v)| v.clone()))
+                    .collect();
+
+                return Some(group);
+            }
+        }
+
+        if contains_task(&t.rescue, name) || contains_task(&t.always, name) {
+            return Some(t.clone());
+        }
+    }
+
+    None
+}
+
+// Returns the delay before the attempt following the `attempt`th one
+fn retry_delay(t: &Task, attempt: u32) -> Duration {
+    let mut delay = t.delay.unwrap_or_else(default_retry_delay) as f64;
+
+    if let Some(b) = t.backoff {
+        delay *= b.powi(attempt as i32 - 1);
+    }
+
+    if let Some(j) = t.jitter {
+        let j = j.clamp(0.0, 1.0);
+        if j > 0.0 {
+            delay *= 1.0 + rand::thread_rng().gen_range(-j..=j);
+        }
+    }
+
+    Duration::from_millis(delay.max(0.0) as u64)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -663,6 +1105,7 @@ mod tests {
     use serde_json::{Map, Number, json};
     use crate::plugin::{PluginRegistry, PluginExecResult};
     use crate::plugin_exec_result;
+    use json_ops::json_map;
 
     #[tokio::test]
     async fn test_check_tasks() {
@@ -692,7 +1135,8 @@ mod tests {
             params: params_task1.clone(),
             r#loop: None,
             on_success: "task-2".to_string(),
-            on_failure: "task-3".to_string()
+            on_failure: "task-3".to_string(),
+            ..Default::default()
         };
 
         let mut task2 = Task {
@@ -702,7 +1146,8 @@ mod tests {
             params: params_task2.clone(),
             r#loop: None,
             on_success: "task-4".to_string(),
-            on_failure: "task-4".to_string()
+            on_failure: "task-4".to_string(),
+            ..Default::default()
         };
 
         let mut task3 = Task {
@@ -712,7 +1157,8 @@ mod tests {
             params: params_task3.clone(),
             r#loop: None,
             on_success: "task-4".to_string(),
-            on_failure: "".to_string()
+            on_failure: "".to_string(),
+            ..Default::default()
         };
 
         let task4 = Task {
@@ -722,7 +1168,8 @@ mod tests {
             params: params_task4.clone(),
             r#loop: None,
             on_success: "".to_string(),
-            on_failure: "".to_string()
+            on_failure: "".to_string(),
+            ..Default::default()
         };
 
         job.tasks = vec![task1.clone(), task2.clone(), task3.clone(), task4.clone()];
@@ -774,7 +1221,8 @@ mod tests {
             params: params_task1.clone(),
             r#loop: None,
             on_success: "task-2".to_string(),
-            on_failure: "task-3".to_string()
+            on_failure: "task-3".to_string(),
+            ..Default::default()
         };
 
         let mut task2 = Task {
@@ -784,7 +1232,8 @@ mod tests {
             params: params_task2.clone(),
             r#loop: None,
             on_success: "task-4".to_string(),
-            on_failure: "task-4".to_string()
+            on_failure: "task-4".to_string(),
+            ..Default::default()
         };
 
         let mut task3 = Task {
@@ -794,7 +1243,8 @@ mod tests {
             params: params_task3.clone(),
             r#loop: None,
             on_success: "task-4".to_string(),
-            on_failure: "".to_string()
+            on_failure: "".to_string(),
+            ..Default::default()
         };
 
         let task4 = Task {
@@ -804,7 +1254,8 @@ mod tests {
             params: params_task4.clone(),
             r#loop: None,
             on_success: "".to_string(),
-            on_failure: "".to_string()
+            on_failure: "".to_string(),
+            ..Default::default()
         };
 
         job.tasks = vec![task1.clone(), task2.clone(), task3.clone(), task4.clone()];
@@ -895,6 +1346,342 @@ mod tests {
 
     }
 
+    #[tokio::test]
+    async fn test_task_retries() {
+        let _ =  env_logger::try_init();
+        PluginRegistry::load_plugins("target/debug").await;
+
+        let mut job = Job::default();
+        job.name = "job-1".to_string();
+        job.hosts = "localhost".to_string();
+
+        let mut params = Map::new();
+        params.insert("cmd".to_string(), jsonValue::String("hello".to_string()));
+
+        let mut task1 = Task {
+            name: "task-1".to_string(),
+            plugin: "builtin-shell".to_string(),
+            params: params.clone(),
+            retries: 2,
+            delay: Some(1),
+            ..Default::default()
+        };
+
+        job.tasks = vec![task1.clone()];
+        job.run(None, None).await.unwrap();
+
+        assert_eq!(Status::Ko, job.status);
+        assert_eq!(Some(&json!([
+            { "status": PluginStatus::Ko, "error": "No such file or directory (os error 2)" },
+            { "status": PluginStatus::Ko, "error": "No such file or directory (os error 2)" },
+            { "status": PluginStatus::Ko, "error": "No such file or directory (os error 2)" },
+        ])), job.result["task-1"].get("attempts"));
+
+        // Until condition never satisfied
+        params.insert("cmd".to_string(), jsonValue::String("echo task1".to_string()));
+        task1.params = params.clone();
+        task1.retries = 1;
+        task1.until = Some("result.output.rc == 1".to_string());
+
+        job.result.clear();
+        job.tasks = vec![task1.clone()];
+        job.run(None, None).await.unwrap();
+
+        assert_eq!(Some(&json!(PluginStatus::Ko)), job.result["task-1"].get("status"));
+        assert_eq!(2, job.result["task-1"]["attempts"].as_array().unwrap().len());
+
+        // Until condition satisfied at the first attempt
+        task1.until = Some("result.output.rc == 0".to_string());
+
+        job.result.clear();
+        job.tasks = vec![task1.clone()];
+        job.run(None, None).await.unwrap();
+
+        assert_eq!(Some(&json!(PluginStatus::Ok)), job.result["task-1"].get("status"));
+        assert_eq!(Some(&json!([{ "status": PluginStatus::Ok, "error": "" }])), job.result["task-1"].get("attempts"));
+
+        // Typed condition evaluated against the result
+        task1.until = Some("result.output.rc == 0 && 'task1' in result.output.stdout".to_string());
+
+        job.result.clear();
+        job.tasks = vec![task1.clone()];
+        job.run(None, None).await.unwrap();
+
+        assert_eq!(Some(&json!(PluginStatus::Ok)), job.result["task-1"].get("status"));
+    }
+
+    #[tokio::test]
+    async fn test_timeouts() {
+        let _ =  env_logger::try_init();
+        PluginRegistry::load_plugins("target/debug").await;
+
+        let mut job = Job::default();
+        job.name = "job-1".to_string();
+        job.hosts = "localhost".to_string();
+
+        let mut params_task1 = Map::new();
+        params_task1.insert("cmd".to_string(), jsonValue::String("sleep 5".to_string()));
+
+        let mut params_task2 = Map::new();
+        params_task2.insert("cmd".to_string(), jsonValue::String("echo task2".to_string()));
+
+        let task1 = Task {
+            name: "task-1".to_string(),
+            plugin: "builtin-shell".to_string(),
+            params: params_task1,
+            on_failure: "task-2".to_string(),
+            timeout: Some(100),
+            ..Default::default()
+        };
+
+        let task2 = Task {
+            name: "task-2".to_string(),
+            plugin: "builtin-shell".to_string(),
+            params: params_task2,
+            ..Default::default()
+        };
+
+        // Task timeout is handled by on_failure
+        job.tasks = vec![task1.clone(), task2.clone()];
+        job.run(None, None).await.unwrap();
+
+        assert_eq!(Status::Ko, job.status);
+        assert_eq!(serde_json::to_value(plugin_exec_result!(PluginStatus::Ko, "Task timed out after 100ms",)).unwrap(),
+                   job.result["task-1"]);
+        assert_eq!(Some(&json!(PluginStatus::Ok)), job.result["task-2"].get("status"));
+
+        // Job timeout cancels the running task
+        let mut task1_no_timeout = task1.clone();
+        task1_no_timeout.timeout = None;
+
+        job.status = Status::Ok;
+        job.result.clear();
+        job.timeout = Some(100);
+        job.tasks = vec![task1_no_timeout, task2];
+
+        let started = std::time::Instant::now();
+        job.run(None, None).await.unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(Status::Ko, job.status);
+        assert!(job.result.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_loop_concurrency() {
+        let _ =  env_logger::try_init();
+        PluginRegistry::load_plugins("target/debug").await;
+
+        let mut job = Job::default();
+        job.name = "job-1".to_string();
+        job.hosts = "localhost".to_string();
+
+        let mut params = Map::new();
+        params.insert("cmd".to_string(), jsonValue::String("{{ loop_item }}".to_string()));
+
+        let mut task1 = Task {
+            name: "task-1".to_string(),
+            plugin: "builtin-shell".to_string(),
+            params,
+            r#loop: Some(json!(["sleep 0.5", "echo 1", "sleep 0.5", "echo 3"])),
+            loop_concurrency: Some(4),
+            ..Default::default()
+        };
+
+        job.tasks = vec![task1.clone()];
+
+        let started = std::time::Instant::now();
+        job.run(None, None).await.unwrap();
+
+        // Items run concurrently and their results keep the order of the items
+        assert!(started.elapsed() < Duration::from_millis(1000));
+        let output = job.result["task-1"]["output"].as_array().unwrap();
+        assert_eq!(4, output.len());
+        assert_eq!(json!(1), output[1]["output"]["stdout"]);
+        assert_eq!(json!(3), output[3]["output"]["stdout"]);
+
+        // No more items after the first failure
+        task1.r#loop = Some(json!(["echo 0", "hello", "echo 2"]));
+        task1.loop_concurrency = None;
+        task1.fail_fast = true;
+
+        job.result.clear();
+        job.tasks = vec![task1.clone()];
+        job.run(None, None).await.unwrap();
+
+        assert_eq!(Status::Ko, job.status);
+        assert_eq!(2, job.result["task-1"]["output"].as_array().unwrap().len());
+    }
+
+    #[tokio::test]
+    async fn test_stream_concurrency() {
+        let _ =  env_logger::try_init();
+        PluginRegistry::load_plugins("target/debug").await;
+
+        let mut job = Job::default();
+        job.name = "job-1".to_string();
+        job.hosts = "localhost".to_string();
+        job.concurrency = Some(4);
+        job.tasks = vec![
+            Task {
+                name: "task-1".to_string(),
+                plugin: "builtin-shell".to_string(),
+                params: json_map!("cmd" => json!("sleep 0.5")),
+                ..Default::default()
+            },
+            Task {
+                name: "task-2".to_string(),
+                plugin: "builtin-shell".to_string(),
+                params: json_map!("cmd" => json!("echo {{ context.msg_id.data.seq }}")),
+                ..Default::default()
+            },
+        ];
+
+        // Runs the job until all messages are processed and returns the seq of the results
+        async fn run_stream(mut job: Job, nb: usize) -> Vec<(u64, u64)> {
+            let (input, rx) = bounded::<FlowMessage>(nb);
+            let (tx, output) = bounded::<FlowMessage>(nb);
+            job.tx = vec![rx];
+            job.rx = vec![tx];
+
+            for seq in 0..nb {
+                input.send(FlowMessage::JsonWithSender {
+                    uuid: seq.to_string(),
+                    sender: "src-1".to_string(),
+                    source: None,
+                    value: json!({ "seq": seq, "key": seq % 3 }),
+                }).await.unwrap();
+            }
+            input.close();
+
+            job.run(None, None).await.unwrap();
+
+            let mut results = Vec::new();
+            while let Ok(FlowMessage::JsonWithSender { uuid, value, .. }) = output.try_recv() {
+                let seq = value["task-2"]["output"]["stdout"].as_u64().unwrap();
+                assert_eq!(uuid, seq.to_string());
+                results.push((seq % 3, seq));
+            }
+
+            results
+        }
+
+        // Messages are processed in parallel
+        let started = std::time::Instant::now();
+        let results = run_stream(job.clone(), 8).await;
+        assert!(started.elapsed() < Duration::from_millis(2500));
+        assert_eq!(8, results.len());
+
+        // Messages with the same key are processed in order
+        job.partition_key = Some("{{ context.msg_id.data.key }}".to_string());
+        let results = run_stream(job.clone(), 9).await;
+        assert_eq!(9, results.len());
+
+        for key in 0..3 {
+            let seqs: Vec<u64> = results.iter().filter(|r| r.0 == key).map(|r| r.1).collect();
+            assert_eq!(vec![key, key + 3, key + 6], seqs);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_task_groups() {
+        let _ =  env_logger::try_init();
+        PluginRegistry::load_plugins("target/debug").await;
+
+        let shell_task = |name: &str, cmd: &str| {
+            let mut params = Map::new();
+            params.insert("cmd".to_string(), jsonValue::String(cmd.to_string()));
+
+            Task {
+                name: name.to_string(),
+                plugin: "builtin-shell".to_string(),
+                params,
+                ..Default::default()
+            }
+        };
+
+        let mut group = Task {
+            name: "group-1".to_string(),
+            block: vec![shell_task("block-1", "hello")],
+            rescue: vec![shell_task("rescue-1", "echo {{ context.failed_task.name }}")],
+            always: vec![shell_task("always-1", "echo always")],
+            ..Default::default()
+        };
+
+        let mut job = Job::default();
+        job.name = "job-1".to_string();
+        job.hosts = "localhost".to_string();
+        job.tasks = vec![group.clone()];
+
+        // The failure is rescued
+        job.run(None, None).await.unwrap();
+
+        assert_eq!(Status::Ok, job.status);
+        assert_eq!(Some(&json!(PluginStatus::Ko)), job.result["block-1"].get("status"));
+        assert_eq!(json!("block-1\n"), job.result["rescue-1"]["output"]["stdout"]);
+        assert_eq!(json!("always\n"), job.result["always-1"]["output"]["stdout"]);
+        assert_eq!(Some(&json!(PluginStatus::Ok)), job.result["group-1"].get("status"));
+        assert!(job.context.get("failed_task").is_none());
+
+        // Without rescue, the group fails but always tasks are run
+        group.rescue.clear();
+
+        job.result.clear();
+        job.tasks = vec![group.clone()];
+        job.run(None, None).await.unwrap();
+
+        assert_eq!(Status::Ko, job.status);
+        assert_eq!(json!({ "status": PluginStatus::Ko, "error": "task block-1 failed", "output": {} }), job.result["group-1"]);
+        assert!(job.result.contains_key("always-1"));
+        assert_eq!(Some("block-1".to_string()), job.failed_task);
+    }
+
+    #[test]
+    fn test_find_resume_task() {
+        let task = |name: &str, on_success: &str| Task {
+            name: name.to_string(),
+            plugin: "builtin-shell".to_string(),
+            on_success: on_success.to_string(),
+            ..Default::default()
+        };
+
+        let group = Task {
+            name: "group-1".to_string(),
+            block: vec![task("block-1", "block-2"), task("block-2", "block-3"), task("block-3", "")],
+            rescue: vec![task("rescue-1", "")],
+            ..Default::default()
+        };
+        let tasks = vec![task("task-1", "group-1"), group.clone()];
+
+        assert_eq!("task-1", find_resume_task(&tasks, "task-1").unwrap().name);
+
+        // The block restarts from its failed task
+        let start = find_resume_task(&tasks, "block-2").unwrap();
+        let block: Vec<&str> = start.block.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!("group-1", start.name);
+        assert_eq!(vec!["block-2", "block-1", "block-3"], block);
+
+        assert_eq!(group.block.len(), find_resume_task(&tasks, "rescue-1").unwrap().block.len());
+        assert!(find_resume_task(&tasks, "task-2").is_none());
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let mut task = Task::default();
+        assert_eq!(Duration::from_millis(1000), retry_delay(&task, 1));
+
+        task.delay = Some(100);
+        task.backoff = Some(2.0);
+        assert_eq!(Duration::from_millis(100), retry_delay(&task, 1));
+        assert_eq!(Duration::from_millis(400), retry_delay(&task, 3));
+
+        task.jitter = Some(0.5);
+        for _ in 0..10 {
+            let d = retry_delay(&task, 2);
+            assert!(d >= Duration::from_millis(100) && d <= Duration::from_millis(300), "{:?}", d);
+        }
+    }
+
     #[tokio::test]
     async fn test_run_task_by_task() {
         let _ =  env_logger::try_init();
@@ -923,7 +1710,8 @@ mod tests {
             params: params_task1.clone(),
             r#loop: None,
             on_success: "task-2".to_string(),
-            on_failure: "task-3".to_string()
+            on_failure: "task-3".to_string(),
+            ..Default::default()
         };
 
         let task2 = Task {
@@ -933,7 +1721,8 @@ mod tests {
             params: params_task2.clone(),
             r#loop: None,
             on_success: "task-4".to_string(),
-            on_failure: "task-4".to_string()
+            on_failure: "task-4".to_string(),
+            ..Default::default()
         };
 
         let task3 = Task {
@@ -943,7 +1732,8 @@ mod tests {
             params: params_task3.clone(),
             r#loop: None,
             on_success: "task-4".to_string(),
-            on_failure: "".to_string()
+            on_failure: "".to_string(),
+            ..Default::default()
         };
 
         let task4 = Task {
@@ -953,7 +1743,8 @@ mod tests {
             params: params_task4.clone(),
             r#loop: None,
             on_success: "".to_string(),
-            on_failure: "".to_string()
+            on_failure: "".to_string(),
+            ..Default::default()
         };
 
         job.tasks = vec![task1.clone(), task2.clone(), task3.clone(), task4.clone()];
@@ -1050,12 +1841,18 @@ mod tests {
             params: params_task1.clone(),
             r#loop: None,
             on_success: "task-2".to_string(),
-            on_failure: "task-3".to_string()
+            on_failure: "task-3".to_string(),
+            ..Default::default()
         };
 
         job.tasks = vec![task1.clone()];
         job.context.insert("variables".to_string(), vars);
 
+        // Templates are compiled once, then rendered for each call
+        assert!(job.render_task_template(&mut task1).is_err());
+        job.compile_templates().unwrap();
+        assert!(!job.templates().unwrap().is_empty());
+
         let mut vec_params = job.render_task_template(&mut task1).unwrap();
 
         // Expected result
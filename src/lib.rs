@@ -5,9 +5,19 @@ pub mod plugin;
 pub mod message;
 pub mod utils;
 pub mod flow;
+pub mod checkpoint;
+pub mod history;
+pub mod secret;
+pub mod condition;
+pub mod vault;
+mod definition;
 pub mod job;
 pub mod source;
 pub mod sink;
+pub mod template;
 pub mod datastore;
+pub mod inventory;
+pub mod remote;
+pub mod validate;
 pub mod test;
 mod tera;
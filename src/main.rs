@@ -8,22 +8,21 @@ use log::{info, error};
 
 use clap::{Arg, App};
 
+// Core modules come from the library so that builtin plugins compiled in the
+// binary share the same plugin registry and types as the runner.
+use flowrunner::{checkpoint, flow, history, plugin, remote, secret, utils, validate, vault};
 use crate::plugin::PluginRegistry;
 
 mod config;
 mod exec;
-#[macro_use]
-mod plugin;
-mod flow;
-mod job;
-mod source;
-mod sink;
-mod message;
-mod utils;
-mod datastore;
+mod plugins;
+mod runs;
+mod secrets;
+mod vars;
 mod server;
-mod tera;
 mod cron;
+#[cfg(feature = "static-plugins")]
+mod builtins;
 
 #[tokio::main]
 async fn main() {
@@ -56,7 +55,126 @@ async fn main() {
                                     .long("--flow-file")
                                     .short("f")
                                     .takes_value(true)
-                                    .help("Name of the flow file to execute")))
+                                    .help("Name of the flow file to execute"))
+                                .arg(Arg::with_name("check")
+                                    .long("--check")
+                                    .help("Show what the tasks would change without changing anything. Tasks whose plugin does not support it are skipped"))
+                                .arg(Arg::with_name("env")
+                                    .long("--env")
+                                    .takes_value(true)
+                                    .help("Override the flow variables with the ones of variables.ENV.yaml in the flow directory"))
+                                .arg(Arg::with_name("vars-file")
+                                    .long("--vars-file")
+                                    .takes_value(true)
+                                    .multiple(true)
+                                    .number_of_values(1)
+                                    .help("Override the flow variables with the ones of a file, after --env. Can be repeated"))
+                                .arg(Arg::with_name("var")
+                                    .long("--var")
+                                    .takes_value(true)
+                                    .multiple(true)
+                                    .number_of_values(1)
+                                    .help("Override a flow variable with KEY=VALUE, the value being parsed as YAML. Takes precedence over --env and --vars-file. Can be repeated"))
+                                .arg(Arg::with_name("payload")
+                                    .long("--payload")
+                                    .takes_value(true)
+                                    .help("User payload as JSON, or @FILE to read it from a JSON file")))
+                        .subcommand(
+                            App::new("resume")
+                                .about("Resume a failed run of a flow with checkpoint from its failed tasks")
+                                .arg(Arg::with_name("run-id")
+                                    .required(true)
+                                    .help("Identifier of the run to resume"))
+                                .arg(Arg::with_name("flow-file")
+                                    .long("--flow-file")
+                                    .short("f")
+                                    .takes_value(true)
+                                    .help("Name of the flow file of the run. By default, the run is searched in all flows")))
+                        .subcommand(
+                            App::new("runs")
+                                .about("Query the run history of flows")
+                                .subcommand(
+                                    App::new("list")
+                                        .about("List the runs, the most recent first")
+                                        .arg(Arg::with_name("flow")
+                                            .long("--flow")
+                                            .takes_value(true)
+                                            .help("Only list the runs of this flow"))
+                                        .arg(Arg::with_name("status")
+                                            .long("--status")
+                                            .takes_value(true)
+                                            .possible_values(&["running", "succeeded", "failed"])
+                                            .help("Only list the runs with this status"))
+                                        .arg(Arg::with_name("limit")
+                                            .long("--limit")
+                                            .takes_value(true)
+                                            .help("Maximum number of runs to list"))
+                                        .arg(Arg::with_name("json")
+                                            .long("--json")
+                                            .help("Print runs as JSON")))
+                                .subcommand(
+                                    App::new("show")
+                                        .about("Show the status, errors and outputs of the jobs and tasks of a run")
+                                        .arg(Arg::with_name("run-id")
+                                            .required(true)
+                                            .help("Identifier of the run"))
+                                        .arg(Arg::with_name("json")
+                                            .long("--json")
+                                            .help("Print the run as JSON"))))
+                        .subcommand(
+                            App::new("secrets")
+                                .about("Manage the secrets of encrypted keyfiles. The password is given by FLOWRUNNER_VAULT_PASSWORD or FLOWRUNNER_VAULT_PASSWORD_FILE")
+                                .subcommand(
+                                    App::new("set")
+                                        .about("Add or replace a secret in a keyfile with the value given on stdin")
+                                        .arg(Arg::with_name("keyfile")
+                                            .long("--keyfile")
+                                            .required(true)
+                                            .takes_value(true)
+                                            .help("Path of the keyfile, created if it does not exist"))
+                                        .arg(Arg::with_name("key")
+                                            .required(true)
+                                            .help("Key of the secret")))
+                                .subcommand(
+                                    App::new("list")
+                                        .about("List the keys of the secrets of a keyfile")
+                                        .arg(Arg::with_name("keyfile")
+                                            .long("--keyfile")
+                                            .required(true)
+                                            .takes_value(true)
+                                            .help("Path of the keyfile"))))
+                        .subcommand(
+                            App::new("vars")
+                                .about("Manage encrypted variable files. The password is given by FLOWRUNNER_VAULT_PASSWORD or FLOWRUNNER_VAULT_PASSWORD_FILE")
+                                .subcommand(
+                                    App::new("encrypt")
+                                        .about("Encrypt a variable file in place")
+                                        .arg(Arg::with_name("file")
+                                            .required(true)
+                                            .help("Path of the variable file")))
+                                .subcommand(
+                                    App::new("decrypt")
+                                        .about("Decrypt a variable file in place")
+                                        .arg(Arg::with_name("file")
+                                            .required(true)
+                                            .help("Path of the variable file"))
+                                        .arg(Arg::with_name("stdout")
+                                            .long("--stdout")
+                                            .help("Print the variables instead of writing them in the file")))
+                                .subcommand(
+                                    App::new("edit")
+                                        .about("Edit an encrypted variable file with VISUAL or EDITOR")
+                                        .arg(Arg::with_name("file")
+                                            .required(true)
+                                            .help("Path of the variable file"))))
+                        .subcommand(
+                            App::new("validate")
+                                .about("Validate a flow file and its plugin params without running it")
+                                .arg(Arg::with_name("flow-file")
+                                    .long("--flow-file")
+                                    .short("f")
+                                    .takes_value(true)
+                                    .help("Name of the flow file to validate")))
                         .subcommand(
                             App::new("server")
                                 .about("Launch a flow server that only take classic flows")
@@ -68,13 +186,40 @@ async fn main() {
                         .subcommand(
                             App::new("cron")
                                 .about("Launch a cron server to execute scheduled classic flows"))
+                        .subcommand(
+                            App::new("plugins")
+                                .about("List and describe the loaded plugins")
+                                .subcommand(
+                                    App::new("list")
+                                        .about("List the loaded plugins")
+                                        .arg(Arg::with_name("json")
+                                            .long("--json")
+                                            .help("Print plugins as JSON")))
+                                .subcommand(
+                                    App::new("info")
+                                        .about("Show the version, description, path and parameters of a plugin")
+                                        .arg(Arg::with_name("name")
+                                            .required(true)
+                                            .help("Name of the plugin"))
+                                        .arg(Arg::with_name("json")
+                                            .long("--json")
+                                            .help("Print the plugin as JSON"))))
+                        .subcommand(
+                            App::new("remote-job")
+                                .about("Execute a job received on stdin (used for remote execution)")
+                                .setting(clap::AppSettings::Hidden))
                         .get_matches();
 
-    env_logger::init();
+    // Values of secrets are masked in all logs
+    secret::init_logger();
 
     // Gets a value for config if supplied by user, or defaults to ".mgr.yaml"
     let config_file = matches.value_of("config");
-    let mut config = config::new(config_file.unwrap_or(".flowrunner.yaml")).unwrap();
+    let mut config = match matches.subcommand_name() {
+        // Remote hosts do not necessarily have a configuration file
+        Some("remote-job") => config::new(config_file.unwrap_or(".flowrunner.yaml")).unwrap_or_default(),
+        _ => config::new(config_file.unwrap_or(".flowrunner.yaml")).unwrap(),
+    };
     info!("--- Configuration ---");
     info!("File: {:?}", config_file);
     info!("Content: {:?}", config);
@@ -94,13 +239,33 @@ async fn main() {
     info!("--- Final configuration ---");
     info!("{:?}", config);
 
+    PluginRegistry::set_override_builtins(config.runner.override_builtin_plugins);
+
+    #[cfg(feature = "static-plugins")]
+    builtins::register_builtin_plugins();
+
     PluginRegistry::load_plugins(config.runner.plugin_dir.as_str()).await;
 
     match matches.subcommand() {
         ("exec", Some(exec_matches)) => {
             match exec::exec_cmd(&config, exec_matches).await {
                 Ok(()) => (),
-                Err(e) => { error!("{}", e.to_string()); },
+                Err(e) => {
+                    error!("{}", e.to_string());
+                    std::process::exit(1);
+                },
+            }
+        },
+        ("resume", Some(resume_matches)) => {
+            if let Err(e) = exec::resume_cmd(&config, resume_matches).await {
+                error!("{}", e.to_string());
+                std::process::exit(1);
+            }
+        },
+        ("validate", Some(validate_matches)) => {
+            if let Err(e) = exec::validate_cmd(&config, validate_matches) {
+                error!("{}", e.to_string());
+                std::process::exit(1);
             }
         },
         ("server", Some(server_matches)) => {
@@ -109,6 +274,36 @@ async fn main() {
                 Err(e) => { error!("{}", e.to_string()); },
             }
         },
+        ("runs", Some(runs_matches)) => {
+            if let Err(e) = runs::runs_cmd(&config, runs_matches) {
+                error!("{}", e.to_string());
+                std::process::exit(1);
+            }
+        },
+        ("secrets", Some(secrets_matches)) => {
+            if let Err(e) = secrets::secrets_cmd(secrets_matches) {
+                error!("{}", e.to_string());
+                std::process::exit(1);
+            }
+        },
+        ("vars", Some(vars_matches)) => {
+            if let Err(e) = vars::vars_cmd(vars_matches) {
+                error!("{}", e.to_string());
+                std::process::exit(1);
+            }
+        },
+        ("plugins", Some(plugins_matches)) => {
+            if let Err(e) = plugins::plugins_cmd(plugins_matches) {
+                error!("{}", e.to_string());
+                std::process::exit(1);
+            }
+        },
+        ("remote-job", _) => {
+            if let Err(e) = remote::remote_job_cmd().await {
+                error!("{}", e.to_string());
+                std::process::exit(1);
+            }
+        },
         ("cron", _) => {
             match cron::cron_run(&config).await {
                 Ok(()) => (),
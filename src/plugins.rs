@@ -0,0 +1,98 @@
+use clap::ArgMatches;
+
+use anyhow::{anyhow, Result};
+
+use crate::plugin::{PluginInfo, PluginRegistry};
+
+pub fn plugins_cmd(matches: &ArgMatches<'_>) -> Result<()> {
+    match matches.subcommand() {
+        ("list", Some(list_matches)) => {
+            let infos = PluginRegistry::list_plugins();
+
+            if list_matches.is_present("json") {
+                println!("{}", serde_json::to_string_pretty(&infos)?);
+                return Ok(());
+            }
+
+            println!("{}", format_plugin_list(&infos));
+        },
+        ("info", Some(info_matches)) => {
+            let name = info_matches.value_of("name").unwrap_or_default();
+            let info = PluginRegistry::get_plugin_info(name)
+                .ok_or_else(|| anyhow!("Plugin {} is not found", name))?;
+
+            if info_matches.is_present("json") {
+                println!("{}", serde_json::to_string_pretty(&info)?);
+                return Ok(());
+            }
+
+            println!("{}", format_plugin_info(&info)?);
+        },
+        _ => return Err(anyhow!("You must specify a plugins subcommand: list or info")),
+    }
+
+    Ok(())
+}
+
+// Formats plugins as a table with one plugin per line
+fn format_plugin_list(infos: &[PluginInfo]) -> String {
+    let header = ("NAME", "VERSION", "PATH", "DESCRIPTION");
+
+    let name_width = infos.iter().map(|i| i.name.len()).chain(std::iter::once(header.0.len())).max().unwrap_or_default();
+    let version_width = infos.iter().map(|i| i.version.len()).chain(std::iter::once(header.1.len())).max().unwrap_or_default();
+    let path_width = infos.iter().map(|i| i.path.len()).chain(std::iter::once(header.2.len())).max().unwrap_or_default();
+
+    let mut lines = vec![format!("{:nw$}  {:vw$}  {:pw$}  {}", header.0, header.1, header.2, header.3,
+                                 nw = name_width, vw = version_width, pw = path_width).trim_end().to_string()];
+
+    for i in infos.iter() {
+        lines.push(format!("{:nw$}  {:vw$}  {:pw$}  {}", i.name, i.version, i.path, i.description,
+                           nw = name_width, vw = version_width, pw = path_width).trim_end().to_string());
+    }
+
+    lines.join("\n")
+}
+
+fn format_plugin_info(info: &PluginInfo) -> Result<String> {
+    Ok(format!("Name:         {}\nVersion:      {}\nDescription:  {}\nPath:         {}\nBuiltin:      {}\nCore version: {}\nParams:\n{}\nParam schema:\n{}",
+               info.name, info.version, info.description, info.path, info.builtin, info.core_version,
+               serde_json::to_string_pretty(&info.params)?, serde_json::to_string_pretty(&info.param_schema)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{Map, json};
+
+    #[test]
+    fn test_format_plugin_list() {
+        let infos = vec![
+            PluginInfo {
+                name: "builtin-shell".to_string(),
+                version: "0.1.0".to_string(),
+                description: "Execute shell commands".to_string(),
+                path: "plugins/libbuiltin_shell.so".to_string(),
+                builtin: false,
+                core_version: "0.11.0".to_string(),
+                params: Map::new(),
+                param_schema: json!({ "type": "object" }),
+            },
+            PluginInfo {
+                name: "builtin-uri".to_string(),
+                version: "0.2.0".to_string(),
+                description: "".to_string(),
+                path: "builtin".to_string(),
+                builtin: true,
+                core_version: "0.11.0".to_string(),
+                params: Map::new(),
+                param_schema: json!({ "type": "object" }),
+            },
+        ];
+
+        let expected = r#"NAME           VERSION  PATH                         DESCRIPTION
+builtin-shell  0.1.0    plugins/libbuiltin_shell.so  Execute shell commands
+builtin-uri    0.2.0    builtin"#;
+
+        assert_eq!(expected, format_plugin_list(&infos));
+    }
+}
@@ -0,0 +1,376 @@
+use std::process::Stdio;
+
+use serde_json::{Map, Value, json};
+
+use anyhow::{anyhow, Result};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::process::Command;
+
+use log::*;
+
+use crate::inventory::Inventory;
+use crate::job::{Job, Status as JobStatus, Task};
+use crate::plugin::PluginRegistry;
+
+/// Settings needed to execute jobs on remote hosts
+#[derive(Default, Debug, Clone)]
+pub struct RemoteConfig {
+    pub inventory: Inventory,
+    pub plugin_dir: String,
+    pub exec_dir: String,
+}
+
+impl RemoteConfig {
+    pub fn new(inventory_file: &str, plugin_dir: &str, exec_dir: &str) -> Result<Self> {
+        let inventory = match inventory_file {
+            "" => Inventory::default(),
+            f => Inventory::new_from_file(f)?,
+        };
+
+        let exec_dir = match exec_dir {
+            "" => "/tmp/flowrunner".to_string(),
+            d => d.to_string(),
+        };
+
+        let plugin_dir = match plugin_dir {
+            "" => exec_dir.clone() + "/plugins",
+            d => d.to_string(),
+        };
+
+        Ok(RemoteConfig {
+            inventory,
+            plugin_dir,
+            exec_dir,
+        })
+    }
+}
+
+/// SSH connection to a remote host using the system `ssh` and `scp` binaries.
+///
+/// Connection settings are read from host variables: `ssh_host` (host name by default),
+/// `ssh_port` (22 by default), `ssh_user`, `ssh_private_key` and
+/// `ssh_strict_host_key_checking` (`accept-new` by default).
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct SshClient {
+    pub host: String,
+    pub port: u64,
+    pub user: String,
+    pub private_key: String,
+    pub strict_host_key_checking: String,
+}
+
+impl SshClient {
+    pub fn new(name: &str, vars: &Map<String, Value>) -> Self {
+        let get_str = |k: &str| -> Option<String> {
+            vars.get(k).and_then(|v| match v {
+                Value::String(s) => Some(s.clone()),
+                Value::Number(n) => Some(n.to_string()),
+                _ => None,
+            })
+        };
+
+        SshClient {
+            host: get_str("ssh_host").unwrap_or_else(|| name.to_string()),
+            port: get_str("ssh_port").and_then(|p| p.parse::<u64>().ok()).unwrap_or(22),
+            user: get_str("ssh_user").unwrap_or_default(),
+            private_key: get_str("ssh_private_key").unwrap_or_default(),
+            strict_host_key_checking: get_str("ssh_strict_host_key_checking").unwrap_or_else(|| "accept-new".to_string()),
+        }
+    }
+
+    fn destination(&self) -> String {
+        match self.user.as_str() {
+            "" => self.host.clone(),
+            u => format!("{}@{}", u, self.host),
+        }
+    }
+
+    fn common_args(&self) -> Vec<String> {
+        let mut args = vec![
+            "-o".to_string(), "BatchMode=yes".to_string(),
+            "-o".to_string(), format!("StrictHostKeyChecking={}", self.strict_host_key_checking),
+        ];
+
+        if !self.private_key.is_empty() {
+            args.push("-i".to_string());
+            args.push(self.private_key.clone());
+        }
+
+        args
+    }
+
+    fn ssh_args(&self, cmd: &str) -> Vec<String> {
+        let mut args = self.common_args();
+
+        args.push("-p".to_string());
+        args.push(self.port.to_string());
+        args.push(self.destination());
+        args.push(cmd.to_string());
+
+        args
+    }
+
+    fn scp_args(&self, local: &str, remote: &str) -> Vec<String> {
+        let mut args = self.common_args();
+
+        args.push("-P".to_string());
+        args.push(self.port.to_string());
+        args.push(local.to_string());
+        args.push(format!("{}:{}", self.destination(), remote));
+
+        args
+    }
+
+    /// Executes a command on the remote host, writes `input` on its stdin and returns its stdout.
+    pub async fn exec(&self, cmd: &str, input: Option<&[u8]>) -> Result<String> {
+        debug!("Executing remote command: host={}, cmd={}", self.host, cmd);
+
+        let mut child = Command::new("ssh")
+            .args(self.ssh_args(cmd))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        // Stdin is closed once written so that the remote command can terminate
+        if let Some(mut stdin) = child.stdin.take() {
+            if let Some(i) = input {
+                stdin.write_all(i).await?;
+            }
+        }
+
+        let output = child.wait_with_output().await?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        debug!("Remote command stderr: host={}, stderr={}", self.host, stderr);
+
+        if !output.status.success() {
+            return Err(anyhow!("Remote command failed: host={}, rc={:?}, stderr={}", self.host, output.status.code(), stderr.trim()));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    /// Copies a local file to the remote host
+    pub async fn upload(&self, local: &str, remote: &str) -> Result<()> {
+        debug!("Uploading file: host={}, local={}, remote={}", self.host, local, remote);
+
+        let output = Command::new("scp")
+            .args(self.scp_args(local, remote))
+            .output()
+            .await?;
+
+        if !output.status.success() {
+            return Err(anyhow!("Failed to upload {} to {}:{}: {}",
+                               local, self.host, remote, String::from_utf8_lossy(&output.stderr).trim()));
+        }
+
+        Ok(())
+    }
+}
+
+/// Executes a job on all hosts given by `job.hosts` and merges results per host.
+///
+/// Each host receives the flowrunner binary and the plugins used by the job's tasks,
+/// then runs the job with the hidden subcommand `remote-job`. The job result is a map
+/// of host names to `{status, result}` and the job is `Ko` if one host fails.
+pub async fn exec_job_remote(job: &mut Job, config: &RemoteConfig) -> Result<()> {
+    let hosts = config.inventory.resolve_hosts(&job.hosts)?;
+
+    info!("Executing remotely the job: job={}, hosts={:?}", job.name, hosts);
+
+    let futures: Vec<_> = hosts.iter().map(|h| {
+        let vars = config.inventory.get_host_vars(h);
+        let job_cloned = job.clone();
+
+        async move {
+            let res = exec_job_on_host(&job_cloned, h, &vars, config).await;
+            (h.clone(), res)
+        }
+    }).collect();
+
+    job.result.clear();
+
+    for (h, res) in futures::future::join_all(futures).await {
+        match res {
+            Ok(j) => {
+                if j.status == JobStatus::Ko {
+                    job.status = JobStatus::Ko;
+                }
+
+                job.result.insert(h, json!({
+                    "status": j.status,
+                    "result": j.result
+                }));
+            },
+            Err(e) => {
+                error!("Failed to execute job remotely: job={}, host={}, err={e}", job.name, h);
+                job.status = JobStatus::Ko;
+
+                job.result.insert(h, json!({
+                    "status": JobStatus::Ko,
+                    "error": e.to_string(),
+                    "result": {}
+                }));
+            },
+        }
+    }
+
+    Ok(())
+}
+
+async fn exec_job_on_host(job: &Job, host: &str, vars: &Map<String, Value>, config: &RemoteConfig) -> Result<Job> {
+    let client = SshClient::new(host, vars);
+
+    client.exec(&format!("mkdir -p {} {}", quote(&config.exec_dir), quote(&config.plugin_dir)), None).await?;
+
+    // Ship the runner and all the plugins needed by the job
+    let bin = std::env::current_exe()?;
+    let remote_bin = config.exec_dir.clone() + "/flowrunner";
+    client.upload(&bin.display().to_string(), &remote_bin).await?;
+
+    for p in task_plugins(&job.tasks).iter() {
+        // Builtin plugins are shipped within the runner
+        if PluginRegistry::is_builtin(p) {
+            continue;
+        }
+
+        let path = PluginRegistry::get_plugin_path(p)
+            .ok_or_else(|| anyhow!("Plugin {} is not found", p))?;
+        let file_name = std::path::Path::new(&path)
+            .file_name()
+            .map(|f| f.to_string_lossy().to_string())
+            .unwrap_or_else(|| p.clone());
+
+        client.upload(&path, &(config.plugin_dir.clone() + "/" + &file_name)).await?;
+    }
+
+    // The job is executed locally on the remote host
+    let mut remote_job = job.clone();
+    remote_job.hosts = "localhost".to_string();
+    remote_job.context.insert("host".to_string(), Value::String(host.to_string()));
+
+    let input = serde_json::to_vec(&remote_job)?;
+    let cmd = format!("cd {} && {} --plugin-dir {} remote-job",
+                      quote(&config.exec_dir), quote(&remote_bin), quote(&config.plugin_dir));
+
+    let output = client.exec(&cmd, Some(&input)).await?;
+
+    serde_json::from_str::<Job>(output.trim())
+        .map_err(|e| anyhow!("Cannot parse the job result returned by host {}: {}", host, e))
+}
+
+/// Runs a job read as JSON on stdin and writes the executed job as JSON on stdout.
+///
+/// This is the counterpart of `exec_job_remote` on the remote host.
+pub async fn remote_job_cmd() -> Result<()> {
+    let mut input = String::new();
+    tokio::io::stdin().read_to_string(&mut input).await?;
+
+    let mut job: Job = serde_json::from_str(&input)?;
+    job.compile_templates()?;
+
+    if let Err(e) = job.run(None, None).await {
+        error!("Failed to run the job: job={}, err={e}", job.name);
+        job.status = JobStatus::Ko;
+    }
+
+    let mut stdout = tokio::io::stdout();
+    stdout.write_all(serde_json::to_string(&job)?.as_bytes()).await?;
+    stdout.flush().await?;
+
+    Ok(())
+}
+
+// Plugins used by the tasks, including the tasks of groups
+fn task_plugins(tasks: &[Task]) -> Vec<String> {
+    let mut plugins: Vec<String> = Vec::new();
+
+    for t in tasks.iter() {
+        if t.is_group() {
+            for group_tasks in [&t.block, &t.rescue, &t.always] {
+                plugins.extend(task_plugins(group_tasks));
+            }
+        } else if !t.plugin.is_empty() {
+            plugins.push(t.plugin.clone());
+        }
+    }
+
+    plugins.sort();
+    plugins.dedup();
+
+    plugins
+}
+
+// Quotes a string to be used as an argument in a remote shell command
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ssh_client() {
+        let vars = json!({
+            "ssh_host": "127.0.0.1",
+            "ssh_port": 2222,
+            "ssh_user": "flowrunner",
+            "ssh_private_key": "/tmp/id_rsa"
+        });
+
+        let client = SshClient::new("web1", vars.as_object().unwrap());
+
+        assert_eq!(
+            vec!["-o", "BatchMode=yes", "-o", "StrictHostKeyChecking=accept-new", "-i", "/tmp/id_rsa",
+                 "-p", "2222", "flowrunner@127.0.0.1", "ls -la"],
+            client.ssh_args("ls -la")
+        );
+
+        assert_eq!(
+            vec!["-o", "BatchMode=yes", "-o", "StrictHostKeyChecking=accept-new", "-i", "/tmp/id_rsa",
+                 "-P", "2222", "/tmp/file", "flowrunner@127.0.0.1:/tmp/remote"],
+            client.scp_args("/tmp/file", "/tmp/remote")
+        );
+
+        let client = SshClient::new("web1", &Map::new());
+        assert_eq!(vec!["-o", "BatchMode=yes", "-o", "StrictHostKeyChecking=accept-new", "-p", "22", "web1", "ls"], client.ssh_args("ls"));
+
+        assert_eq!("'/tmp/it'\\''s'", quote("/tmp/it's"));
+    }
+
+    #[test]
+    fn test_task_plugins() {
+        let content = r#"
+name: flow1
+kind: action
+jobs:
+  - name: job1
+    hosts: web
+    tasks:
+    - builtin-shell:
+        params:
+          cmd: ls
+    - name: deploy
+      block:
+      - builtin-git:
+          params:
+            repo: https://github.com/flowrunner/flowrunner
+      rescue:
+      - name: rollback
+        block:
+        - custom-rollback:
+            params: {}
+      always:
+      - builtin-shell:
+          params:
+            cmd: echo done
+"#;
+
+        let flow = crate::flow::Flow::new_from_str(content).unwrap();
+
+        assert_eq!(vec!["builtin-git", "builtin-shell", "custom-rollback"], task_plugins(&flow.jobs[0].tasks));
+    }
+}
@@ -0,0 +1,158 @@
+use clap::ArgMatches;
+
+use anyhow::{anyhow, Result};
+
+use crate::checkpoint::RunStatus;
+use crate::config::Config;
+use crate::exec::open_history;
+use crate::history::{RunFilter, RunRecord};
+
+pub fn runs_cmd(config: &Config, matches: &ArgMatches<'_>) -> Result<()> {
+    let history = open_history(config)?
+        .ok_or_else(|| anyhow!("No run history is configured in the runner configuration (runner.history)"))?;
+
+    match matches.subcommand() {
+        ("list", Some(list_matches)) => {
+            let filter = RunFilter {
+                flow: list_matches.value_of("flow").map(|f| f.to_string()),
+                status: list_matches.value_of("status").map(|s| s.parse::<RunStatus>()).transpose()?,
+                limit: list_matches.value_of("limit").map(|l| l.parse::<usize>())
+                    .transpose()
+                    .map_err(|e| anyhow!("Limit is not valid: {}", e))?,
+            };
+
+            let runs = history.list(&filter)?;
+
+            if list_matches.is_present("json") {
+                println!("{}", serde_json::to_string_pretty(&runs)?);
+                return Ok(());
+            }
+
+            println!("{}", format_run_list(&runs));
+        },
+        ("show", Some(show_matches)) => {
+            let run = history.get(show_matches.value_of("run-id").unwrap_or_default())?;
+
+            if show_matches.is_present("json") {
+                println!("{}", serde_json::to_string_pretty(&run)?);
+                return Ok(());
+            }
+
+            println!("{}", format_run(&run));
+        },
+        _ => return Err(anyhow!("You must specify a runs subcommand: list or show")),
+    }
+
+    Ok(())
+}
+
+// Formats runs as a table with one run per line
+fn format_run_list(runs: &[RunRecord]) -> String {
+    let header = ["RUN_ID", "FLOW", "TRIGGER", "STATUS", "STARTED_AT", "ENDED_AT"];
+    let rows: Vec<[String; 6]> = runs.iter()
+        .map(|r| [r.run_id.clone(), r.flow.clone(), format!("{:?}", r.trigger), format!("{:?}", r.status), r.started_at.clone(), r.ended_at.clone()])
+        .collect();
+
+    let widths: Vec<usize> = (0..header.len())
+        .map(|i| rows.iter().map(|r| r[i].len()).chain(std::iter::once(header[i].len())).max().unwrap_or_default())
+        .collect();
+
+    let format_row = |cols: Vec<&str>| cols.iter().zip(widths.iter())
+        .map(|(c, w)| format!("{:w$}", c, w = w))
+        .collect::<Vec<String>>()
+        .join("  ")
+        .trim_end()
+        .to_string();
+
+    let mut lines = vec![format_row(header.to_vec())];
+    for r in rows.iter() {
+        lines.push(format_row(r.iter().map(|c| c.as_str()).collect()));
+    }
+
+    lines.join("\n")
+}
+
+// Formats a run with the status of its jobs and tasks
+fn format_run(run: &RunRecord) -> String {
+    let mut lines = vec![
+        format!("Run:        {}", run.run_id),
+        format!("Flow:       {}", run.flow),
+        format!("Trigger:    {:?}", run.trigger),
+        format!("Status:     {:?}", run.status),
+        format!("Started at: {}", run.started_at),
+        format!("Ended at:   {}", run.ended_at),
+    ];
+
+    if let Some(e) = &run.error {
+        lines.push(format!("Error:      {}", e));
+    }
+
+    lines.push("Jobs:".to_string());
+    for j in run.jobs.iter() {
+        match &j.failed_task {
+            Some(t) => lines.push(format!("  {}: {:?} (failed task: {})", j.name, j.status, t)),
+            None => lines.push(format!("  {}: {:?}", j.name, j.status)),
+        }
+
+        for (name, res) in j.tasks.iter() {
+            let status = res.get("status").and_then(|s| s.as_str()).unwrap_or_default();
+
+            match res.get("error").and_then(|e| e.as_str()).filter(|e| !e.is_empty()) {
+                Some(e) => lines.push(format!("    {}: {}: {}", name, status, e)),
+                None => lines.push(format!("    {}: {}", name, status)),
+            }
+        }
+    }
+
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{Map, json};
+
+    use crate::history::{JobRecord, Trigger};
+    use flowrunner::job::Status as JobStatus;
+
+    #[test]
+    fn test_format_run() {
+        let mut tasks = Map::new();
+        tasks.insert("task1".to_string(), json!({ "status": "Ok", "error": "" }));
+        tasks.insert("task2".to_string(), json!({ "status": "Ko", "error": "exit code 1" }));
+
+        let run = RunRecord {
+            run_id: "run1".to_string(),
+            flow: "flow1".to_string(),
+            trigger: Trigger::Cron,
+            status: RunStatus::Failed,
+            started_at: "2022-06-01T10:00:00.000Z".to_string(),
+            ended_at: "2022-06-01T10:00:01.000Z".to_string(),
+            error: None,
+            jobs: vec![JobRecord {
+                name: "job1".to_string(),
+                status: JobStatus::Ko,
+                failed_task: Some("task2".to_string()),
+                tasks,
+            }],
+        };
+
+        let expected = r#"RUN_ID  FLOW   TRIGGER  STATUS  STARTED_AT                ENDED_AT
+run1    flow1  Cron     Failed  2022-06-01T10:00:00.000Z  2022-06-01T10:00:01.000Z"#;
+
+        assert_eq!(expected, format_run_list(&[run.clone()]));
+
+        let expected = r#"Run:        run1
+Flow:       flow1
+Trigger:    Cron
+Status:     Failed
+Started at: 2022-06-01T10:00:00.000Z
+Ended at:   2022-06-01T10:00:01.000Z
+Jobs:
+  job1: Ko (failed task: task2)
+    task1: Ok
+    task2: Ko: exit code 1"#;
+
+        assert_eq!(expected, format_run(&run));
+    }
+}
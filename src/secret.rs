@@ -0,0 +1,281 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::{Map, Value};
+
+use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
+
+use crate::vault;
+
+/// Text replacing secret values
+pub const MASK: &str = "******";
+
+/// Minimum length of the variable values masked by `register_value`. Shorter values, such as
+/// `prod`, `true` or `5432`, would be replaced in every text which contains them.
+pub const MIN_VALUE_LENGTH: usize = 8;
+
+lazy_static! {
+    // Values of the secrets resolved by the process, masked in logs and outputs
+    static ref SECRET_VALUES: RwLock<HashSet<String>> = RwLock::new(HashSet::new());
+    // Decrypted keyfiles, read once per process
+    static ref KEYFILES: Mutex<HashMap<PathBuf, BTreeMap<String, String>>> = Mutex::new(HashMap::new());
+}
+
+/// Secret declared in the section `secrets` of a flow
+///
+/// The value is read from a provider when a template calls `secret(name="...")`:
+/// - `env`: the environment variable `key`
+/// - `file`: the content of the file `path`, without the trailing newline
+/// - `keyfile`: the entry `key` of the keyfile `path`, a YAML map encrypted with
+///   the password given by `FLOWRUNNER_VAULT_PASSWORD` or `FLOWRUNNER_VAULT_PASSWORD_FILE`
+#[derive(Default, Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SecretDef {
+    pub provider: String,
+    #[serde(default)]
+    pub key: Option<String>,
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+impl SecretDef {
+    pub fn validate(&self) -> Result<()> {
+        match (self.provider.as_str(), &self.key, &self.path) {
+            ("env", Some(_), None) | ("file", None, Some(_)) | ("keyfile", Some(_), Some(_)) => Ok(()),
+            ("env", _, _) => Err(anyhow!("provider env requires only a key")),
+            ("file", _, _) => Err(anyhow!("provider file requires only a path")),
+            ("keyfile", _, _) => Err(anyhow!("provider keyfile requires a key and a path")),
+            (p, _, _) => Err(anyhow!("provider {} is not supported, expected: env, file or keyfile", p)),
+        }
+    }
+
+    /// Reads the value of the secret from its provider
+    pub fn resolve(&self) -> Result<Secret> {
+        let key = self.key.clone().unwrap_or_default();
+        let path = self.path.clone().unwrap_or_default();
+
+        let value = match self.provider.as_str() {
+            "env" => std::env::var(&key)
+                .map_err(|_| anyhow!("environment variable {} is not set", key))?,
+            "file" => std::fs::read_to_string(&path)
+                .map_err(|e| anyhow!("cannot read the file {}: {}", path, e))?
+                .trim_end_matches(&['\r', '\n'][..])
+                .to_string(),
+            "keyfile" => read_keyfile(Path::new(&path))?
+                .get(&key)
+                .cloned()
+                .ok_or_else(|| anyhow!("key {} is not found in the keyfile {}", key, path))?,
+            p => return Err(anyhow!("provider {} is not supported", p)),
+        };
+
+        Ok(Secret::new(value))
+    }
+}
+
+/// Value of a secret. It is masked when it is formatted or serialized and it is registered
+/// to be masked in logs and outputs.
+#[derive(Clone, PartialEq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: String) -> Self {
+        register(&value);
+
+        Secret(value)
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Secret({})", MASK)
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", MASK)
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(MASK)
+    }
+}
+
+/// Registers a value to mask
+pub fn register(value: &str) {
+    if value.is_empty() {
+        return;
+    }
+
+    if let Ok(mut values) = SECRET_VALUES.write() {
+        values.insert(value.to_string());
+    }
+}
+
+/// Registers the strings of the value to mask, except the ones shorter than `MIN_VALUE_LENGTH`.
+/// It is used for variables, whose values are not all secrets.
+pub fn register_value(value: &Value) {
+    match value {
+        Value::String(s) if s.chars().count() >= MIN_VALUE_LENGTH => register(s),
+        Value::Array(a) => a.iter().for_each(register_value),
+        Value::Object(o) => o.values().for_each(register_value),
+        _ => (),
+    }
+}
+
+/// Replaces the values of the resolved secrets in the text
+pub fn mask(text: &str) -> String {
+    let values = match SECRET_VALUES.read() {
+        Ok(v) => v,
+        Err(_) => return text.to_string(),
+    };
+
+    // Longer values first, as a secret can contain another one
+    let mut sorted: Vec<&String> = values.iter().filter(|v| text.contains(v.as_str())).collect();
+    sorted.sort_by_key(|v| std::cmp::Reverse(v.len()));
+
+    sorted.iter().fold(text.to_string(), |t, v| t.replace(v.as_str(), MASK))
+}
+
+/// Replaces the values of the resolved secrets in all strings of the value
+pub fn mask_value(value: &Value) -> Value {
+    match value {
+        Value::String(s) => Value::String(mask(s)),
+        Value::Array(a) => Value::Array(a.iter().map(mask_value).collect()),
+        Value::Object(o) => Value::Object(o.iter().map(|(k, v)| (k.clone(), mask_value(v))).collect::<Map<String, Value>>()),
+        _ => value.clone(),
+    }
+}
+
+/// Returns the Tera function `secret(name="...")` reading the secrets `defs`
+pub fn tera_function(defs: BTreeMap<String, SecretDef>) -> impl tera::Function {
+    move |args: &HashMap<String, Value>| -> tera::Result<Value> {
+        let name = args.get("name")
+            .and_then(|n| n.as_str())
+            .ok_or_else(|| tera::Error::msg("secret: the argument name is required"))?;
+
+        let def = defs.get(name)
+            .ok_or_else(|| tera::Error::msg(format!("secret {} is not declared in the flow secrets", name)))?;
+
+        let secret = def.resolve()
+            .map_err(|e| tera::Error::msg(format!("secret {}: {}", name, e)))?;
+
+        Ok(Value::String(secret.expose().to_string()))
+    }
+}
+
+/// Initializes the logger configured by `RUST_LOG` with the values of secrets masked
+pub fn init_logger() {
+    env_logger::Builder::from_default_env()
+        .format(|buf, record| {
+            writeln!(buf, "[{} {:<5} {}] {}", buf.timestamp(), record.level(), record.target(), mask(&record.args().to_string()))
+        })
+        .init();
+}
+
+/// Reads the entries of an encrypted keyfile
+pub fn read_keyfile(path: &Path) -> Result<BTreeMap<String, String>> {
+    let mut keyfiles = KEYFILES.lock().map_err(|e| anyhow!("{}", e))?;
+
+    if let Some(k) = keyfiles.get(path) {
+        return Ok(k.clone());
+    }
+
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("cannot read the keyfile {}: {}", path.display(), e))?;
+    let plain = vault::decrypt(&content, &vault::password()?)
+        .map_err(|e| anyhow!("keyfile {}: {}", path.display(), e))?;
+
+    let entries: BTreeMap<String, String> = serde_yaml::from_slice(&plain)
+        .map_err(|e| anyhow!("keyfile {}: {}", path.display(), e))?;
+
+    for v in entries.values() {
+        register(v);
+    }
+
+    keyfiles.insert(path.to_path_buf(), entries.clone());
+
+    Ok(entries)
+}
+
+/// Writes the entries in an encrypted keyfile
+pub fn write_keyfile(path: &Path, entries: &BTreeMap<String, String>) -> Result<()> {
+    let content = vault::encrypt(serde_yaml::to_string(entries)?.as_bytes(), &vault::password()?)?;
+
+    std::fs::write(path, content)
+        .map_err(|e| anyhow!("cannot write the keyfile {}: {}", path.display(), e))?;
+
+    if let Ok(mut keyfiles) = KEYFILES.lock() {
+        keyfiles.remove(path);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    use crate::template::TemplateCache;
+
+    #[test]
+    fn test_secrets() {
+        let dir = std::env::temp_dir().join("flowrunner-test-secrets");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let _env = crate::utils::lock_env();
+        std::env::set_var("FLOWRUNNER_TEST_SECRET", "env-secret-1");
+        std::env::set_var(vault::PASSWORD_ENV, "vault-pass");
+        std::fs::write(dir.join("secret"), "file-secret-1\n").unwrap();
+
+        let mut entries = BTreeMap::new();
+        entries.insert("token".to_string(), "keyfile-secret-1".to_string());
+        write_keyfile(&dir.join("keyfile"), &entries).unwrap();
+
+        let defs: BTreeMap<String, SecretDef> = serde_json::from_value(json!({
+            "s1": { "provider": "env", "key": "FLOWRUNNER_TEST_SECRET" },
+            "s2": { "provider": "file", "path": dir.join("secret") },
+            "s3": { "provider": "keyfile", "key": "token", "path": dir.join("keyfile") },
+            "s4": { "provider": "env", "key": "FLOWRUNNER_TEST_SECRET_UNSET" },
+        })).unwrap();
+
+        for d in defs.values() {
+            d.validate().unwrap();
+        }
+
+        // Secrets of jobs run alone are read from their context
+        let mut data = Map::new();
+        data.insert("context".to_string(), json!({ "secrets": defs }));
+        let cache = TemplateCache::from_context(data["context"].as_object().unwrap()).unwrap();
+
+        let mut txt = r#"{{ secret(name="s1") }} {{ secret(name="s2") }} {{ secret(name="s3") }}"#.to_string();
+        cache.render_text("test", &mut txt, &data).unwrap();
+        assert_eq!("env-secret-1 file-secret-1 keyfile-secret-1", txt);
+
+        let mut txt = r#"{{ secret(name="s4") }}"#.to_string();
+        assert!(cache.render_text("test", &mut txt, &data).is_err());
+
+        assert_eq!(format!("conn_str: user:{}@host, {}", MASK, MASK), mask("conn_str: user:env-secret-1@host, keyfile-secret-1"));
+        assert_eq!(json!({ "output": [format!("{} ok", MASK)], "n": 1 }), mask_value(&json!({ "output": ["file-secret-1 ok"], "n": 1 })));
+
+        let secret = Secret::new("value".to_string());
+        assert_eq!(MASK, secret.to_string());
+        assert_eq!(format!("\"{}\"", MASK), serde_json::to_string(&secret).unwrap());
+
+        let def = SecretDef { provider: "file".to_string(), key: Some("k".to_string()), ..Default::default() };
+        assert_eq!("provider file requires only a path", def.validate().unwrap_err().to_string());
+    }
+}
@@ -0,0 +1,45 @@
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::Path;
+
+use clap::ArgMatches;
+
+use anyhow::{anyhow, Result};
+
+use crate::secret::{read_keyfile, write_keyfile};
+
+pub fn secrets_cmd(matches: &ArgMatches<'_>) -> Result<()> {
+    match matches.subcommand() {
+        ("set", Some(set_matches)) => {
+            let keyfile = Path::new(set_matches.value_of("keyfile").unwrap_or_default());
+            let key = set_matches.value_of("key").unwrap_or_default();
+
+            // The value is read on stdin so that it does not appear in the shell history
+            let mut value = String::new();
+            std::io::stdin().read_to_string(&mut value)?;
+            let value = value.trim_end_matches(&['\r', '\n'][..]).to_string();
+
+            if value.is_empty() {
+                return Err(anyhow!("The value of the secret {} must be given on stdin", key));
+            }
+
+            let mut entries = match keyfile.exists() {
+                true => read_keyfile(keyfile)?,
+                false => BTreeMap::new(),
+            };
+
+            entries.insert(key.to_string(), value);
+            write_keyfile(keyfile, &entries)?;
+        },
+        ("list", Some(list_matches)) => {
+            let keyfile = Path::new(list_matches.value_of("keyfile").unwrap_or_default());
+
+            for key in read_keyfile(keyfile)?.keys() {
+                println!("{}", key);
+            }
+        },
+        _ => return Err(anyhow!("You must specify a secrets subcommand: set or list")),
+    }
+
+    Ok(())
+}
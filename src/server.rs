@@ -9,20 +9,36 @@ use std::collections::HashMap;
 use std::net::{SocketAddr, SocketAddrV4};
 use std::sync::{Arc, RwLock};
 
+use serde::Deserialize;
 use serde_json::Value;
 
+use chrono::Utc;
+
 use axum::Router;
 use axum::routing::*;
-use axum::extract::{Path, Json, Extension};
+use axum::extract::{Path, Json, Extension, Query};
 use axum::response::IntoResponse;
 use axum::http::StatusCode;
 
 use crate::config::Config;
+use crate::exec::open_history;
 use crate::flow::{Flow, Kind};
+use crate::checkpoint::RunStatus;
+use crate::history::{History, RunFilter, Trigger};
+use crate::secret::{mask, mask_value};
 
 #[derive(Default)]
 struct State {
     flows: HashMap<String, Flow>,
+    history: Option<History>,
+}
+
+// Query parameters of the run list
+#[derive(Deserialize)]
+struct RunsQuery {
+    flow: Option<String>,
+    status: Option<String>,
+    limit: Option<usize>,
 }
 
 type SharedState = Arc<RwLock<State>>;
@@ -37,8 +53,15 @@ pub async fn server_run(config: &Config, matches: &ArgMatches<'_>) -> Result<()>
 
     let paths = fs::read_dir(config.runner.flow_dir.clone()).unwrap_or_else(|_| panic!("Cannot read files in the directory {}", config.runner.flow_dir));
     for path in paths {
-        if let Some(p) = path?.path().to_str() {
-            let flow = Flow::new_from_file(p)?;
+        let path = path?.path();
+
+        // Subdirectories hold files included in flows
+        if !path.is_file() {
+            continue;
+        }
+
+        if let Some(p) = path.file_name().and_then(|f| f.to_str()) {
+            let flow = Flow::new_from_flow_dir(&config.runner.flow_dir, p)?;
 
             debug!("{:#?}", flow);
             if flow.kind == Kind::Action {
@@ -52,11 +75,15 @@ pub async fn server_run(config: &Config, matches: &ArgMatches<'_>) -> Result<()>
         }
     }
 
-    let state = State {flows};
+    let history = open_history(config)?;
+
+    let state = State {flows, history};
     let shared_state: SharedState = Arc::new(RwLock::new(state));
     // Build our application with a route
     let app = Router::new()
         .route("/flows/:flow", post(handler))
+        .route("/runs", get(list_runs))
+        .route("/runs/:run_id", get(get_run))
         .layer(Extension(shared_state));
 
     // run our app with hyper
@@ -91,14 +118,62 @@ async fn handler(
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
 
     let mut flows = state.write().unwrap().flows.clone();
+    let history = state.read().unwrap().history.clone();
+
     if let Some(f) = flows.get_mut(&flow) {
         f.user_payload = payload.clone();
-        if let Err(e) = f.run().await {
-            return Err((StatusCode::INTERNAL_SERVER_ERROR, format!("{{\"error\": {}}}", e)));
+
+        let started_at = Utc::now();
+        let result = f.run().await;
+        if let Some(h) = &history {
+            h.record_flow(f, Trigger::Server, &started_at, &result);
+        }
+
+        if let Err(e) = result {
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, format!("{{\"error\": {}}}", mask(&e.to_string()))));
         }
 
-        return Ok(Json(f.jobs.clone()));
+        // Registered variables and contexts may hold rendered secrets
+        let jobs = serde_json::to_value(&f.jobs)
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("{{\"error\": {}}}", e)))?;
+
+        return Ok(Json(mask_value(&jobs)));
     }
 
     Err((StatusCode::NOT_FOUND, format!("{{\"error\": flow {} not found}}", flow)))
 }
+
+async fn list_runs(
+    Query(query): Query<RunsQuery>,
+    Extension(state): Extension<SharedState>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let history = get_history(&state)?;
+
+    let status = query.status.map(|s| s.parse::<RunStatus>())
+        .transpose()
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("{{\"error\": {}}}", e)))?;
+
+    let filter = RunFilter { flow: query.flow, status, limit: query.limit };
+
+    match history.list(&filter) {
+        Ok(runs) => Ok(Json(runs)),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, format!("{{\"error\": {}}}", e))),
+    }
+}
+
+async fn get_run(
+    Path(run_id): Path<String>,
+    Extension(state): Extension<SharedState>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let history = get_history(&state)?;
+
+    match history.get(&run_id) {
+        Ok(run) => Ok(Json(run)),
+        Err(e) => Err((StatusCode::NOT_FOUND, format!("{{\"error\": {}}}", e))),
+    }
+}
+
+fn get_history(state: &SharedState) -> Result<History, (StatusCode, String)> {
+    state.read().unwrap().history.clone()
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "{\"error\": no run history configured}".to_string()))
+}
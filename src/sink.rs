@@ -6,15 +6,15 @@ use serde_json::Value;
 //use tokio::sync::mpsc::*;
 //use futures::lock::Mutex;
 use async_channel::*;
+use std::sync::Arc;
 
-use anyhow::{Result, anyhow};
-
-use evalexpr::*;
+use anyhow::Result;
 
 use log::*;
 
 use crate::plugin::{PluginRegistry, Status as PluginStatus};
 use crate::message::Message as FlowMessage;
+use crate::template::TemplateCache;
 use crate::utils::*;
 
 #[derive(Default, Debug, Serialize, Deserialize, Clone)]
@@ -27,13 +27,23 @@ pub struct Sink {
     pub plugin: String,
     #[serde(default)]
 	pub params: Map<String, Value>,
+    // Jobs sending their results to the sink, all jobs if empty
+    #[serde(default)]
+    pub from: Vec<String>,
 
     #[serde(default)]
 	pub context: Map<String, Value>,
     #[serde(skip_serializing, skip_deserializing)]
 	pub rx: Vec<Sender<FlowMessage>>,
     #[serde(skip_serializing, skip_deserializing)]
-	pub tx: Vec<Receiver<FlowMessage>>
+	pub tx: Vec<Receiver<FlowMessage>>,
+    // Capacity of the channel of messages received from jobs
+    #[serde(default)]
+    pub channel_capacity: Option<usize>,
+
+    // Compiled templates shared with the flow
+    #[serde(skip_serializing, skip_deserializing)]
+    pub templates: Option<Arc<TemplateCache>>,
 }
 
 impl PartialEq for Sink {
@@ -41,13 +51,20 @@ impl PartialEq for Sink {
         self.name == other.name &&
         self.plugin == other.plugin &&
         self.params == other.params &&
+        self.from == other.from &&
         self.context == other.context
     }
 }
 
 impl Sink {
+    /// Returns true if the sink receives the results of the job
+    pub fn receives_from(&self, job: &str) -> bool {
+        self.from.is_empty() || self.from.iter().any(|j| j == job)
+    }
+
     pub async fn run(&mut self) -> Result<()> {
         info!("SINK RUN STARTED: name {}, plugin {}, params: {:?}, nb tx: {}", self.name, self.plugin, self.params, self.tx.len());
+        TemplateCache::get_or_create(&mut self.templates, &self.context)?;
 
         if !self.tx.is_empty() {
             loop {
@@ -123,26 +140,20 @@ impl Sink {
 
         expand_env_map(&mut data);
 
-        if let Some(mut txt) = sink.r#if.clone() {
-            // Expand job if condition
-            render_text_template(component, &mut txt, &data)?;
+        let templates = TemplateCache::shared(&self.templates, &self.name)?;
 
-            match eval_boolean(&txt) {
-                Ok(b) => {
-                    if !b {
-                        debug!("{}", format!("sink ignored: {}, if: {:?}, eval: {:?}", component, txt, b));
-                        return Ok(false);
-                    }
+        if let Some(cond) = sink.r#if.as_ref() {
+            if !templates.eval_condition(component, cond, &data)? {
+                debug!("{}", format!("sink ignored: {}, if: {:?}, eval: false", component, cond));
+                return Ok(false);
+            }
 
-                    return Ok(true)
-                },
-                Err(e) => return Err(anyhow!(e)),
-            };
+            return Ok(true)
         }
 
         // Expand task's params
         for (n, v) in sink.params.clone().into_iter() {
-            sink.params.insert(n.to_string(), render_value_template(sink.name.as_str(), &n, &v, &data)?);
+            sink.params.insert(n.to_string(), templates.render_value(sink.name.as_str(), &n, &v, &data)?);
         }
 
         Ok(true)
@@ -6,6 +6,7 @@ use serde_json::Value;
 //use tokio::sync::mpsc::*;
 //use futures::lock::Mutex;
 use async_channel::*;
+use std::sync::Arc;
 
 use anyhow::Result;
 
@@ -13,6 +14,7 @@ use log::*;
 
 use crate::plugin::{PluginRegistry, Status as PluginStatus};
 use crate::message::Message as FlowMessage;
+use crate::template::TemplateCache;
 use crate::utils::*;
 
 #[derive(Default, Debug, Serialize, Deserialize, Clone)]
@@ -29,7 +31,14 @@ pub struct Source {
     #[serde(skip_serializing, skip_deserializing)]
 	pub rx: Vec<Sender<FlowMessage>>,
     #[serde(skip_serializing, skip_deserializing)]
-	pub tx: Vec<Receiver<FlowMessage>>
+	pub tx: Vec<Receiver<FlowMessage>>,
+    // Capacity of the channel of messages received from jobs
+    #[serde(default)]
+    pub channel_capacity: Option<usize>,
+
+    // Compiled templates shared with the flow
+    #[serde(skip_serializing, skip_deserializing)]
+    pub templates: Option<Arc<TemplateCache>>,
 }
 
 impl PartialEq for Source {
@@ -41,8 +50,17 @@ impl PartialEq for Source {
 }
 
 impl Source {
+    /// Returns true if the source also receives the results of the jobs which read from it,
+    /// such as the http server which replies to the requests
+    pub fn is_also_sink(&self) -> bool {
+        self.params.get("is_also_sink")
+            .and_then(|v| v.as_bool())
+            .unwrap_or_default()
+    }
+
     pub async fn run(&mut self) -> Result<()> {
         info!("SOURCE RUN STARTED: name {}, plugin {}, params: {:?}, nb rx: {}", self.name, self.plugin, self.params, self.rx.len());
+        TemplateCache::get_or_create(&mut self.templates, &self.context)?;
         let mut s = self.clone();
 
         if !self.render_template(&mut s)? {
@@ -83,9 +101,11 @@ impl Source {
 
         expand_env_map(&mut data);
 
+        let templates = TemplateCache::shared(&self.templates, &self.name)?;
+
         // Expand task's params
         for (n, v) in source.params.clone().into_iter() {
-            source.params.insert(n.to_string(), render_value_template(source.name.as_str(), &n, &v, &data)?);
+            source.params.insert(n.to_string(), templates.render_value(source.name.as_str(), &n, &v, &data)?);
         }
 
         Ok(true)
@@ -0,0 +1,329 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use serde_json::{Map, Value};
+
+use anyhow::{anyhow, Result};
+use log::*;
+
+use envmnt::{ExpandOptions, ExpansionType};
+use tera::{Context, ErrorKind, Tera};
+
+use crate::condition::Condition;
+use crate::datastore::store::StoreConfig;
+use crate::secret::{self, SecretDef};
+use crate::tera::register_library;
+use crate::utils::expand_env_value;
+
+/// Templates and conditions of a flow, parsed once and shared by its jobs, sources and sinks.
+///
+/// Each template is compiled under its own content as name. The templates of a flow are
+/// compiled when it is loaded, the other ones the first time they are rendered. Strings
+/// without template tags are returned as they are without going through Tera. Conditions
+/// are cached the same way.
+pub struct TemplateCache {
+    tera: RwLock<Tera>,
+    conditions: RwLock<HashMap<String, Arc<Condition>>>,
+}
+
+impl TemplateCache {
+    /// Creates an empty cache with the functions and filters of flowrunner. The function
+    /// `secret` reads the secrets `secrets` and `datastore_get` the store `datastore`.
+    pub fn new(secrets: &BTreeMap<String, SecretDef>, datastore: Option<&StoreConfig>) -> Self {
+        let mut tera = Tera::default();
+        // Template names are their content, which must never be escaped
+        tera.autoescape_on(vec![]);
+        register_library(&mut tera, datastore.cloned());
+        tera.register_function("secret", secret::tera_function(secrets.clone()));
+
+        TemplateCache {
+            tera: RwLock::new(tera),
+            conditions: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Creates an empty cache for the secrets declared in `context.secrets`
+    pub fn from_context(context: &Map<String, Value>) -> Result<Self> {
+        let secrets: BTreeMap<String, SecretDef> = match context.get("secrets") {
+            Some(s) => serde_json::from_value(s.clone())?,
+            None => BTreeMap::new(),
+        };
+
+        Ok(TemplateCache::new(&secrets, None))
+    }
+
+    /// Returns the cache of a component, after creating it for the secrets of the context
+    /// when the component does not run in a flow. The cache is then kept by the component.
+    pub fn get_or_create(templates: &mut Option<Arc<TemplateCache>>, context: &Map<String, Value>) -> Result<Arc<TemplateCache>> {
+        if let Some(t) = templates {
+            return Ok(t.clone());
+        }
+
+        let created = Arc::new(TemplateCache::from_context(context)?);
+        *templates = Some(created.clone());
+
+        Ok(created)
+    }
+
+    /// Returns the cache of a component, set by its flow or by `get_or_create`
+    pub fn shared(templates: &Option<Arc<TemplateCache>>, component: &str) -> Result<Arc<TemplateCache>> {
+        templates.clone().ok_or_else(|| anyhow!("{}: templates are not compiled", component))
+    }
+
+    /// Compiles all strings of the value which are templates, after the expansion of
+    /// the environment variables. Invalid templates are skipped: their errors are
+    /// reported when they are rendered.
+    pub fn compile(&self, value: &Value) -> Result<()> {
+        let mut templates: Vec<String> = Vec::new();
+        collect_templates(&expand_env_value(value), &mut templates);
+
+        let mut tera = self.tera.write().map_err(|e| anyhow!("{}", e))?;
+        let compiled: HashSet<&str> = tera.get_template_names().collect();
+        templates.retain(|t| !compiled.contains(t.as_str()));
+        templates.sort();
+        templates.dedup();
+
+        if tera.add_raw_templates(templates.iter().map(|t| (t.as_str(), t.as_str()))).is_ok() {
+            return Ok(());
+        }
+
+        for t in templates.iter() {
+            if let Err(e) = tera.add_raw_template(t, t) {
+                debug!("Template not compiled: template={:?}, err={}", t, e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Parses the condition if it is not in the cache yet
+    pub fn compile_condition(&self, cond: &str) -> Result<Arc<Condition>> {
+        if let Some(c) = self.conditions.read().map_err(|e| anyhow!("{}", e))?.get(cond) {
+            return Ok(c.clone());
+        }
+
+        let condition = Arc::new(Condition::parse(cond)?);
+        self.conditions.write().map_err(|e| anyhow!("{}", e))?.insert(cond.to_string(), condition.clone());
+
+        Ok(condition)
+    }
+
+    /// Returns the number of compiled templates
+    pub fn len(&self) -> usize {
+        self.tera.read().map(|t| t.get_template_names().count()).unwrap_or_default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Renders all strings of the value
+    pub fn render_value(&self, component: &str, key: &str, value: &Value, data: &Map<String, Value>) -> Result<Value> {
+        debug!(
+            "Rendering value templating: component {}, key {}, value {:?}, data {:?}",
+            component, key, value, data
+        );
+
+        let context = new_context(data)?;
+
+        self.render_value_with(&expand_env_value(value), &context)
+    }
+
+    /// Renders the text in place
+    pub fn render_text(&self, component: &str, text: &mut String, data: &Map<String, Value>) -> Result<()> {
+        debug!(
+            "Rendering text templating: component {}, text {}, data {:?}",
+            component, text, data
+        );
+
+        let mut options = ExpandOptions::new();
+        options.expansion_type = Some(ExpansionType::UnixBracketsWithDefaults);
+
+        let context = new_context(data)?;
+        let rendered = self.render_str(&envmnt::expand(text, Some(options)), &context)?;
+
+        text.clear();
+        text.insert_str(0, rendered.as_str());
+
+        Ok(())
+    }
+
+    /// Renders the items of a loop: either an array whose strings are rendered, or a
+    /// string rendered to a JSON array
+    pub fn render_loop(&self, component: &str, value: &Value, data: &Map<String, Value>) -> Result<Value> {
+        debug!(
+            "Rendering loop templating: component {}, value {}, data {:?}",
+            component, value, data
+        );
+
+        let context = new_context(data)?;
+
+        match expand_env_value(value) {
+            Value::Array(arr) => {
+                let mut v: Vec<Value> = vec![];
+                for e in arr.into_iter() {
+                    match e.as_str() {
+                        Some(s) => v.push(Value::String(self.render_str(s, &context)?)),
+                        None => v.push(e),
+                    }
+                }
+
+                Ok(Value::Array(v))
+            }
+            Value::String(s) => {
+                let s1 = self.render_str(&s, &context)?;
+                let val: Value = serde_json::from_str(s1.as_str())?;
+
+                debug!("render_loop_template: s: {:?},  val: {:?}", s1, val);
+
+                if val.as_array().is_none() {
+                    return Err(anyhow!("Value must be an array"));
+                }
+
+                Ok(val)
+            }
+            _ => Err(anyhow!("Loop must be a array of items or template string")),
+        }
+    }
+
+    /// Evaluates the condition against the data. It is not rendered: values of the data are
+    /// never parsed as a part of the condition.
+    pub fn eval_condition(&self, component: &str, cond: &str, data: &Map<String, Value>) -> Result<bool> {
+        debug!("Evaluating condition: component {}, cond {}, data {:?}", component, cond, data);
+
+        self.compile_condition(cond)?.eval(data)
+    }
+
+    fn render_value_with(&self, value: &Value, context: &Context) -> Result<Value> {
+        match value {
+            Value::Array(arr) => Ok(Value::Array(arr.iter()
+                .map(|e| self.render_value_with(e, context))
+                .collect::<Result<Vec<Value>>>()?)),
+            Value::Object(map) => Ok(Value::Object(map.iter()
+                .map(|(k, v)| Ok((k.to_string(), self.render_value_with(v, context)?)))
+                .collect::<Result<Map<String, Value>>>()?)),
+            Value::Null | Value::Bool(_) | Value::Number(_) => Ok(value.to_owned()),
+            Value::String(s) => Ok(Value::String(self.render_str(s, context)?)),
+        }
+    }
+
+    // Renders the compiled template, which is compiled first if it is not in the cache
+    fn render_str(&self, template: &str, context: &Context) -> Result<String> {
+        if !is_template(template) {
+            return Ok(template.to_string());
+        }
+
+        match self.tera.read().map_err(|e| anyhow!("{}", e))?.render(template, context) {
+            Err(e) if matches!(&e.kind, ErrorKind::TemplateNotFound(n) if n == template) => (),
+            res => return res.map_err(|e| anyhow!(e)),
+        }
+
+        self.tera.write().map_err(|e| anyhow!("{}", e))?.add_raw_template(template, template)?;
+
+        self.tera.read().map_err(|e| anyhow!("{}", e))?
+            .render(template, context)
+            .map_err(|e| anyhow!(e))
+    }
+}
+
+impl fmt::Debug for TemplateCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TemplateCache")
+            .field("templates", &self.len())
+            .finish()
+    }
+}
+
+// Compiled templates are derived from the flow definition, so they are not compared
+impl PartialEq for TemplateCache {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+/// Returns true if the string contains template tags
+pub fn is_template(s: &str) -> bool {
+    s.contains("{{") || s.contains("{%") || s.contains("{#")
+}
+
+fn collect_templates(value: &Value, templates: &mut Vec<String>) {
+    match value {
+        Value::String(s) if is_template(s) => templates.push(s.to_string()),
+        Value::Array(a) => a.iter().for_each(|v| collect_templates(v, templates)),
+        Value::Object(o) => o.values().for_each(|v| collect_templates(v, templates)),
+        _ => (),
+    }
+}
+
+fn new_context(data: &Map<String, Value>) -> Result<Context> {
+    Context::from_serialize(data).map_err(|e| anyhow!(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_template_cache() {
+        let cache = TemplateCache::new(&BTreeMap::new(), None);
+        let n = cache.len();
+
+        cache.compile(&json!({
+            "cmd": "echo {{ context.variables.name }}",
+            "args": ["-n", "{{ context.variables.count + 1 }}", "{% if true %}yes{% endif %}"],
+            "plain": "no template",
+            "invalid": "{{ unclosed",
+        })).unwrap();
+
+        assert_eq!(n + 3, cache.len());
+
+        let mut data = Map::new();
+        data.insert("context".to_string(), json!({ "variables": { "name": "flow1", "count": 1 } }));
+
+        let value = json!({
+            "cmd": "echo {{ context.variables.name }}",
+            "args": ["-n", "{{ context.variables.count + 1 }}", "{% if true %}yes{% endif %}"],
+            "plain": "<no template>.html",
+            "n": 1,
+        });
+
+        let expected = json!({
+            "cmd": "echo flow1",
+            "args": ["-n", "2", "yes"],
+            "plain": "<no template>.html",
+            "n": 1,
+        });
+
+        assert_eq!(expected, cache.render_value("task1", "params", &value, &data).unwrap());
+
+        // Templates which are not compiled yet are compiled when they are rendered
+        let mut txt = "{{ context.variables.name }} <b>".to_string();
+        cache.render_text("task1", &mut txt, &data).unwrap();
+        assert_eq!("flow1 <b>", txt);
+        assert_eq!(n + 4, cache.len());
+
+        let mut txt = "{{ unclosed".to_string();
+        assert!(cache.render_text("task1", &mut txt, &data).is_err());
+        assert_eq!(n + 4, cache.len());
+
+        assert_eq!(json!(["a", "flow1"]), cache.render_loop("task1", &json!(["a", "{{ context.variables.name }}"]), &data).unwrap());
+        assert_eq!(json!([1, 2]), cache.render_loop("task1", &json!("[1, {{ context.variables.count + 1 }}]"), &data).unwrap());
+    }
+
+    #[test]
+    fn test_eval_condition() {
+        let cache = TemplateCache::new(&BTreeMap::new(), None);
+
+        let mut data = Map::new();
+        data.insert("context".to_string(), json!({ "variables": { "name": r#"x" || true || ""# } }));
+
+        assert!(!cache.eval_condition("task1", r#"context.variables.name == "admin""#, &data).unwrap());
+        assert!(cache.eval_condition("task1", "{{ context.variables.name == 'admin' }}", &data).is_err());
+
+        let c = cache.compile_condition("len(context.variables.name) > 1").unwrap();
+        assert!(Arc::ptr_eq(&c, &cache.compile_condition("len(context.variables.name) > 1").unwrap()));
+        assert!(c.eval(&data).unwrap());
+    }
+}
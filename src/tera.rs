@@ -1,12 +1,374 @@
 use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::Mutex;
 
 use serde_json::value::Value;
 
-use tera::Result;
+use chrono::{DateTime, Duration, FixedOffset, Local, TimeZone, Utc};
+use chrono_tz::Tz;
+use lazy_static::lazy_static;
+use openssl::base64::{decode_block, encode_block};
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use rand::Rng;
+use regex::Regex;
+use tera::{Error, Result, Tera};
 use uuid::Uuid;
 
+use crate::datastore::store::StoreConfig;
+
+// Functions and filters available in all flow templates, in addition to the Tera builtins:
+//
+// - functions: `generate_uuid()`, `now(tz, format, utc, timestamp)`, `env(name, default)`,
+//   `random_password(length, symbols)`, `datastore_get(ns, key, default)`
+// - filters: `from_json`, `from_yaml`, `to_yaml`, `b64encode`, `b64decode`, `sha256`,
+//   `hmac_sha256(key)`, `date_add(days, hours, minutes, seconds)`, `regex_match(pattern)`,
+//   `regex_replace(pattern, rep)`, `regex_captures(pattern)`
+//
+// ```yaml
+// params:
+//   cmd: echo {{ now(tz="Europe/Paris") | date_add(days=-1) }}
+//   token: "{{ context.variables.payload | to_yaml | b64encode }}"
+//   version: "{{ datastore_get(ns='deploy', key='version', default='1.0') }}"
+// ```
+
+lazy_static! {
+    // Regexes of templates, compiled once per process
+    static ref REGEXES: Mutex<HashMap<String, Regex>> = Mutex::new(HashMap::new());
+}
+
+/// Registers the functions and filters of flowrunner. `datastore_get` reads the store
+/// of the flow, if any.
+pub fn register_library(tera: &mut Tera, datastore: Option<StoreConfig>) {
+    tera.register_function("generate_uuid", generate_uuid);
+    tera.register_function("now", now);
+    tera.register_function("env", env);
+    tera.register_function("random_password", random_password);
+    tera.register_function("datastore_get", move |args: &HashMap<String, Value>| datastore_get(datastore.as_ref(), args));
+
+    tera.register_filter("from_json", from_json);
+    tera.register_filter("from_yaml", from_yaml);
+    tera.register_filter("to_yaml", to_yaml);
+    tera.register_filter("b64encode", b64encode);
+    tera.register_filter("b64decode", b64decode);
+    tera.register_filter("sha256", sha256);
+    tera.register_filter("hmac_sha256", hmac_sha256);
+    tera.register_filter("date_add", date_add);
+    tera.register_filter("regex_match", regex_match);
+    tera.register_filter("regex_replace", regex_replace);
+    tera.register_filter("regex_captures", regex_captures);
+}
+
 pub fn generate_uuid(_args: &HashMap<String, Value>) -> Result<Value> {
     let uuid = Uuid::new_v4();
 
     Ok(Value::String(uuid.to_hyphenated().to_string()))
 }
+
+/// Current date in RFC 3339, or formatted with `format`. The date is in the time zone `tz`,
+/// in UTC with `utc=true` or in the local time zone by default. `timestamp=true` returns
+/// the number of seconds since the epoch.
+fn now(args: &HashMap<String, Value>) -> Result<Value> {
+    let now = Utc::now();
+
+    if get_bool(args, "now", "timestamp")?.unwrap_or_default() {
+        return Ok(Value::from(now.timestamp()));
+    }
+
+    let format = get_str(args, "now", "format")?;
+
+    let date = match get_str(args, "now", "tz")? {
+        Some(tz) => {
+            let tz: Tz = tz.parse().map_err(|e| Error::msg(format!("now: {}", e)))?;
+            format_date(now.with_timezone(&tz), format)
+        },
+        None if get_bool(args, "now", "utc")?.unwrap_or_default() => format_date(now, format),
+        None => format_date(now.with_timezone(&Local), format),
+    };
+
+    Ok(Value::String(date))
+}
+
+/// Value of the environment variable `name`, or `default` if it is not set
+fn env(args: &HashMap<String, Value>) -> Result<Value> {
+    let name = get_str(args, "env", "name")?
+        .ok_or_else(|| Error::msg("env: the argument name is required"))?;
+
+    match (std::env::var(name), args.get("default")) {
+        (Ok(v), _) => Ok(Value::String(v)),
+        (Err(_), Some(d)) => Ok(d.clone()),
+        (Err(_), None) => Err(Error::msg(format!("env: environment variable {} is not set and has no default", name))),
+    }
+}
+
+/// Random password of `length` characters (16 by default) made of letters, digits and,
+/// unless `symbols=false`, symbols
+fn random_password(args: &HashMap<String, Value>) -> Result<Value> {
+    const ALPHANUMERIC: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+    const SYMBOLS: &str = "!#$%&()*+,-.:;<=>?@[]^_{}~";
+
+    let length = match args.get("length") {
+        Some(l) => l.as_u64().ok_or_else(|| Error::msg("random_password: length must be a positive integer"))? as usize,
+        None => 16,
+    };
+
+    let mut charset: Vec<char> = ALPHANUMERIC.chars().collect();
+    if get_bool(args, "random_password", "symbols")?.unwrap_or(true) {
+        charset.extend(SYMBOLS.chars());
+    }
+
+    let mut rng = rand::thread_rng();
+    let password: String = (0..length).map(|_| charset[rng.gen_range(0..charset.len())]).collect();
+
+    Ok(Value::String(password))
+}
+
+/// Value of `key` in the namespace `ns` of the flow datastore, or `default` if the key
+/// is not found
+fn datastore_get(datastore: Option<&StoreConfig>, args: &HashMap<String, Value>) -> Result<Value> {
+    let ns = get_str(args, "datastore_get", "ns")?
+        .ok_or_else(|| Error::msg("datastore_get: the argument ns is required"))?;
+    let key = get_str(args, "datastore_get", "key")?
+        .ok_or_else(|| Error::msg("datastore_get: the argument key is required"))?;
+
+    let store = datastore
+        .ok_or_else(|| Error::msg("datastore_get: no datastore is configured in the flow"))?
+        .new_store()
+        .map_err(|e| Error::msg(format!("datastore_get: {}", e)))?;
+
+    let value = store.get(ns, key).map_err(|e| Error::msg(format!("datastore_get: {}", e)))?;
+
+    match (value.is_empty(), args.get("default")) {
+        (true, Some(d)) => Ok(d.clone()),
+        _ => Ok(Value::String(value)),
+    }
+}
+
+fn from_json(value: &Value, _args: &HashMap<String, Value>) -> Result<Value> {
+    serde_json::from_str(get_filter_str(value, "from_json")?)
+        .map_err(|e| Error::msg(format!("from_json: {}", e)))
+}
+
+fn from_yaml(value: &Value, _args: &HashMap<String, Value>) -> Result<Value> {
+    serde_yaml::from_str(get_filter_str(value, "from_yaml")?)
+        .map_err(|e| Error::msg(format!("from_yaml: {}", e)))
+}
+
+fn to_yaml(value: &Value, _args: &HashMap<String, Value>) -> Result<Value> {
+    let yaml = serde_yaml::to_string(value).map_err(|e| Error::msg(format!("to_yaml: {}", e)))?;
+
+    Ok(Value::String(yaml.trim_start_matches("---\n").to_string()))
+}
+
+fn b64encode(value: &Value, _args: &HashMap<String, Value>) -> Result<Value> {
+    Ok(Value::String(encode_block(get_filter_str(value, "b64encode")?.as_bytes())))
+}
+
+fn b64decode(value: &Value, _args: &HashMap<String, Value>) -> Result<Value> {
+    let bytes = decode_block(get_filter_str(value, "b64decode")?)
+        .map_err(|e| Error::msg(format!("b64decode: {}", e)))?;
+
+    String::from_utf8(bytes)
+        .map(Value::String)
+        .map_err(|e| Error::msg(format!("b64decode: {}", e)))
+}
+
+/// SHA-256 digest in hexadecimal
+fn sha256(value: &Value, _args: &HashMap<String, Value>) -> Result<Value> {
+    Ok(Value::String(to_hex(&openssl::sha::sha256(get_filter_str(value, "sha256")?.as_bytes()))))
+}
+
+/// HMAC-SHA256 with the key `key`, in hexadecimal
+fn hmac_sha256(value: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    let key = get_str(args, "hmac_sha256", "key")?
+        .ok_or_else(|| Error::msg("hmac_sha256: the argument key is required"))?;
+
+    let data = get_filter_str(value, "hmac_sha256")?;
+
+    let sign = || -> std::result::Result<Vec<u8>, openssl::error::ErrorStack> {
+        let pkey = PKey::hmac(key.as_bytes())?;
+        let mut signer = Signer::new(MessageDigest::sha256(), &pkey)?;
+        signer.update(data.as_bytes())?;
+        signer.sign_to_vec()
+    };
+
+    sign().map(|s| Value::String(to_hex(&s)))
+        .map_err(|e| Error::msg(format!("hmac_sha256: {}", e)))
+}
+
+/// Adds `days`, `hours`, `minutes` and `seconds`, which can be negative, to a date in
+/// RFC 3339 or a timestamp. The result is in RFC 3339 with the offset of the date.
+fn date_add(value: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    let date: DateTime<FixedOffset> = match value {
+        Value::String(s) => DateTime::parse_from_rfc3339(s)
+            .map_err(|e| Error::msg(format!("date_add: {:?} is not a RFC 3339 date: {}", s, e)))?,
+        Value::Number(n) => n.as_i64()
+            .and_then(|ts| Utc.timestamp_opt(ts, 0).single())
+            .map(|d| d.with_timezone(&FixedOffset::east(0)))
+            .ok_or_else(|| Error::msg(format!("date_add: {} is not a valid timestamp", n)))?,
+        _ => return Err(Error::msg("date_add: the value must be a RFC 3339 date or a timestamp")),
+    };
+
+    let mut duration = Duration::zero();
+    for (unit, to_duration) in [("days", Duration::days as fn(i64) -> Duration), ("hours", Duration::hours), ("minutes", Duration::minutes), ("seconds", Duration::seconds)] {
+        if let Some(v) = args.get(unit) {
+            let n = v.as_i64().ok_or_else(|| Error::msg(format!("date_add: {} must be an integer", unit)))?;
+            duration = duration + to_duration(n);
+        }
+    }
+
+    Ok(Value::String((date + duration).to_rfc3339()))
+}
+
+fn regex_match(value: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    let s = get_filter_str(value, "regex_match")?;
+
+    with_regex(args, "regex_match", |re| Value::Bool(re.is_match(s)))
+}
+
+/// Replaces all matches of `pattern` with `rep`, which can refer to groups with `$1` or `${name}`
+fn regex_replace(value: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    let s = get_filter_str(value, "regex_replace")?;
+    let rep = get_str(args, "regex_replace", "rep")?.unwrap_or_default();
+
+    with_regex(args, "regex_replace", |re| Value::String(re.replace_all(s, rep).to_string()))
+}
+
+/// Groups of the first match of `pattern`: a map when the groups are named, otherwise an
+/// array whose first item is the whole match. Null if there is no match.
+fn regex_captures(value: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    let s = get_filter_str(value, "regex_captures")?;
+
+    with_regex(args, "regex_captures", |re| {
+        let caps = match re.captures(s) {
+            Some(c) => c,
+            None => return Value::Null,
+        };
+
+        let to_value = |m: Option<regex::Match>| m.map(|m| Value::String(m.as_str().to_string())).unwrap_or(Value::Null);
+
+        if re.capture_names().any(|n| n.is_some()) {
+            return Value::Object(re.capture_names()
+                .flatten()
+                .map(|n| (n.to_string(), to_value(caps.name(n))))
+                .collect());
+        }
+
+        Value::Array(caps.iter().map(to_value).collect())
+    })
+}
+
+// Applies the function to the regex of the argument `pattern`
+fn with_regex<F: FnOnce(&Regex) -> Value>(args: &HashMap<String, Value>, filter: &str, f: F) -> Result<Value> {
+    let pattern = get_str(args, filter, "pattern")?
+        .ok_or_else(|| Error::msg(format!("{}: the argument pattern is required", filter)))?;
+
+    let mut regexes = REGEXES.lock().map_err(|e| Error::msg(e.to_string()))?;
+
+    if !regexes.contains_key(pattern) {
+        let re = Regex::new(pattern).map_err(|e| Error::msg(format!("{}: {}", filter, e)))?;
+        regexes.insert(pattern.to_string(), re);
+    }
+
+    Ok(f(&regexes[pattern]))
+}
+
+fn format_date<T: TimeZone>(date: DateTime<T>, format: Option<&str>) -> String
+where
+    T::Offset: Display,
+{
+    match format {
+        Some(f) => date.format(f).to_string(),
+        None => date.to_rfc3339(),
+    }
+}
+
+fn get_str<'a>(args: &'a HashMap<String, Value>, name: &str, arg: &str) -> Result<Option<&'a str>> {
+    match args.get(arg) {
+        Some(v) => v.as_str()
+            .map(Some)
+            .ok_or_else(|| Error::msg(format!("{}: {} must be a string", name, arg))),
+        None => Ok(None),
+    }
+}
+
+fn get_bool(args: &HashMap<String, Value>, name: &str, arg: &str) -> Result<Option<bool>> {
+    match args.get(arg) {
+        Some(v) => v.as_bool()
+            .map(Some)
+            .ok_or_else(|| Error::msg(format!("{}: {} must be a boolean", name, arg))),
+        None => Ok(None),
+    }
+}
+
+fn get_filter_str<'a>(value: &'a Value, name: &str) -> Result<&'a str> {
+    value.as_str().ok_or_else(|| Error::msg(format!("{}: the value must be a string", name)))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tera::Context;
+
+    use crate::datastore::store::StoreNamespace;
+
+    fn render(tera: &mut Tera, template: &str) -> String {
+        tera.render_str(template, &Context::new()).unwrap()
+    }
+
+    #[test]
+    fn test_register_library() {
+        let mut datastore = StoreConfig::default();
+        datastore.kind = "rocksdb".to_string();
+        datastore.conn_str = "/tmp/flowrunner-test-tera-library".to_string();
+        datastore.namespaces = vec![StoreNamespace { name: "ns1".to_string(), ..Default::default() }];
+        datastore.new_store().unwrap().set("ns1", "version", "1.2.0").unwrap();
+
+        let mut tera = Tera::default();
+        register_library(&mut tera, Some(datastore));
+
+        assert_eq!("1.2.0 default", render(&mut tera, "{{ datastore_get(ns='ns1', key='version') }} {{ datastore_get(ns='ns1', key='unknown', default='default') }}"));
+
+        assert_eq!("2", render(&mut tera, r#"{% set v = '{"a": {"b": 2}}' | from_json %}{{ v.a.b }}"#));
+        assert_eq!("v1", render(&mut tera, r#"{% set v = "k: v1" | from_yaml %}{{ v.k }}"#));
+        assert_eq!("a: 1\nb:\n  - x\n", render(&mut tera, r#"{{ '{"a": 1, "b": ["x"]}' | from_json | to_yaml }}"#));
+
+        assert_eq!("Zmxvd3J1bm5lcg== flowrunner", render(&mut tera, "{{ 'flowrunner' | b64encode }} {{ 'Zmxvd3J1bm5lcg==' | b64decode }}"));
+        assert_eq!("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855", render(&mut tera, "{{ '' | sha256 }}"));
+        assert_eq!("f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8",
+                   render(&mut tera, "{{ 'The quick brown fox jumps over the lazy dog' | hmac_sha256(key='key') }}"));
+
+        assert_eq!("2022-06-01T22:30:00+02:00", render(&mut tera, "{{ '2022-06-02T10:00:00+02:00' | date_add(days=-1, hours=12, minutes=30) }}"));
+        assert_eq!("1970-01-02T00:00:00+00:00", render(&mut tera, "{{ 0 | date_add(days=1) }}"));
+
+        assert_eq!("true v1.2.x 1.2 2", render(&mut tera,
+            r#"{{ 'v1.2.0' | regex_match(pattern='^v\d+') }} {{ 'v1.2.0' | regex_replace(pattern='\d+$', rep='x') }} {% set c = 'v1.2.0' | regex_captures(pattern='v(\d+\.\d+)') %}{{ c.1 }} {% set c = 'v1.2.0' | regex_captures(pattern='\.(?P<minor>\d+)\.') %}{{ c.minor }}"#));
+
+        let _env = crate::utils::lock_env();
+        std::env::set_var("FLOWRUNNER_TEST_TERA_ENV", "value1");
+        assert_eq!("value1 default", render(&mut tera, "{{ env(name='FLOWRUNNER_TEST_TERA_ENV') }} {{ env(name='FLOWRUNNER_TEST_TERA_UNSET', default='default') }}"));
+        assert!(tera.render_str("{{ env(name='FLOWRUNNER_TEST_TERA_UNSET') }}", &Context::new()).is_err());
+
+        let password = render(&mut tera, "{{ random_password(length=24, symbols=false) }}");
+        assert_eq!(24, password.len());
+        assert!(password.chars().all(|c| c.is_ascii_alphanumeric()));
+
+        assert!(render(&mut tera, "{{ now(tz='Asia/Tokyo') }}").ends_with("+09:00"));
+        assert_eq!(Utc::now().format("%Y").to_string(), render(&mut tera, "{{ now(utc=true, format='%Y') }}"));
+        assert!(tera.render_str("{{ now(tz='Mars/Olympus') }}", &Context::new()).is_err());
+
+        let mut tera = Tera::default();
+        register_library(&mut tera, None);
+        assert!(tera.render_str("{{ datastore_get(ns='ns1', key='version') }}", &Context::new()).is_err());
+
+        let mut args = HashMap::new();
+        args.insert("pattern".to_string(), json!("\\d"));
+        assert_eq!(json!(null), regex_captures(&json!("abc"), &args).unwrap());
+    }
+}
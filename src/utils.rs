@@ -1,17 +1,13 @@
+
 use serde_json::value::Value as jsonValue;
 use serde_json::{Map, Number, Value};
 use serde_yaml::Value as yamlValue;
 
 use anyhow::{anyhow, Result};
 
-use log::*;
-
 use envmnt::{ExpandOptions, ExpansionType};
-use tera::{Context, Tera};
-
 use uuid::Uuid;
 
-use crate::tera::generate_uuid as gen_uuid;
 
 pub fn convert_value_yaml_to_json(v: &yamlValue) -> Result<jsonValue> {
     let mut val = jsonValue::Null;
@@ -107,151 +103,69 @@ pub fn expand_env_value(value: &Value) -> Value {
     }
 }
 
-pub fn render_value_template(
-    component: &str,
-    key: &str,
-    value: &Value,
-    data: &Map<String, Value>,
-) -> Result<Value> {
-    debug!(
-        "Rendering value templating: component {}, key {}, value {:?}, data {:?}",
-        component, key, value, data
-    );
-
-    let mut tera = Tera::default();
-    tera.register_function("generate_uuid", gen_uuid);
-
-    let exp_env_v = expand_env_value(value);
-
-    let context = match Context::from_value(Value::Object(data.to_owned())) {
-        Ok(c) => c,
-        Err(e) => return Err(anyhow!(e)),
-    };
-
-    match exp_env_v {
-        Value::Array(arr) => {
-            let mut v: Vec<Value> = vec![];
-            for e in arr.into_iter() {
-                let rendered_v = render_value_template(component, key, &e, data)?;
-                v.push(rendered_v);
-            }
-
-            Ok(Value::Array(v))
-        }
-        Value::Object(map) => {
-            let mut m: Map<String, Value> = Map::new();
-            for (k, v) in map.into_iter() {
-                let rendered_v = render_value_template(component, key, &v, data)?;
-                m.insert(k.to_string(), rendered_v);
-            }
+#[allow(dead_code)]
+pub fn generate_uuid() -> String {
+    Uuid::new_v4().to_hyphenated().to_string()
+}
 
-            Ok(Value::Object(m))
+/// Returns the lines removed from `old` prefixed by `-` and the lines added in `new`
+/// prefixed by `+`, in the order of the files. Unchanged lines are not returned, so the
+/// diff is empty when both contents are equal.
+pub fn diff_lines(old: &str, new: &str) -> String {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+
+    // Length of the longest common subsequence of a[i..] and b[j..]
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
         }
-        Value::Null | Value::Bool(_) | Value::Number(_) => Ok(exp_env_v.to_owned()),
-        Value::String(s) => match tera.render_str(s.as_str(), &context) {
-            Ok(s) => Ok(Value::String(s)),
-            Err(e) => Err(anyhow!(e)),
-        },
     }
-}
-
-pub fn render_text_template(
-    component: &str,
-    text: &mut String,
-    data: &Map<String, Value>,
-) -> Result<()> {
-    debug!(
-        "Rendering text templating: component {}, text {}, data {:?}",
-        component, text, data
-    );
-
-    let mut tera = Tera::default();
-    tera.register_function("generate_uuid", gen_uuid);
-
-    let mut options = ExpandOptions::new();
-    options.expansion_type = Some(ExpansionType::UnixBracketsWithDefaults);
 
-    let context = match Context::from_value(Value::Object(data.to_owned())) {
-        Ok(c) => c,
-        Err(e) => return Err(anyhow!(e)),
-    };
-
-    let text_rendered = envmnt::expand(text, Some(options));
-
-    match tera.render_str(text_rendered.as_str(), &context) {
-        Ok(s) => {
-            text.clear();
-            text.insert_str(0, s.as_str());
+    let mut diff: Vec<String> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            i += 1;
+            j += 1;
+        } else if j == b.len() || (i < a.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            diff.push(format!("-{}", a[i]));
+            i += 1;
+        } else {
+            diff.push(format!("+{}", b[j]));
+            j += 1;
         }
-        Err(e) => return Err(anyhow!(e)),
     }
 
-    Ok(())
+    diff.join("\n")
 }
 
-pub fn render_loop_template(
-    component: &str,
-    value: &Value,
-    data: &Map<String, Value>,
-) -> Result<Value> {
-    debug!(
-        "Rendering loop templating: component {}, value {}, data {:?}",
-        component, value, data
-    );
-
-    let mut tera = Tera::default();
-    tera.register_function("generate_uuid", gen_uuid);
-
-    let exp_env_v = expand_env_value(value);
-
-    let context = match Context::from_value(Value::Object(data.to_owned())) {
-        Ok(c) => c,
-        Err(e) => return Err(anyhow!(e)),
-    };
-
-    match exp_env_v {
-        Value::Array(arr) => {
-            let mut v: Vec<Value> = vec![];
-            for e in arr.into_iter() {
-                if let Some(s) = e.as_str() {
-                    match tera.render_str(s, &context) {
-                        Ok(s1) => v.push(Value::String(s1)),
-                        Err(e) => return Err(anyhow!(e)),
-                    }
-                } else {
-                    v.push(e);
-                }
-            }
-
-            Ok(Value::Array(v))
-        }
-        Value::String(s) => match tera.render_str(s.as_str(), &context) {
-            Ok(s1) => {
-                let val: Value = serde_json::from_str(s1.as_str())?;
-
-                debug!("render_loop_template: s: {:?},  val: {:?}", s1, val);
-
-                if val.as_array().is_none() {
-                    return Err(anyhow!("Value must be an array"));
-                }
-
-                Ok(val)
-            }
-            Err(e) => Err(anyhow!(e)),
-        },
-        _ => Err(anyhow!("Loop must be a array of items or template string")),
-    }
+#[cfg(test)]
+lazy_static::lazy_static! {
+    // Held by the tests which set environment variables, such as the vault password,
+    // so that they do not change them while another one reads them
+    static ref ENV_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
 }
 
-#[allow(dead_code)]
-pub fn generate_uuid() -> String {
-    Uuid::new_v4().to_hyphenated().to_string()
+/// Locks the environment of the tests, even if a test panicked while holding it
+#[cfg(test)]
+pub(crate) fn lock_env() -> std::sync::MutexGuard<'static, ()> {
+    ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use serde_json::json;
+    use std::collections::BTreeMap;
+
+    use crate::template::TemplateCache;
 
     #[test]
     fn test_convert_value_yaml_to_json() {
@@ -365,12 +279,14 @@ mod tests {
         let mut data = input.as_object().unwrap().to_owned();
         expand_env_map(&mut data);
 
+        let cache = TemplateCache::new(&BTreeMap::new(), None);
+
         let mut result =
-            render_loop_template("test", &Value::String("{{ var2 }}".to_string()), &data).unwrap();
+            cache.render_loop("test", &Value::String("{{ var2 }}".to_string()), &data).unwrap();
 
         assert_eq!(json!([1, 2, 3]), result);
 
-        result = render_loop_template(
+        result = cache.render_loop(
             "test",
             &Value::String("{{ var3 | json_encode() | safe }}".to_string()),
             &data,
@@ -379,7 +295,7 @@ mod tests {
 
         assert_eq!(json!(["var31", "var32", "var33"]), result);
 
-        result = render_loop_template(
+        result = cache.render_loop(
             "test",
             &json!(["{{ var1 }}", "{{ var4['var42'] | as_str }}"]),
             &data,
@@ -390,10 +306,18 @@ mod tests {
 
         assert_eq!(
             "Value must be an array",
-            render_loop_template("test", &json!("{{ var4 | json_encode() | safe }}"), &data)
+            cache.render_loop("test", &json!("{{ var4 | json_encode() | safe }}"), &data)
                 .unwrap_err()
                 .to_string()
                 .as_str()
         );
     }
+
+    #[test]
+    fn test_diff_lines() {
+        assert_eq!("", diff_lines("a\nb\n", "a\nb\n"));
+        assert_eq!("-b\n+c", diff_lines("a\nb\nd", "a\nc\nd"));
+        assert_eq!("+a\n-d", diff_lines("b\nc\nd", "a\nb\nc"));
+        assert_eq!("+a\n+b", diff_lines("", "a\nb"));
+    }
 }
@@ -0,0 +1,495 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+
+use serde_json::{Map, Value};
+
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::Marker;
+
+use jsonschema::JSONSchema;
+
+use crate::definition::Loader;
+use crate::flow;
+use crate::job::Task;
+use crate::plugin::PluginRegistry;
+use crate::template::is_template;
+
+/// Error found while validating a flow file.
+///
+/// `path` is a JSON pointer to the faulty element in the flow, for example
+/// `/jobs/0/tasks/1/builtin-shell/params/cmd`. `line` and `column` start at 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationError {
+    pub path: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.line, self.column) {
+            (Some(l), Some(c)) => write!(f, "{}:{}: {}", l, c, self.message),
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Validates a flow definition without running it and returns all errors found.
+///
+/// The flow is parsed with the same parser as `exec`, then the params of all tasks,
+/// sources and sinks are checked against the JSON schema of their plugin. Templated
+/// values are skipped because they are only known at runtime. Task targets
+/// (`on_success`, `on_failure`), job dependencies (`depends_on`) and routes (`from`)
+/// must exist.
+///
+/// Included files and imported tasks are resolved relative to `base_dir`. Errors in
+/// flows using them are not located, as elements may come from other files.
+pub fn validate_flow_str(content: &str, base_dir: &Path) -> Vec<ValidationError> {
+    let mut locator = match YamlLocator::new(content) {
+        Ok(l) => l,
+        Err(e) => {
+            let marker = e.marker();
+
+            return vec![ValidationError {
+                path: String::new(),
+                line: Some(marker.line()),
+                column: Some(marker.col() + 1),
+                message: e.to_string(),
+            }];
+        },
+    };
+
+    let mut errors: Vec<ValidationError> = Vec::new();
+
+    let mut loader = Loader::new(base_dir, None);
+
+    let flow = match flow::parse_unchecked(content, &mut loader) {
+        Ok(f) => f,
+        Err(e) => {
+            // Deserialization errors give the position of the faulty element
+            let location = e.downcast_ref::<serde_yaml::Error>().and_then(|e| e.location());
+
+            errors.push(ValidationError {
+                path: String::new(),
+                line: location.as_ref().map(|l| l.line()),
+                column: location.as_ref().map(|l| l.column()),
+                message: format!("Invalid flow: {}", e),
+            });
+            return errors;
+        },
+    };
+
+    if loader.is_composed() {
+        locator = YamlLocator::default();
+    }
+
+    // Sources
+    for (i, s) in flow.sources.iter().enumerate() {
+        let path = format!("/sources/{}", i);
+        validate_params(&locator, &mut errors, &s.plugin, &s.params, &(path.clone() + "/plugin"), &(path + "/params"));
+    }
+
+    // Jobs
+    let job_names: HashSet<&str> = flow.jobs.iter().map(|j| j.name.as_str()).collect();
+
+    for (i, j) in flow.jobs.iter().enumerate() {
+        let job_path = format!("/jobs/{}", i);
+
+        for (k, d) in j.depends_on.iter().enumerate() {
+            if !job_names.contains(d.as_str()) {
+                errors.push(locator.error(&format!("{}/depends_on/{}", job_path, k),
+                                          format!("Job {} depends on the unknown job {}", j.name, d)));
+            }
+        }
+
+        validate_tasks(&locator, &mut errors, &j.name, &j.tasks, &(job_path + "/tasks"));
+    }
+
+    // Cycles are only meaningful when all dependencies exist
+    if !errors.iter().any(|e| e.path.contains("/depends_on/")) {
+        if let Err(e) = flow::check_job_cycles(&flow.jobs) {
+            errors.push(locator.error("/jobs", e.to_string()));
+        }
+    }
+
+    // Sinks
+    for (i, s) in flow.sinks.iter().enumerate() {
+        let path = format!("/sinks/{}", i);
+        validate_params(&locator, &mut errors, &s.plugin, &s.params, &(path.clone() + "/plugin"), &(path + "/params"));
+    }
+
+    // Routes between sources, jobs and sinks
+    for (path, message) in flow::check_routes(&flow) {
+        errors.push(locator.error(&path, message));
+    }
+
+    // Conditions of jobs, tasks and sinks
+    for (path, message) in flow::check_conditions(&flow) {
+        errors.push(locator.error(&path, message));
+    }
+
+    errors.sort_by_key(|e| (e.line.unwrap_or_default(), e.column.unwrap_or_default()));
+
+    errors
+}
+
+// Checks the tasks of a list and of its groups. Targets of on_success and on_failure
+// must be in the same list.
+fn validate_tasks(locator: &YamlLocator, errors: &mut Vec<ValidationError>, job_name: &str, tasks: &[Task], path: &str) {
+    let task_names: HashSet<&str> = tasks.iter().map(|t| t.name.as_str()).collect();
+
+    for (k, t) in tasks.iter().enumerate() {
+        let task_path = format!("{}/{}", path, k);
+
+        for (field, target) in [("on_success", &t.on_success), ("on_failure", &t.on_failure)] {
+            if !target.is_empty() && !task_names.contains(target.as_str()) {
+                errors.push(locator.error(&format!("{}/{}", task_path, field),
+                                          format!("Task {} of job {} has an unknown {} task: {}", t.name, job_name, field, target)));
+            }
+        }
+
+        if t.is_group() {
+            for (section, group_tasks) in [("block", &t.block), ("rescue", &t.rescue), ("always", &t.always)] {
+                validate_tasks(locator, errors, job_name, group_tasks, &format!("{}/{}", task_path, section));
+            }
+
+            continue;
+        }
+
+        let plugin_path = format!("{}/{}", task_path, t.plugin);
+        validate_params(locator, errors, &t.plugin, &t.params, &plugin_path, &(plugin_path.clone() + "/params"));
+    }
+}
+
+// Checks params against the schema of the plugin. Errors on templated values are ignored.
+fn validate_params(
+    locator: &YamlLocator,
+    errors: &mut Vec<ValidationError>,
+    plugin_name: &str,
+    params: &Map<String, Value>,
+    plugin_path: &str,
+    params_path: &str,
+) {
+    let plugin = match PluginRegistry::get_plugin(plugin_name) {
+        Some(p) => p,
+        None => {
+            errors.push(locator.error(plugin_path, format!("Plugin {} is not found", plugin_name)));
+            return;
+        },
+    };
+
+    let schema = plugin.param_schema();
+    let compiled = match JSONSchema::compile(&schema) {
+        Ok(s) => s,
+        Err(e) => {
+            errors.push(locator.error(plugin_path, format!("Plugin {} has an invalid param schema: {}", plugin_name, e)));
+            return;
+        },
+    };
+
+    let instance = Value::Object(params.clone());
+
+    let mut templated: Vec<String> = Vec::new();
+    get_templated_paths(&instance, "", &mut templated);
+
+    if let Err(errs) = compiled.validate(&instance) {
+        for e in errs {
+            let instance_path = e.instance_path.to_string();
+
+            if templated.iter().any(|t| instance_path == *t || instance_path.starts_with(&(t.to_owned() + "/"))) {
+                continue;
+            }
+
+            errors.push(locator.error(&(params_path.to_owned() + &instance_path),
+                                      format!("{}: params{}: {}", plugin_name, instance_path, e)));
+        }
+    }
+}
+
+// Collects the JSON pointers of all string values containing a template expression
+fn get_templated_paths(value: &Value, path: &str, paths: &mut Vec<String>) {
+    match value {
+        Value::String(s) => {
+            if is_template(s) {
+                paths.push(path.to_string());
+            }
+        },
+        Value::Array(a) => {
+            for (i, v) in a.iter().enumerate() {
+                get_templated_paths(v, &format!("{}/{}", path, i), paths);
+            }
+        },
+        Value::Object(o) => {
+            for (k, v) in o.iter() {
+                get_templated_paths(v, &format!("{}/{}", path, k), paths);
+            }
+        },
+        _ => (),
+    }
+}
+
+enum Frame {
+    Seq { path: String, index: usize },
+    // key is none while waiting for the next key
+    Map { path: String, key: Option<String> },
+}
+
+/// Positions of YAML nodes indexed by JSON pointer.
+///
+/// Map entries are located at their key. Unknown paths fall back to their nearest parent.
+#[derive(Default, Debug)]
+struct YamlLocator {
+    positions: HashMap<String, (usize, usize)>,
+}
+
+#[derive(Default)]
+struct LocatorBuilder {
+    stack: Vec<Frame>,
+    positions: HashMap<String, (usize, usize)>,
+}
+
+impl YamlLocator {
+    fn new(content: &str) -> Result<Self, yaml_rust::ScanError> {
+        let mut builder = LocatorBuilder::default();
+        let mut parser = Parser::new(content.chars());
+
+        parser.load(&mut builder, false)?;
+
+        Ok(YamlLocator { positions: builder.positions })
+    }
+
+    fn locate(&self, path: &str) -> Option<(usize, usize)> {
+        let mut p = path;
+
+        loop {
+            if let Some(pos) = self.positions.get(p) {
+                return Some(*pos);
+            }
+
+            match p.rfind('/') {
+                Some(i) => p = &p[..i],
+                None => return None,
+            }
+        }
+    }
+
+    fn error(&self, path: &str, message: String) -> ValidationError {
+        let pos = self.locate(path);
+
+        ValidationError {
+            path: path.to_string(),
+            line: pos.map(|p| p.0),
+            column: pos.map(|p| p.1),
+            message,
+        }
+    }
+}
+
+impl LocatorBuilder {
+    fn record(&mut self, path: String, mark: Marker) {
+        self.positions.entry(path).or_insert((mark.line(), mark.col() + 1));
+    }
+
+    // Returns the path of the node starting now as a value
+    fn value_path(&mut self) -> String {
+        match self.stack.last_mut() {
+            Some(Frame::Seq { path, index }) => format!("{}/{}", path, index),
+            Some(Frame::Map { path, key }) => {
+                // Complex keys are not supported and are named "?"
+                let k = key.get_or_insert_with(|| "?".to_string());
+                format!("{}/{}", path, k)
+            },
+            None => String::new(),
+        }
+    }
+
+    fn value_done(&mut self) {
+        match self.stack.last_mut() {
+            Some(Frame::Seq { index, .. }) => *index += 1,
+            Some(Frame::Map { key, .. }) => *key = None,
+            None => (),
+        }
+    }
+}
+
+impl MarkedEventReceiver for LocatorBuilder {
+    fn on_event(&mut self, ev: Event, mark: Marker) {
+        match ev {
+            Event::Scalar(v, ..) => {
+                if let Some(Frame::Map { path, key: key @ None }) = self.stack.last_mut() {
+                    let p = format!("{}/{}", path, v);
+                    *key = Some(v);
+                    self.record(p, mark);
+                    return;
+                }
+
+                let p = self.value_path();
+                self.record(p, mark);
+                self.value_done();
+            },
+            Event::Alias(_) => {
+                let p = self.value_path();
+                self.record(p, mark);
+                self.value_done();
+            },
+            Event::SequenceStart(_) => {
+                let p = self.value_path();
+                self.record(p.clone(), mark);
+                self.stack.push(Frame::Seq { path: p, index: 0 });
+            },
+            Event::MappingStart(_) => {
+                let p = self.value_path();
+                self.record(p.clone(), mark);
+                self.stack.push(Frame::Map { path: p, key: None });
+            },
+            Event::SequenceEnd | Event::MappingEnd => {
+                self.stack.pop();
+                self.value_done();
+            },
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_yaml_locator() {
+        let content = r#"
+name: flow1
+jobs:
+  - name: job1
+    tasks:
+      - builtin-shell:
+          params:
+            cmd: ls
+"#;
+        let locator = YamlLocator::new(content).unwrap();
+
+        assert_eq!(Some((2, 1)), locator.locate("/name"));
+        assert_eq!(Some((4, 5)), locator.locate("/jobs/0"));
+        assert_eq!(Some((6, 9)), locator.locate("/jobs/0/tasks/0/builtin-shell"));
+        assert_eq!(Some((8, 13)), locator.locate("/jobs/0/tasks/0/builtin-shell/params/cmd"));
+        assert_eq!(Some((8, 13)), locator.locate("/jobs/0/tasks/0/builtin-shell/params/cmd/unknown"));
+    }
+
+    #[test]
+    fn test_get_templated_paths() {
+        let params = serde_json::json!({
+            "a": "{{ context.a }}",
+            "b": ["ls", "{% if context.b %}1{% endif %}"],
+            "c": "{# rendered #}5",
+            "d": "ls",
+        });
+
+        let mut paths: Vec<String> = Vec::new();
+        get_templated_paths(&params, "", &mut paths);
+        paths.sort();
+
+        assert_eq!(vec!["/a", "/b/1", "/c"], paths);
+    }
+
+    #[tokio::test]
+    async fn test_validate_flow_str() {
+        PluginRegistry::load_plugins("target/debug").await;
+
+        let content = r#"
+name: flow1
+jobs:
+  - name: job1
+    depends_on:
+      - job3
+    tasks:
+      - name: task1
+        builtin-shell:
+          params:
+            command: ls
+        on_failure: task3
+      - name: task2
+        builtin-shell:
+          params:
+            cmd: "{{ context.cmd }}"
+  - name: job2
+    tasks:
+      - builtin-unknown:
+          params: {}
+"#;
+
+        let errors: Vec<(Option<usize>, String)> = validate_flow_str(content, Path::new(".")).into_iter()
+            .map(|e| (e.line, e.path))
+            .collect();
+
+        assert_eq!(vec![
+            (Some(6), "/jobs/0/depends_on/0".to_string()),
+            (Some(10), "/jobs/0/tasks/0/builtin-shell/params".to_string()),
+            (Some(10), "/jobs/0/tasks/0/builtin-shell/params".to_string()),
+            (Some(12), "/jobs/0/tasks/0/on_failure".to_string()),
+            (Some(19), "/jobs/1/tasks/0/builtin-unknown".to_string()),
+        ], errors);
+
+        let content = r#"
+name: flow1
+kind: stream
+sources:
+  - name: src-a
+    plugin: builtin-shell
+  - name: src-b
+    plugin: builtin-shell
+jobs:
+  - name: job1
+    from: [src-a, src-c]
+    tasks:
+      - builtin-shell:
+          params:
+            cmd: ls
+sinks:
+  - plugin: builtin-shell
+    from:
+      - job9
+"#;
+
+        let errors: Vec<(Option<usize>, String)> = validate_flow_str(content, Path::new(".")).into_iter()
+            .filter(|e| e.path.contains("/from") || e.path == "/sources/1" || e.path == "/jobs/0")
+            .map(|e| (e.line, e.path))
+            .collect();
+
+        assert_eq!(vec![
+            (Some(7), "/sources/1".to_string()),
+            (Some(10), "/jobs/0".to_string()),
+            (Some(11), "/jobs/0/from/1".to_string()),
+            (Some(19), "/sinks/0/from/0".to_string()),
+        ], errors);
+
+        let content = r#"
+name: flow1
+jobs:
+  - name: job1
+    if: "{{ context.x }}"
+    tasks:
+      - name: group1
+        block:
+          - name: task1
+            until: "result.task1.status =="
+            builtin-shell:
+              params:
+                cmd: ls
+"#;
+
+        let errors: Vec<(Option<usize>, String)> = validate_flow_str(content, Path::new(".")).into_iter()
+            .map(|e| (e.line, e.path))
+            .collect();
+
+        assert_eq!(vec![
+            (Some(5), "/jobs/0/if".to_string()),
+            (Some(10), "/jobs/0/tasks/0/block/0/until".to_string()),
+        ], errors);
+
+        let errors = validate_flow_str("name: flow1\njobs: [\n", Path::new("."));
+        assert_eq!(1, errors.len());
+        assert!(errors[0].line.is_some());
+    }
+}
@@ -0,0 +1,123 @@
+use std::path::Path;
+use std::process::Command;
+
+use clap::ArgMatches;
+
+use anyhow::{anyhow, Result};
+use serde_json::{Map, Value};
+
+use crate::utils::generate_uuid;
+use crate::vault;
+
+pub fn vars_cmd(matches: &ArgMatches<'_>) -> Result<()> {
+    match matches.subcommand() {
+        ("encrypt", Some(encrypt_matches)) => {
+            let file = Path::new(encrypt_matches.value_of("file").unwrap_or_default());
+            let content = read_file(file)?;
+
+            if vault::is_encrypted(&content) {
+                return Err(anyhow!("{} is already encrypted", file.display()));
+            }
+
+            check_variables(content.as_bytes())?;
+            write_file(file, &vault::encrypt(content.as_bytes(), &vault::password()?)?)?;
+        },
+        ("decrypt", Some(decrypt_matches)) => {
+            let file = Path::new(decrypt_matches.value_of("file").unwrap_or_default());
+            let plain = decrypt_file(file)?;
+
+            if decrypt_matches.is_present("stdout") {
+                print!("{}", String::from_utf8_lossy(&plain));
+                return Ok(());
+            }
+
+            write_file(file, &String::from_utf8_lossy(&plain))?;
+        },
+        ("edit", Some(edit_matches)) => {
+            let file = Path::new(edit_matches.value_of("file").unwrap_or_default());
+            edit_file(file)?;
+        },
+        _ => return Err(anyhow!("You must specify a vars subcommand: encrypt, decrypt or edit")),
+    }
+
+    Ok(())
+}
+
+// Decrypts the file in a temporary file only readable by the user, opens it with the
+// editor given by VISUAL or EDITOR, then encrypts the result back in the file. The file
+// is left untouched if the edited variables are not valid.
+fn edit_file(file: &Path) -> Result<()> {
+    let password = vault::password()?;
+    let plain = decrypt_file(file)?;
+
+    let tmp = std::env::temp_dir().join(format!("flowrunner-vars-{}.yaml", generate_uuid()));
+    write_private_file(&tmp, &plain)?;
+
+    let res = (|| -> Result<()> {
+        let editor = std::env::var("VISUAL")
+            .or_else(|_| std::env::var("EDITOR"))
+            .unwrap_or_else(|_| "vi".to_string());
+
+        let status = Command::new(&editor).arg(&tmp).status()
+            .map_err(|e| anyhow!("Cannot launch the editor {}: {}", editor, e))?;
+        if !status.success() {
+            return Err(anyhow!("Editor {} failed: {}", editor, status));
+        }
+
+        let edited = std::fs::read(&tmp)?;
+        if edited == plain {
+            return Ok(());
+        }
+
+        check_variables(&edited)?;
+        write_file(file, &vault::encrypt(&edited, &password)?)
+    })();
+
+    let _ = std::fs::remove_file(&tmp);
+
+    res
+}
+
+fn decrypt_file(file: &Path) -> Result<Vec<u8>> {
+    let content = read_file(file)?;
+
+    if !vault::is_encrypted(&content) {
+        return Err(anyhow!("{} is not encrypted", file.display()));
+    }
+
+    vault::decrypt(&content, &vault::password()?).map_err(|e| anyhow!("{}: {}", file.display(), e))
+}
+
+// Variable files must contain a map of variables
+fn check_variables(content: &[u8]) -> Result<()> {
+    serde_yaml::from_slice::<Map<String, Value>>(content)
+        .map(|_| ())
+        .map_err(|e| anyhow!("Variables are not valid: {}", e))
+}
+
+fn read_file(file: &Path) -> Result<String> {
+    std::fs::read_to_string(file).map_err(|e| anyhow!("Cannot read the file {}: {}", file.display(), e))
+}
+
+fn write_file(file: &Path, content: &str) -> Result<()> {
+    std::fs::write(file, content).map_err(|e| anyhow!("Cannot write the file {}: {}", file.display(), e))
+}
+
+#[cfg(unix)]
+fn write_private_file(file: &Path, content: &[u8]) -> Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(file)
+        .and_then(|mut f| f.write_all(content))
+        .map_err(|e| anyhow!("Cannot write the file {}: {}", file.display(), e))
+}
+
+#[cfg(not(unix))]
+fn write_private_file(file: &Path, content: &[u8]) -> Result<()> {
+    std::fs::write(file, content).map_err(|e| anyhow!("Cannot write the file {}: {}", file.display(), e))
+}
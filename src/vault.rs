@@ -0,0 +1,112 @@
+use anyhow::{anyhow, Result};
+
+use openssl::base64::{decode_block, encode_block};
+use openssl::hash::MessageDigest;
+use openssl::pkcs5::pbkdf2_hmac;
+use openssl::rand::rand_bytes;
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+
+/// Environment variable giving the password of encrypted files
+pub const PASSWORD_ENV: &str = "FLOWRUNNER_VAULT_PASSWORD";
+/// Environment variable giving the path of a file containing the password
+pub const PASSWORD_FILE_ENV: &str = "FLOWRUNNER_VAULT_PASSWORD_FILE";
+
+// First line of encrypted files. It is also authenticated with the content.
+const HEADER: &str = "$FLOWRUNNER_VAULT;1;AES256-GCM";
+const SALT_LEN: usize = 16;
+const IV_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const ITERATIONS: usize = 100_000;
+
+/// Returns the password of encrypted files given by the environment, either directly
+/// or by a password file whose trailing newline is ignored
+pub fn password() -> Result<String> {
+    if let Ok(p) = std::env::var(PASSWORD_ENV) {
+        return Ok(p);
+    }
+
+    match std::env::var(PASSWORD_FILE_ENV) {
+        Ok(f) => std::fs::read_to_string(&f)
+            .map(|p| p.trim_end_matches(&['\r', '\n'][..]).to_string())
+            .map_err(|e| anyhow!("Cannot read the password file {}: {}", f, e)),
+        Err(_) => Err(anyhow!("The password of encrypted files must be given by the environment variable {} or {}", PASSWORD_ENV, PASSWORD_FILE_ENV)),
+    }
+}
+
+/// Returns true if the content was encrypted by `encrypt`
+pub fn is_encrypted(content: &str) -> bool {
+    content.starts_with(HEADER)
+}
+
+/// Encrypts the content with AES-256-GCM and a key derived from the password. The result
+/// is a text: the header followed by the salt, IV, tag and encrypted content in base64.
+pub fn encrypt(plain: &[u8], password: &str) -> Result<String> {
+    let mut salt = [0u8; SALT_LEN];
+    let mut iv = [0u8; IV_LEN];
+    let mut tag = [0u8; TAG_LEN];
+
+    rand_bytes(&mut salt)?;
+    rand_bytes(&mut iv)?;
+
+    let key = derive_key(password, &salt)?;
+    let encrypted = encrypt_aead(Cipher::aes_256_gcm(), &key, Some(&iv), HEADER.as_bytes(), plain, &mut tag)?;
+
+    let data = encode_block(&[&salt[..], &iv[..], &tag[..], &encrypted[..]].concat());
+    let lines: Vec<String> = data.as_bytes()
+        .chunks(76)
+        .map(|c| String::from_utf8_lossy(c).to_string())
+        .collect();
+
+    Ok(format!("{}\n{}\n", HEADER, lines.join("\n")))
+}
+
+/// Decrypts a content encrypted by `encrypt`
+pub fn decrypt(content: &str, password: &str) -> Result<Vec<u8>> {
+    let mut lines = content.lines();
+
+    if lines.next() != Some(HEADER) {
+        return Err(anyhow!("Content is not encrypted or its format is not supported"));
+    }
+
+    let data = decode_block(&lines.map(|l| l.trim()).collect::<String>())
+        .map_err(|e| anyhow!("Encrypted content is not valid: {}", e))?;
+
+    if data.len() < SALT_LEN + IV_LEN + TAG_LEN {
+        return Err(anyhow!("Encrypted content is truncated"));
+    }
+
+    let (salt, data) = data.split_at(SALT_LEN);
+    let (iv, data) = data.split_at(IV_LEN);
+    let (tag, encrypted) = data.split_at(TAG_LEN);
+
+    let key = derive_key(password, salt)?;
+
+    decrypt_aead(Cipher::aes_256_gcm(), &key, Some(iv), HEADER.as_bytes(), encrypted, tag)
+        .map_err(|_| anyhow!("Cannot decrypt the content: the password is wrong or the content is corrupted"))
+}
+
+fn derive_key(password: &str, salt: &[u8]) -> Result<[u8; 32]> {
+    let mut key = [0u8; 32];
+    pbkdf2_hmac(password.as_bytes(), salt, ITERATIONS, MessageDigest::sha256(), &mut key)?;
+
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let encrypted = encrypt(b"password: secret1\n", "pass1").unwrap();
+
+        assert!(is_encrypted(&encrypted));
+        assert!(!encrypted.contains("secret1"));
+        assert_eq!(b"password: secret1\n".to_vec(), decrypt(&encrypted, "pass1").unwrap());
+
+        assert_eq!("Cannot decrypt the content: the password is wrong or the content is corrupted",
+                   decrypt(&encrypted, "pass2").unwrap_err().to_string());
+        assert_eq!("Content is not encrypted or its format is not supported",
+                   decrypt("password: secret1", "pass1").unwrap_err().to_string());
+    }
+}
@@ -41,14 +41,14 @@ sources:
 
 jobs:
 - hosts: localhost
-  if: "{{ context.sender == 'webserver' and context.source == '/routes/:route' }}"
+  if: "context.sender == 'webserver' and context.source == '/routes/:route'"
   tasks:
   - builtin-shell:
       params:
         cmd: "echo '{\"message\": \"unknown\"}'"
 
 - hosts: localhost
-  if: "{{ context.sender == 'webserver' and context.source == '/routes' }}"
+  if: "context.sender == 'webserver' and context.source == '/routes'"
   tasks:
   - builtin-shell:
       params: